@@ -0,0 +1,189 @@
+use embedded_hal::delay::DelayNs;
+
+use crate::navigation::{Channel, Device};
+use crate::pmbus::{to_u16, to_value, Command, Page, PmbusDevice, VrmError};
+use crate::protocol::{Format, Register, Request, RequestError, Response, MAX_READ, NVM_CONFIRM};
+use crate::vrm_controller::TPSC536C7;
+use pmbus_types_rs::slinear11;
+
+/// Reads the telemetry of both channels into dev, flagging a channel whose read failed
+pub fn update_vrm_read<D: PmbusDevice>(dev: &mut Device, controller: &mut D) {
+    // Get Values for Display
+    let core = update_channel_read(dev.core(), controller, Page::ChannelA);
+    dev.core().set_error(core.is_err());
+    let mem = update_channel_read(dev.mem(), controller, Page::ChannelB);
+    dev.mem().set_error(mem.is_err());
+}
+
+/// Reads the per phase current and temperature of both channels into dev
+pub fn update_phase_read<I: embedded_hal::i2c::I2c>(
+    dev: &mut Device,
+    controller: &mut TPSC536C7<I>,
+) {
+    for page in [Page::ChannelA, Page::ChannelB] {
+        let chan = match page {
+            Page::ChannelA => dev.core(),
+            _ => dev.mem(),
+        };
+        match controller.read_phase_telemetry(page, chan.phases_mut()) {
+            Ok(count) => chan.set_phase_count(count),
+            Err(err) => {
+                error!("Phase Read Failed {}: {}", page, err);
+                chan.set_phase_count(0);
+            }
+        }
+    }
+}
+
+// Reads every value of a single channel, stopping at the first failed transaction
+fn update_channel_read<D: PmbusDevice>(
+    chan: &mut Channel,
+    controller: &mut D,
+    page: Page,
+) -> Result<(), VrmError> {
+    controller.select_page(page)?;
+    // Status
+    chan.set_status(controller.status_word()?);
+    // Voltage, Temperature, Current
+    let telemetry = controller.telemetry()?;
+    chan.set_voltage(telemetry.vout);
+    chan.set_temperature(telemetry.temperature);
+    chan.set_current(telemetry.iout);
+    // Voltage Setpoint
+    chan.set_voltage_setpoint(controller.read_vout_class(Command::VOUTCommand.to_address())?);
+    // Current Limit
+    chan.set_current_limit(controller.read_linear11(Command::IoutOCFaultLimit.to_address())?);
+    Ok(())
+}
+
+/// Writes a value accepted on the front panel to the controller, position is (channel, row)
+pub fn write_setpoint<I: embedded_hal::i2c::I2c>(
+    controller: &mut TPSC536C7<I>,
+    position: (i32, i32),
+    val: f32,
+) -> Result<(), VrmError> {
+    let c = controller.page(Page::from_channel(position.0 as usize))?;
+    match position {
+        (_, 0) => c.vout_command().write(val),
+        (_, 1) => c.iout_oc_fault_limit().write(val),
+        (_, _) => Ok(()), // Default condition that sound never match
+    }
+}
+
+/// True for the commands that store or restore the controller NVM
+pub fn is_nvm_command(cmd: u8) -> bool {
+    cmd == Command::StoreDefaultAll.to_address()
+        || cmd == Command::RestoreDefaultAll.to_address()
+        || cmd == Command::StoreUserAll.to_address()
+        || cmd == Command::RestoreUserAll.to_address()
+}
+
+/// True for the commands that switch the rails on and off, see sequence::Sequencer
+pub fn is_sequence_command(cmd: u8) -> bool {
+    cmd == Command::Operation.to_address() || cmd == Command::OnOffConfig.to_address()
+}
+
+/// Runs a store / restore of the controller NVM
+pub fn nvm_action<I: embedded_hal::i2c::I2c, D: DelayNs>(
+    controller: &mut TPSC536C7<I>,
+    delay: &mut D,
+    cmd: u8,
+) -> Result<(), VrmError> {
+    if cmd == Command::StoreDefaultAll.to_address() {
+        controller.store_default_all(delay)
+    } else if cmd == Command::StoreUserAll.to_address() {
+        controller.store_user_all(delay)
+    } else if cmd == Command::RestoreDefaultAll.to_address() {
+        controller.restore_default_all(delay)
+    } else {
+        Err(VrmError::UnsupportedCommand)
+    }
+}
+
+/// Carries out a request received from the host, returning the reply to send back
+pub fn handle_request<I: embedded_hal::i2c::I2c, D: DelayNs>(
+    controller: &mut TPSC536C7<I>,
+    delay: &mut D,
+    request: &Request,
+) -> Response {
+    let result = match *request {
+        Request::Ping => Ok(Response::Pong),
+        Request::Nvm { confirm, .. } if confirm != NVM_CONFIRM => Err(RequestError::NotConfirmed),
+        Request::Nvm { cmd, .. } => nvm_action(controller, delay, cmd)
+            .map(|()| Response::Ack)
+            .map_err(RequestError::Vrm),
+        // NVM commands have to go through the guarded path above
+        Request::Write { data, .. } if data.first().is_some_and(|&cmd| is_nvm_command(cmd)) => {
+            Err(RequestError::Refused)
+        }
+        // The rails are switched by the power sequencer alone, so they keep their order
+        Request::Write { data, .. }
+            if data.first().is_some_and(|&cmd| is_sequence_command(cmd)) =>
+        {
+            Err(RequestError::Refused)
+        }
+        Request::Write { page, data } => controller
+            .page(Page::from_channel(page as usize))
+            .and_then(|c| c.command(data))
+            .map(|()| Response::Ack)
+            .map_err(RequestError::Vrm),
+        Request::Read {
+            page,
+            cmd,
+            len,
+            format,
+        } => read_register(
+            controller,
+            Page::from_channel(page as usize),
+            cmd,
+            len as usize,
+            format,
+        )
+        .map(Response::Register),
+        // Streaming is scheduled by the main loop, which gives these to stream::Streamer first
+        Request::StartStream(_) | Request::StopStream => Err(RequestError::Refused),
+        // Profiles are kept by the main loop, which gives these to profile::Profiles first
+        Request::ListProfiles
+        | Request::GetProfile(_)
+        | Request::LoadProfile(_)
+        | Request::SaveProfile { .. }
+        | Request::DeleteProfile(_) => Err(RequestError::Refused),
+        // As is the ramp, which takes the rate and VOUT_COMMAND writes (see ramp::Ramp::handle)
+        Request::SetRampRate { .. } | Request::GetRampAbort => Err(RequestError::Refused),
+        // And the power sequence (see sequence::Sequencer::handle)
+        Request::SetOutput(_) | Request::GetOutput => Err(RequestError::Refused),
+        // And margining (see margin::Margin::handle)
+        Request::SetMargin { .. }
+        | Request::SetMarginPercent { .. }
+        | Request::StartMarginSweep(_)
+        | Request::StopMarginSweep
+        | Request::GetMargin
+        | Request::GetSweepStep(_) => Err(RequestError::Refused),
+    };
+    result.unwrap_or_else(Response::Error)
+}
+
+// Reads len bytes of cmd from page, decoding them as format
+fn read_register<I: embedded_hal::i2c::I2c>(
+    controller: &mut TPSC536C7<I>,
+    page: Page,
+    cmd: u8,
+    len: usize,
+    format: Format,
+) -> Result<Register, RequestError> {
+    let word = matches!(format, Format::Vout | Format::Linear11);
+    if len == 0 || len > MAX_READ || (word && len != 2) {
+        return Err(RequestError::InvalidLength);
+    }
+    let mut buf = [0u8; MAX_READ];
+    let c = controller.page(page).map_err(RequestError::Vrm)?;
+    c.read(cmd, &mut buf[..len]).map_err(RequestError::Vrm)?;
+    let raw = to_u16([buf[0], buf[1]]);
+    let value = match format {
+        Format::Raw => None,
+        Format::Vout => Some(c.vout_mode().and_then(|mode| mode.to_volts(raw))),
+        Format::Linear11 => Some(to_value(slinear11::to(raw))),
+    };
+    let value = value.transpose().map_err(RequestError::Vrm)?;
+    Ok(Register::new(&buf[..len], value))
+}
@@ -0,0 +1,727 @@
+// The TPS536C7 driver, written once and expanded by vrm_controller over embedded_hal::i2c::I2c
+// and by vrm_controller_async over embedded_hal_async::i2c::I2c
+//
+// The register table, the envelope check, the VOUT_MODE cache and the NVM sequence only exist
+// here, so a safety change reaches both drivers. Each expansion is given its I2C and delay traits
+// and either no tokens or `async` / `.await`, and provides the bus transactions itself (bus_write,
+// bus_read and bus_block). The pmbus, safety and vrm_status items named below are resolved where
+// the driver is expanded.
+
+/// Decodes a raw register value in the given format (vout uses the cached VOUT_MODE)
+macro_rules! decode {
+    ({$($await:tt)*} $self:ident, vout, $raw:expr) => {
+        ($self.vout_mode()$($await)*)?.to_volts($raw)
+    };
+    ({$($await:tt)*} $self:ident, $format:ident, $raw:expr) => {
+        to_value($format::to($raw))
+    };
+}
+
+/// Encodes a value to a raw register value in the given format (vout uses the cached VOUT_MODE)
+macro_rules! encode {
+    ({$($await:tt)*} $self:ident, vout, $val:expr) => {
+        ($self.vout_mode()$($await)*)?.from_volts($val)?
+    };
+    ({$($await:tt)*} $self:ident, $format:ident, $val:expr) => {
+        $format::from($val)
+    };
+}
+
+/// An abstracted way to generate i2c PMBUS Read Commands.
+///
+/// Await, Self, Name, Command (u8), format (slinear11 or vout)
+macro_rules! send_read {
+    ({$($await:tt)*} $self:ident, $name:literal, $cmd:expr, $format:ident) => {{
+        let mut buf = [b'\0'; 2];
+        match $self.bus_read($cmd, &mut buf)$($await)* {
+            Ok(_val) => {
+                trace!("{}_Read: {:#X}, {:#X}", $name, $cmd, buf);
+                decode!({$($await)*} $self, $format, to_u16(buf))
+            }
+            Err(val) => {
+                error!("{}_Read_Error: {:#X}, {}", $name, $cmd, val);
+                Err(val)
+            }
+        }
+    }};
+}
+
+/// An abstracted way to generate i2c PMBUS Write Commands.
+///
+/// Await, Self, Name, Command (u8), format (slinear11 or vout), data (float)
+macro_rules! send_write {
+    ({$($await:tt)*} $self:ident, $name:literal, $cmd:expr, $format:ident, $data:expr) => {{
+        let [low, high] = encode!({$($await)*} $self, $format, $data).to_le_bytes();
+        let buf = [$cmd, low, high];
+        match $self.write_raw(&buf)$($await)* {
+            Ok(_val) => {
+                trace!("{}_Write: {}", $name, buf);
+                Ok(())
+            }
+            Err(val) => {
+                error!("{}_Write_Error: {}", $name, val);
+                Err(val)
+            }
+        }
+    }};
+}
+
+/// An abstracted way to generate i2c PMBUS Commands and the TPSC536C7 method returning them.
+///
+/// Async, Await, Bus, Method, Type (name but different), Name, Command, format (slinear11 or vout)
+macro_rules! build_command {
+    (
+        {$($async:tt)?} {$($await:tt)*} $bus:path,
+        $(#[$doc:meta])* $method:ident, $type:ident, $name:literal, $cmd:ident, $format:ident
+    ) => {
+        pub struct $type<'a, I> {
+            dev: &'a mut TPSC536C7<I>,
+        }
+
+        impl<'a, I: $bus> $type<'a, I> {
+            pub $($async)? fn read(&mut self) -> Result<f32, VrmError> {
+                let dev = &mut self.dev;
+                send_read!({$($await)*} dev, $name, Command::$cmd.to_address(), $format)
+            }
+            pub $($async)? fn write(&mut self, val: f32) -> Result<(), VrmError> {
+                let dev = &mut self.dev;
+                send_write!({$($await)*} dev, $name, Command::$cmd.to_address(), $format, val)
+            }
+        }
+
+        impl<I: $bus> TPSC536C7<I> {
+            $(#[$doc])*
+            pub fn $method(&mut self) -> $type<'_, I> {
+                $type { dev: self }
+            }
+        }
+    };
+}
+
+/// Generates the TPSC536C7 driver over the given I2C and delay traits
+///
+/// `raw` is the impl the raw SMBus transactions go in, PmbusDevice for the blocking driver and an
+/// inherent (pub) one for the async driver, which has no trait to implement
+macro_rules! tps536c7_driver {
+    (
+        bus: $bus:path,
+        delay: $delay:path,
+        raw: [$($raw:tt)*] $raw_vis:vis,
+        async: [$($async:tt)?],
+        await: [$($await:tt)*] $(,)?
+    ) => {
+        pub struct TPSC536C7<I> {
+            address: u8,
+            i2c: I,
+            /// Currently selected page, tracked so paged formats (VOUT_MODE) can be applied
+            page: Page,
+            /// VOUT_MODE of channel A and B, read at init
+            vout_mode: [Option<VoutMode>; 2],
+            /// Append and verify an SMBus packet error code on every transaction
+            pec: bool,
+            /// Limits every write is checked against
+            envelope: Envelope,
+            /// The last write refused for leaving the envelope
+            rejected: Option<Rejection>,
+        }
+
+        // READ WRITE COMMANDS
+        build_command!(
+            {$($async)?} {$($await)*} $bus,
+            /// Reads / Writes to the voltage output setpoint for the paged channel
+            vout_command, VOUTCommand, "VOutCommand", VOUTCommand, vout
+        );
+        build_command!(
+            {$($async)?} {$($await)*} $bus,
+            /// Reads / Writes to the voltage max for the paged channel
+            vout_max, VOUTMax, "VOutMax", VOUTMax, vout
+        );
+        build_command!(
+            {$($async)?} {$($await)*} $bus,
+            /// Reads / Writes to the voltage min for the paged channel
+            vout_min, VOUTMin, "VOutMin", VOUTMin, vout
+        );
+        build_command!(
+            {$($async)?} {$($await)*} $bus,
+            /// Reads / Writes to the margin high voltage for the paged channel
+            vout_margin_high, VOUTMarginHigh, "VOutMarginHigh", VOUTMarginHigh, vout
+        );
+        build_command!(
+            {$($async)?} {$($await)*} $bus,
+            /// Reads / Writes to the margin low voltage for the paged channel
+            vout_margin_low, VOUTMarginLow, "VOutMarginLow", VOUTMarginLow, vout
+        );
+        build_command!(
+            {$($async)?} {$($await)*} $bus,
+            /// Reads / Writes to the rate of change of the output voltage (mV/us) for the paged
+            /// channel
+            vout_transition_rate, VOUTTransitionRate, "VOutTransitionRate", VOUTTransitionRate,
+            slinear11
+        );
+        build_command!(
+            {$($async)?} {$($await)*} $bus,
+            /// Reads / Writes to the load line (mV/A) for the paged channel
+            vout_droop, VOUTDroop, "VOutDroop", VOUTDroop, slinear11
+        );
+        build_command!(
+            {$($async)?} {$($await)*} $bus,
+            /// Reads / Writes to the switching frequency (kHz) for the paged channel
+            frequency_switch, FrequencySwitch, "FrequencySwitch", FrequencySwitch, slinear11
+        );
+        build_command!(
+            {$($async)?} {$($await)*} $bus,
+            /// Reads / Writes to the input voltage the controller starts converting at
+            vin_on, VinOn, "VinOn", VinOn, slinear11
+        );
+        build_command!(
+            {$($async)?} {$($await)*} $bus,
+            /// Reads / Writes to the input voltage the controller stops converting at
+            vin_off, VinOff, "VinOff", VinOff, slinear11
+        );
+        build_command!(
+            {$($async)?} {$($await)*} $bus,
+            /// Reads / Writes to the input over voltage fault limit
+            vin_ov_fault_limit, VinOVFaultLimit, "VinOVFaultLimit", VinOVFaultLimit, slinear11
+        );
+        build_command!(
+            {$($async)?} {$($await)*} $bus,
+            /// Reads / Writes to the input under voltage fault limit
+            vin_uv_fault_limit, VinUVFaultLimit, "VinUVFaultLimit", VinUVFaultLimit, slinear11
+        );
+        build_command!(
+            {$($async)?} {$($await)*} $bus,
+            /// Reads / Writes to the current output setpoint for the paged channel
+            ///
+            /// Is phased (can read the individual phases and set individual phase)
+            /// values if we want to implement that
+            iout_oc_fault_limit, IOUTOCFaultLimit, "IOutOCFaultLimit", IoutOCFaultLimit, slinear11
+        );
+        build_command!(
+            {$($async)?} {$($await)*} $bus,
+            /// Reads / Writes to the output over current warning limit for the paged channel
+            iout_oc_warn_limit, IOUTOCWarnLimit, "IOutOCWarnLimit", IoutOCWarnLimit, slinear11
+        );
+        build_command!(
+            {$($async)?} {$($await)*} $bus,
+            /// Reads / Writes to the over temperature fault limit for the paged channel
+            ot_fault_limit, OTFaultLimit, "OTFaultLimit", OTFaultLimit, slinear11
+        );
+        build_command!(
+            {$($async)?} {$($await)*} $bus,
+            /// Reads / Writes to the over temperature warning limit for the paged channel
+            ot_warn_limit, OTWarnLimit, "OTWarnLimit", OTWarnLimit, slinear11
+        );
+        build_command!(
+            {$($async)?} {$($await)*} $bus,
+            /// Reads / Writes to the delay (ms) from enable to the output starting to rise
+            ton_delay, TonDelay, "TonDelay", TonDelay, slinear11
+        );
+        build_command!(
+            {$($async)?} {$($await)*} $bus,
+            /// Reads / Writes to the time (ms) the output takes to rise to the setpoint
+            ton_rise, TonRise, "TonRise", TonRise, slinear11
+        );
+        build_command!(
+            {$($async)?} {$($await)*} $bus,
+            /// Reads / Writes to the delay (ms) from disable to the output starting to fall
+            toff_delay, ToffDelay, "ToffDelay", ToffDelay, slinear11
+        );
+        build_command!(
+            {$($async)?} {$($await)*} $bus,
+            /// Reads / Writes to the time (ms) the output takes to fall to zero
+            toff_fall, ToffFall, "ToffFall", ToffFall, slinear11
+        );
+
+        /// Raw access to the vendor defined MFR_SPECIFIC registers (0xD0 - 0xFD) as words
+        pub struct MfrSpecific<'a, I> {
+            dev: &'a mut TPSC536C7<I>,
+            offset: u8,
+        }
+
+        impl<'a, I: $bus> MfrSpecific<'a, I> {
+            pub $($async)? fn read(&mut self) -> Result<u16, VrmError> {
+                let cmd = Command::mfr_specific(self.offset)?.to_address();
+                self.dev.read_word(cmd)$($await)*
+            }
+
+            pub $($async)? fn write(&mut self, val: u16) -> Result<(), VrmError> {
+                let cmd = Command::mfr_specific(self.offset)?.to_address();
+                self.dev.write_word(cmd, val)$($await)*
+            }
+        }
+
+        impl<I: $bus> TPSC536C7<I> {
+            /// PHASE value that addresses every phase of the paged channel
+            pub const ALL_PHASES: u8 = 0xFF;
+            /// Time between STATUS_BYTE polls while the controller writes its NVM
+            const NVM_POLL_MS: u32 = 10;
+            /// Polls before giving up on the NVM, the TPS536C7 takes up to ~100 ms to store
+            const NVM_POLLS: u32 = 50;
+
+            /// Creates the driver, `pec` enables packet error checking on every transaction
+            pub fn new(i2c: I, address: u8, pec: bool) -> TPSC536C7<I> {
+                TPSC536C7 {
+                    address,
+                    i2c,
+                    page: Page::ChannelA,
+                    vout_mode: [None; 2],
+                    pec,
+                    envelope: Envelope::default(),
+                    rejected: None,
+                }
+            }
+
+            /// Reads the per page configuration (VOUT_MODE) that the other commands depend on
+            pub $($async)? fn init(&mut self) -> Result<(), VrmError> {
+                for (idx, page) in [Page::ChannelA, Page::ChannelB].into_iter().enumerate() {
+                    self.page(page)$($await)*?;
+                    let mode = self.read_vout_mode()$($await)*?;
+                    info!("VOUT_MODE {}: {}", page, mode);
+                    self.vout_mode[idx] = Some(mode);
+                }
+                Ok(())
+            }
+
+            /// Limits every write is checked against
+            pub fn envelope(&self) -> &Envelope {
+                &self.envelope
+            }
+
+            pub fn set_envelope(&mut self, envelope: Envelope) {
+                self.envelope = envelope;
+            }
+
+            /// Programs VOUT_MAX and VOUT_MIN of both channels to the envelope, so the controller
+            /// itself keeps the output inside it
+            pub $($async)? fn apply_envelope(&mut self) -> Result<(), VrmError> {
+                for page in [Page::ChannelA, Page::ChannelB] {
+                    let limits = *self.envelope.limits(page);
+                    let c = self.page(page)$($await)*?;
+                    c.vout_max().write(limits.vout_max)$($await)*?;
+                    c.vout_min().write(limits.vout_min)$($await)*?;
+                }
+                Ok(())
+            }
+
+            /// Checks a write (command code first) to the paged channel against the envelope
+            /// without sending it. A refused write is kept for take_rejection
+            pub $($async)? fn check_write(&mut self, data: &[u8]) -> Result<(), VrmError> {
+                let mode = match data.first() {
+                    Some(&cmd) if safety::needs_vout_mode(cmd) => {
+                        Some(self.vout_mode()$($await)*?)
+                    }
+                    _ => None,
+                };
+                match self.envelope.check_write(self.page, data, mode) {
+                    Ok(()) => Ok(()),
+                    Err(rejection) => {
+                        error!("Write Refused: {}", rejection);
+                        self.rejected = Some(rejection);
+                        Err(VrmError::OutOfEnvelope(rejection.violation))
+                    }
+                }
+            }
+
+            /// Takes the last write refused for leaving the envelope, so it is only reported once
+            pub fn take_rejection(&mut self) -> Option<Rejection> {
+                self.rejected.take()
+            }
+
+            pub $($async)? fn command(&mut self, data: &[u8]) -> Result<(), VrmError> {
+                match self.write_raw(data)$($await)* {
+                    Ok(_val) => {
+                        trace!("Write_OK: {}", data);
+                        self.track_command(data);
+                        Ok(())
+                    }
+                    Err(val) => {
+                        error!("Write Error: {}", val);
+                        Err(val)
+                    }
+                }
+            }
+
+            pub $($async)? fn read(&mut self, cmd: u8, buf: &mut [u8]) -> Result<(), VrmError> {
+                match self.bus_read(cmd, buf)$($await)* {
+                    Ok(_val) => {
+                        trace!("Read_OK: {:#X}, {:#X}", cmd, buf);
+                        Ok(())
+                    }
+                    Err(val) => {
+                        error!("Controller Read: {:#X}, {}", cmd, val);
+                        Err(val)
+                    }
+                }
+            }
+
+            /// Writes data (command code first) to the controller, appending the PEC byte if
+            /// enabled
+            ///
+            /// Every write goes through here, the ones leaving the envelope never reach the bus
+            $($async)? fn write_raw(&mut self, data: &[u8]) -> Result<(), VrmError> {
+                self.check_write(data)$($await)*?;
+                self.bus_write(data)$($await)*
+            }
+
+            /// Sends msg and reads back a block response into buf, returning the data
+            $($async)? fn block_transaction<'b>(
+                &mut self,
+                msg: &[u8],
+                buf: &'b mut [u8],
+            ) -> Result<&'b [u8], VrmError> {
+                match self.bus_block(msg, buf)$($await)* {
+                    Ok(count) => {
+                        trace!("Block_Read_OK: {:#X}, {:#X}", msg, buf[..count]);
+                        Ok(&buf[..count])
+                    }
+                    Err(val) => {
+                        error!("Block Read: {:#X}, {}", msg, val);
+                        Err(val)
+                    }
+                }
+            }
+
+            /// Keeps the cached page and VOUT_MODE in sync with raw writes
+            fn track_command(&mut self, data: &[u8]) {
+                match data {
+                    [cmd, page] if *cmd == Command::Page.to_address() => {
+                        self.page = match page {
+                            0x00 => Page::ChannelA,
+                            0x01 => Page::ChannelB,
+                            _ => Page::Both,
+                        };
+                    }
+                    [cmd, ..] if *cmd == Command::VoutMode.to_address() => match self.page {
+                        Page::ChannelA => self.vout_mode[0] = None,
+                        Page::ChannelB => self.vout_mode[1] = None,
+                        Page::Both => self.vout_mode = [None; 2],
+                    },
+                    _ => (),
+                }
+            }
+
+            /// Reads VOUT_MODE of the paged channel from the controller
+            pub $($async)? fn read_vout_mode(&mut self) -> Result<VoutMode, VrmError> {
+                VoutMode::from_bits(self.read_byte(Command::VoutMode.to_address())$($await)*?)
+            }
+
+            pub $($async)? fn on_off_config(&mut self, val: u8) -> Result<(), VrmError> {
+                self.command(&[Command::OnOffConfig.to_address(), val])$($await)*
+            }
+
+            /// Switches the paged channel on, off or to a margin
+            pub $($async)? fn operation(&mut self, operation: Operation) -> Result<(), VrmError> {
+                self.command(&[Command::Operation.to_address(), operation.to_bits()])$($await)*
+            }
+
+            /// Reads OPERATION of the paged channel
+            pub $($async)? fn read_operation(&mut self) -> Result<Operation, VrmError> {
+                Operation::from_bits(self.read_byte(Command::Operation.to_address())$($await)*?)
+            }
+
+            // PAGING OPTIONS
+            pub $($async)? fn page(&mut self, ch: Page) -> Result<&mut Self, VrmError> {
+                self.command(&[Command::Page.to_address(), ch.to_bits()])$($await)*?;
+                Ok(self)
+            }
+
+            pub $($async)? fn ch_a(&mut self) -> Result<&mut Self, VrmError> {
+                self.page(Page::ChannelA)$($await)*
+            }
+
+            pub $($async)? fn ch_b(&mut self) -> Result<&mut Self, VrmError> {
+                self.page(Page::ChannelB)$($await)*
+            }
+
+            pub $($async)? fn ch_ab(&mut self) -> Result<&mut Self, VrmError> {
+                self.page(Page::Both)$($await)*
+            }
+
+            /// Selects which phase of the paged channel phased commands (READ_IOUT, ...) apply to
+            ///
+            /// 0xFF selects all phases, which is what every other part of the driver expects
+            pub $($async)? fn set_phase(&mut self, phase: u8) -> Result<(), VrmError> {
+                self.command(&[Command::Phase.to_address(), phase])$($await)*
+            }
+
+            /// Reads the current and temperature of every phase on a channel into phases
+            ///
+            /// Phases are walked until the controller rejects the phase number, the number found
+            /// is returned. PHASE is always put back to all phases afterwards
+            pub $($async)? fn read_phase_telemetry(
+                &mut self,
+                page: Page,
+                phases: &mut [PhaseTelemetry],
+            ) -> Result<usize, VrmError> {
+                self.page(page)$($await)*?;
+                let result = self.read_phases(phases)$($await)*;
+                self.set_phase(Self::ALL_PHASES)$($await)*?;
+                result
+            }
+
+            $($async)? fn read_phases(
+                &mut self,
+                phases: &mut [PhaseTelemetry],
+            ) -> Result<usize, VrmError> {
+                for (idx, phase) in phases.iter_mut().enumerate() {
+                    match self.set_phase(idx as u8)$($await)* {
+                        Ok(()) => (),
+                        Err(VrmError::UnsupportedCommand) if idx > 0 => return Ok(idx),
+                        Err(err) => return Err(err),
+                    }
+                    phase.current = self.read_iout()$($await)*?;
+                    phase.temperature = self.read_temperature_1()$($await)*?;
+                }
+                Ok(phases.len())
+            }
+
+            // NON VOLATILE MEMORY
+            /// Stores the current configuration of both channels as the power on defaults
+            ///
+            /// Refused while either channel has a fault latched so a bad state is never made
+            /// permanent
+            pub $($async)? fn store_default_all<D: $delay>(
+                &mut self,
+                delay: &mut D,
+            ) -> Result<(), VrmError> {
+                self.store(Command::StoreDefaultAll.to_address(), delay)$($await)*
+            }
+
+            /// Stores the current configuration of both channels to the user NVM store
+            pub $($async)? fn store_user_all<D: $delay>(
+                &mut self,
+                delay: &mut D,
+            ) -> Result<(), VrmError> {
+                self.store(Command::StoreUserAll.to_address(), delay)$($await)*
+            }
+
+            /// Reloads the configuration of both channels from the power on defaults, and
+            /// programs the envelope's limits again as the defaults replaced them
+            pub $($async)? fn restore_default_all<D: $delay>(
+                &mut self,
+                delay: &mut D,
+            ) -> Result<(), VrmError> {
+                self.command(&[Command::RestoreDefaultAll.to_address()])$($await)*?;
+                // VOUT_MODE may have come back different
+                self.vout_mode = [None; 2];
+                self.wait_not_busy(delay)$($await)*?;
+                self.apply_envelope()$($await)*
+            }
+
+            $($async)? fn store<D: $delay>(
+                &mut self,
+                cmd: u8,
+                delay: &mut D,
+            ) -> Result<(), VrmError> {
+                for page in [Page::ChannelA, Page::ChannelB] {
+                    self.page(page)$($await)*?;
+                    let status = self.status_word()$($await)*?;
+                    if status.is_faulted() {
+                        error!("NVM Store Refused {}: {}", page, status);
+                        return Err(VrmError::FaultLatched);
+                    }
+                }
+                self.command(&[cmd])$($await)*?;
+                self.wait_not_busy(delay)$($await)*
+            }
+
+            /// Polls STATUS_BYTE until BUSY clears, the controller may NACK while it is busy
+            $($async)? fn wait_not_busy<D: $delay>(
+                &mut self,
+                delay: &mut D,
+            ) -> Result<(), VrmError> {
+                for _ in 0..Self::NVM_POLLS {
+                    delay.delay_ms(Self::NVM_POLL_MS)$($await)*;
+                    match self.status_byte()$($await)* {
+                        Ok(status) if !status.contains(StatusByte::BUSY) => return Ok(()),
+                        Ok(_) | Err(VrmError::Nack) => (),
+                        Err(err) => return Err(err),
+                    }
+                }
+                Err(VrmError::Timeout)
+            }
+
+            pub $($async)? fn read_page(&mut self) -> Result<(), VrmError> {
+                let mut buf = [b'\0'; 1];
+                self.read(Command::Page.to_address(), &mut buf)$($await)*
+            }
+
+            /// Reads the raw vendor specific extended status bytes
+            pub $($async)? fn read_status_extended(&mut self) -> Result<[u8; 7], VrmError> {
+                let mut buf = [b'\0'; 7];
+                self.read(Command::StatusExtended.to_address(), &mut buf)$($await)*?;
+                Ok(buf)
+            }
+
+            /// Reads the status registers of both channels in one block read, channel A then
+            /// channel B
+            pub $($async)? fn read_status_all(&mut self) -> Result<[ChannelStatus; 2], VrmError> {
+                let mut buf = [b'\0'; 2 * ChannelStatus::LENGTH];
+                let data = self.block_read(Command::StatusAll.to_address(), &mut buf)$($await)*?;
+                if data.len() < 2 * ChannelStatus::LENGTH {
+                    return Err(VrmError::InvalidData);
+                }
+                let mut chan_a = [b'\0'; ChannelStatus::LENGTH];
+                let mut chan_b = [b'\0'; ChannelStatus::LENGTH];
+                chan_a.copy_from_slice(&data[..ChannelStatus::LENGTH]);
+                chan_b.copy_from_slice(&data[ChannelStatus::LENGTH..]);
+                Ok([
+                    ChannelStatus::from_bytes(&chan_a),
+                    ChannelStatus::from_bytes(&chan_b),
+                ])
+            }
+
+            /// Reads / Writes to a vendor specific register, MFR_SPECIFIC_00 + offset
+            pub fn mfr_specific(&mut self, offset: u8) -> MfrSpecific<'_, I> {
+                MfrSpecific { dev: self, offset }
+            }
+
+            // READ ONLY COMMANDS
+            /// Reads the ouput voltage at the paged channel
+            pub $($async)? fn read_vout(&mut self) -> Result<f32, VrmError> {
+                send_read!({$($await)*} self, "ReadVout", Command::ReadVout.to_address(), vout)
+            }
+            /// Reads the output current at the paged channel
+            pub $($async)? fn read_iout(&mut self) -> Result<f32, VrmError> {
+                send_read!(
+                    {$($await)*} self,
+                    "ReadIout",
+                    Command::ReadIout.to_address(),
+                    slinear11
+                )
+            }
+            /// Reads the output temperature at the paged channel
+            pub $($async)? fn read_temperature_1(&mut self) -> Result<f32, VrmError> {
+                send_read!(
+                    {$($await)*} self,
+                    "READTemperature1",
+                    Command::ReadTemperature1.to_address(),
+                    slinear11
+                )
+            }
+            /// Reads the secondary temperature sensor at the paged channel
+            pub $($async)? fn read_temperature_2(&mut self) -> Result<f32, VrmError> {
+                send_read!(
+                    {$($await)*} self,
+                    "READTemperature2",
+                    Command::ReadTemperature2.to_address(),
+                    slinear11
+                )
+            }
+            /// Reads the input voltage
+            pub $($async)? fn read_vin(&mut self) -> Result<f32, VrmError> {
+                send_read!({$($await)*} self, "ReadVin", Command::ReadVin.to_address(), slinear11)
+            }
+            /// Reads the input current at the paged channel
+            pub $($async)? fn read_iin(&mut self) -> Result<f32, VrmError> {
+                send_read!({$($await)*} self, "ReadIin", Command::ReadIin.to_address(), slinear11)
+            }
+            /// Reads the input power at the paged channel
+            pub $($async)? fn read_pin(&mut self) -> Result<f32, VrmError> {
+                send_read!({$($await)*} self, "ReadPin", Command::ReadPin.to_address(), slinear11)
+            }
+            /// Reads the output power at the paged channel
+            pub $($async)? fn read_pout(&mut self) -> Result<f32, VrmError> {
+                send_read!(
+                    {$($await)*} self,
+                    "ReadPout",
+                    Command::ReadPout.to_address(),
+                    slinear11
+                )
+            }
+        }
+
+        $($raw)* {
+            /// Selects the page (channel) that following commands apply to
+            $raw_vis $($async)? fn select_page(&mut self, page: Page) -> Result<(), VrmError> {
+                self.page(page)$($await)*?;
+                Ok(())
+            }
+
+            /// Sends a command code with no data
+            $raw_vis $($async)? fn send_byte(&mut self, cmd: u8) -> Result<(), VrmError> {
+                self.command(&[cmd])$($await)*
+            }
+
+            $raw_vis $($async)? fn read_byte(&mut self, cmd: u8) -> Result<u8, VrmError> {
+                let mut buf = [b'\0'; 1];
+                self.read(cmd, &mut buf)$($await)*?;
+                Ok(buf[0])
+            }
+
+            $raw_vis $($async)? fn write_byte(&mut self, cmd: u8, val: u8) -> Result<(), VrmError> {
+                self.command(&[cmd, val])$($await)*
+            }
+
+            $raw_vis $($async)? fn read_word(&mut self, cmd: u8) -> Result<u16, VrmError> {
+                let mut buf = [b'\0'; 2];
+                self.read(cmd, &mut buf)$($await)*?;
+                Ok(to_u16(buf))
+            }
+
+            $raw_vis $($async)? fn write_word(
+                &mut self,
+                cmd: u8,
+                val: u16,
+            ) -> Result<(), VrmError> {
+                let [low, high] = val.to_le_bytes();
+                self.command(&[cmd, low, high])$($await)*
+            }
+
+            /// SMBus block read, the data (without the byte count) is placed in buf and returned
+            $raw_vis $($async)? fn block_read<'b>(
+                &mut self,
+                cmd: u8,
+                buf: &'b mut [u8],
+            ) -> Result<&'b [u8], VrmError> {
+                self.block_transaction(&[cmd], buf)$($await)*
+            }
+
+            /// SMBus block write, the byte count is added in front of data
+            $raw_vis $($async)? fn block_write(
+                &mut self,
+                cmd: u8,
+                data: &[u8],
+            ) -> Result<(), VrmError> {
+                let mut buf = [b'\0'; MAX_TRANSACTION];
+                let len = block_message(cmd, data, &mut buf)?;
+                self.command(&buf[..len])$($await)*
+            }
+
+            /// SMBus block write - block read process call, the response data is placed in buf
+            $raw_vis $($async)? fn block_process_call<'b>(
+                &mut self,
+                cmd: u8,
+                data: &[u8],
+                buf: &'b mut [u8],
+            ) -> Result<&'b [u8], VrmError> {
+                let mut msg = [b'\0'; MAX_TRANSACTION];
+                let len = block_message(cmd, data, &mut msg)?;
+                self.block_transaction(&msg[..len], buf)$($await)*
+            }
+
+            /// Returns the cached VOUT_MODE of the paged channel, reading it if unknown
+            $raw_vis $($async)? fn vout_mode(&mut self) -> Result<VoutMode, VrmError> {
+                let idx = match self.page {
+                    Page::ChannelA => 0,
+                    Page::ChannelB => 1,
+                    Page::Both => {
+                        // Writes to both pages only make sense if they share a format
+                        return match self.vout_mode {
+                            [Some(a), Some(b)] if a == b => Ok(a),
+                            _ => Err(VrmError::InvalidData),
+                        };
+                    }
+                };
+                match self.vout_mode[idx] {
+                    Some(mode) => Ok(mode),
+                    None => {
+                        let mode = self.read_vout_mode()$($await)*?;
+                        self.vout_mode[idx] = Some(mode);
+                        Ok(mode)
+                    }
+                }
+            }
+        }
+    };
+}
@@ -0,0 +1,28 @@
+#![no_std]
+
+// Platform independent part of the firmware: the VRM controller driver, the front panel state
+// machine, the USB packet format, the SCPI text interface and the policy tying them together.
+// Builds for the host so it can be tested without the board, the STM32 binary only wires it to
+// the hardware.
+
+#[macro_use]
+mod fmt;
+#[macro_use]
+mod driver;
+
+pub mod control;
+pub mod margin;
+pub mod navigation;
+pub mod pmbus;
+pub mod profile;
+pub mod protocol;
+pub mod ramp;
+pub mod safety;
+pub mod scpi;
+pub mod sequence;
+pub mod settings;
+pub mod stream;
+pub mod vrm_controller;
+#[cfg(feature = "async")]
+pub mod vrm_controller_async;
+pub mod vrm_status;
@@ -0,0 +1,341 @@
+// Voltage margining for stability testing
+//
+// Moves a rail a set percentage above or below its setpoint without touching VOUT_COMMAND:
+// VOUT_MARGIN_HIGH or VOUT_MARGIN_LOW is written from the setpoint and the rail's percentage and
+// OPERATION switches the rail to it (with faults acted on as usual), or back to the nominal
+// voltage. The margins go through the safety envelope like every other VOUT write. The rails
+// have to be on, as OPERATION with the margin bits set would otherwise turn a rail on outside
+// the power sequence, and a rail that is turned off (or faults) is back at nominal when it next
+// comes up.
+//
+// A margin sweep takes each selected rail to its low margin, its high margin and back to
+// nominal, holding every step for the dwell time. Telemetry of the rail is read at the end of
+// each dwell and logged, and kept for the host to fetch with Request::GetSweepStep. A fault in
+// STATUS_WORD ends the sweep early and puts the swept rails back to nominal.
+
+use crate::pmbus::{Command, MarginFault, Operation, Page, PmbusDevice};
+use crate::protocol::{ChannelSample, Request, RequestError, Response, StreamConfig};
+use crate::sequence::Sequencer;
+use crate::stream::read_channel;
+
+/// Margin both rails start out with, in percent of the setpoint
+pub const DEFAULT_PERCENT: f32 = 5.;
+/// Smallest and largest margins that can be set, in percent
+pub const MIN_PERCENT: f32 = 0.5;
+pub const MAX_PERCENT: f32 = 20.;
+
+/// Shortest and longest time a sweep holds each step, in milliseconds
+pub const MIN_DWELL_MS: u32 = 10;
+pub const MAX_DWELL_MS: u32 = 60_000;
+
+/// Steps of a sweep of both rails, low, high and nominal for each
+pub const SWEEP_STEPS: usize = 2 * ORDER.len();
+
+// Levels a sweep takes each rail through, in order
+const ORDER: [Level; 3] = [Level::Low, Level::High, Level::Nominal];
+
+/// Voltage a rail regulates to
+#[derive(Clone, Copy, Debug, Default, PartialEq, bincode::Decode, bincode::Encode)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Level {
+    /// VOUT_COMMAND
+    #[default]
+    Nominal,
+    /// The setpoint plus the rail's margin
+    High,
+    /// The setpoint minus the rail's margin
+    Low,
+}
+
+impl Level {
+    /// Short description for the display
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Level::Nominal => "Nominal",
+            Level::High => "High",
+            Level::Low => "Low",
+        }
+    }
+}
+
+/// Rails a sweep goes through and how long it holds each step
+#[derive(Clone, Copy, Debug, Default, PartialEq, bincode::Decode, bincode::Encode)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SweepConfig {
+    pub core: bool,
+    pub mem: bool,
+    /// MIN_DWELL_MS to MAX_DWELL_MS
+    pub dwell_ms: u32,
+}
+
+/// Telemetry of a rail at the end of one sweep step
+#[derive(Clone, Copy, Debug, PartialEq, bincode::Decode, bincode::Encode)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SweepStep {
+    pub page: Page,
+    pub level: Level,
+    pub sample: ChannelSample,
+}
+
+/// Margin settings of both rails and the progress of a sweep, channel A is the core and channel
+/// B the memory
+#[derive(Clone, Copy, Debug, Default, PartialEq, bincode::Decode, bincode::Encode)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct MarginStatus {
+    /// Margin of each rail in percent
+    pub percent: [f32; 2],
+    pub level: [Level; 2],
+    /// Step the running sweep is on, None once it ended
+    pub sweep: Option<u8>,
+    /// Steps of the last sweep logged so far, fetched with Request::GetSweepStep
+    pub logged: u8,
+}
+
+/// Margins both rails and runs margin sweeps
+#[derive(Debug)]
+pub struct Margin {
+    percent: [f32; 2],
+    level: [Level; 2],
+    sweep: Option<Sweep>,
+    log: [Option<SweepStep>; SWEEP_STEPS],
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Sweep {
+    config: SweepConfig,
+    step: usize,
+    // Clock when the step was taken, None until the first poll
+    since: Option<u32>,
+}
+
+impl Default for Margin {
+    fn default() -> Margin {
+        Margin {
+            percent: [DEFAULT_PERCENT; 2],
+            level: [Level::Nominal; 2],
+            sweep: None,
+            log: [None; SWEEP_STEPS],
+        }
+    }
+}
+
+impl Margin {
+    /// Margin of channel (0 for the core, 1 for the memory) in percent
+    pub fn percent(&self, channel: usize) -> f32 {
+        self.percent[channel.min(1)]
+    }
+
+    /// Sets the margin of channel, used the next time the rail is margined
+    pub fn set_percent(&mut self, channel: usize, percent: f32) -> Result<(), RequestError> {
+        if !(MIN_PERCENT..=MAX_PERCENT).contains(&percent) {
+            return Err(RequestError::InvalidMargin);
+        }
+        self.percent[channel.min(1)] = percent;
+        Ok(())
+    }
+
+    pub fn level(&self, channel: usize) -> Level {
+        self.level[channel.min(1)]
+    }
+
+    pub fn is_sweeping(&self) -> bool {
+        self.sweep.is_some()
+    }
+
+    pub fn status(&self) -> MarginStatus {
+        MarginStatus {
+            percent: self.percent,
+            level: self.level,
+            sweep: self.sweep.map(|sweep| sweep.step as u8),
+            logged: self.log.iter().flatten().count() as u8,
+        }
+    }
+
+    /// Telemetry logged for a step of the last sweep, None if it was not taken
+    pub fn logged(&self, step: usize) -> Option<SweepStep> {
+        self.log.get(step).copied().flatten()
+    }
+
+    /// Moves channel to a margin or back to nominal, only while the output is on
+    pub fn set_level<D: PmbusDevice>(
+        &mut self,
+        controller: &mut D,
+        seq: &Sequencer,
+        channel: usize,
+        level: Level,
+    ) -> Result<(), RequestError> {
+        if !seq.is_on() {
+            return Err(RequestError::OutputOff);
+        }
+        let channel = channel.min(1);
+        let page = Page::from_channel(channel);
+        let percent = self.percent[channel];
+        let result = controller.select_page(page).and_then(|()| {
+            let operation = match level {
+                Level::Nominal => Operation::On,
+                Level::High | Level::Low => {
+                    let (cmd, scale, operation) = match level {
+                        Level::High => (
+                            Command::VOUTMarginHigh,
+                            1. + percent / 100.,
+                            Operation::MarginHigh(MarginFault::Act),
+                        ),
+                        _ => (
+                            Command::VOUTMarginLow,
+                            1. - percent / 100.,
+                            Operation::MarginLow(MarginFault::Act),
+                        ),
+                    };
+                    let nominal = controller.read_vout_class(Command::VOUTCommand.to_address())?;
+                    controller.write_vout_class(cmd.to_address(), nominal * scale)?;
+                    operation
+                }
+            };
+            controller.write_byte(Command::Operation.to_address(), operation.to_bits())
+        });
+        result.map_err(RequestError::Vrm)?;
+        info!("Margin {}: {}", channel, level);
+        self.level[channel] = level;
+        Ok(())
+    }
+
+    /// Starts a sweep through the rails in config, clearing the log of the last one
+    pub fn start_sweep<D: PmbusDevice>(
+        &mut self,
+        controller: &mut D,
+        seq: &Sequencer,
+        config: SweepConfig,
+    ) -> Result<(), RequestError> {
+        if !(MIN_DWELL_MS..=MAX_DWELL_MS).contains(&config.dwell_ms) || step(&config, 0).is_none() {
+            return Err(RequestError::InvalidMargin);
+        }
+        let (channel, level) = step(&config, 0).unwrap_or_default();
+        self.set_level(controller, seq, channel, level)?;
+        info!("Margin Sweep: {} ms Dwell", config.dwell_ms);
+        self.log = [None; SWEEP_STEPS];
+        self.sweep = Some(Sweep {
+            config,
+            step: 0,
+            since: None,
+        });
+        Ok(())
+    }
+
+    /// Ends a running sweep, putting the swept rails back to nominal
+    pub fn stop_sweep<D: PmbusDevice>(
+        &mut self,
+        controller: &mut D,
+        seq: &Sequencer,
+    ) -> Result<(), RequestError> {
+        let Some(sweep) = self.sweep.take() else {
+            return Ok(());
+        };
+        info!("Margin Sweep Stopped");
+        let swept = [sweep.config.core, sweep.config.mem];
+        for channel in (0..2).filter(|&channel| swept[channel]) {
+            self.set_level(controller, seq, channel, Level::Nominal)?;
+        }
+        Ok(())
+    }
+
+    /// Ends a dwell that is up, returning the telemetry logged for it
+    ///
+    /// Forgets the margins once the output is no longer on, the sequencer has switched the
+    /// rails off through OPERATION by then
+    pub fn poll<D: PmbusDevice>(
+        &mut self,
+        controller: &mut D,
+        seq: &Sequencer,
+        now: u32,
+    ) -> Option<SweepStep> {
+        if !seq.is_on() {
+            if self.sweep.take().is_some() {
+                warn!("Margin Sweep Ended: Output Off");
+            }
+            self.level = [Level::Nominal; 2];
+            return None;
+        }
+        let sweep = self.sweep.as_mut()?;
+        let Some(since) = sweep.since else {
+            sweep.since = Some(now);
+            return None;
+        };
+        if now.wrapping_sub(since) < sweep.config.dwell_ms * 1000 {
+            return None;
+        }
+
+        let (channel, level) = step(&sweep.config, sweep.step)?;
+        let config = StreamConfig {
+            fields: StreamConfig::ALL,
+            ..Default::default()
+        };
+        let logged = SweepStep {
+            page: Page::from_channel(channel),
+            level,
+            sample: read_channel(controller, &config, Page::from_channel(channel)),
+        };
+        info!("Margin Sweep Step {}: {}", sweep.step, logged);
+        self.log[sweep.step] = Some(logged);
+        let faulted = logged.sample.error || logged.sample.status.is_some_and(|s| s.is_faulted());
+        sweep.step += 1;
+        sweep.since = None;
+        let next = step(&sweep.config, sweep.step);
+
+        let result = match next {
+            _ if faulted => {
+                error!("Margin Sweep Aborted at Step {}", sweep.step - 1);
+                self.stop_sweep(controller, seq)
+            }
+            Some((channel, level)) => self.set_level(controller, seq, channel, level),
+            None => {
+                info!("Margin Sweep Done");
+                self.sweep = None;
+                Ok(())
+            }
+        };
+        if let Err(err) = result {
+            error!("Margin Sweep Failed: {}", err);
+            self.sweep = None;
+        }
+        Some(logged)
+    }
+
+    /// Handles the margin requests, None for any other request
+    pub fn handle<D: PmbusDevice>(
+        &mut self,
+        request: &Request,
+        controller: &mut D,
+        seq: &Sequencer,
+    ) -> Option<Response> {
+        let result = match *request {
+            Request::SetMargin { page, level } => {
+                self.set_level(controller, seq, page as usize, level)
+            }
+            Request::SetMarginPercent { page, percent } => self.set_percent(page as usize, percent),
+            Request::StartMarginSweep(config) => self.start_sweep(controller, seq, config),
+            Request::StopMarginSweep => self.stop_sweep(controller, seq),
+            Request::GetMargin => return Some(Response::Margin(self.status())),
+            Request::GetSweepStep(step) => {
+                return Some(match self.logged(step as usize) {
+                    Some(logged) => Response::SweepStep(logged),
+                    None => Response::Error(RequestError::InvalidMargin),
+                })
+            }
+            _ => return None,
+        };
+        Some(match result {
+            Ok(()) => Response::Ack,
+            Err(err) => Response::Error(err),
+        })
+    }
+}
+
+// Channel and level of a step of the sweep, None past its end
+fn step(config: &SweepConfig, idx: usize) -> Option<(usize, Level)> {
+    let channel = [config.core, config.mem]
+        .into_iter()
+        .enumerate()
+        .filter_map(|(channel, swept)| swept.then_some(channel))
+        .nth(idx / ORDER.len())?;
+    Some((channel, ORDER[idx % ORDER.len()]))
+}
@@ -0,0 +1,356 @@
+use embedded_graphics::prelude::Point;
+
+use crate::margin::Level;
+use crate::pmbus::{PhaseTelemetry, MAX_PHASES};
+use crate::profile::PROFILES;
+use crate::safety::Envelope;
+use crate::vrm_status::StatusWord;
+
+#[derive(Debug, Default)]
+pub struct Navigation {
+    // x, y
+    position: (i32, i32),
+    mode: Mode,
+    // Value being edited in update mode
+    value: f32,
+    // Slot selected on the profile screen
+    profile: usize,
+    // Level selected on the margin screen, only written once confirmed with Enter
+    margin: Level,
+    // Limits the edited value is kept within
+    envelope: Envelope,
+}
+
+/// Front panel buttons
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Button {
+    Up,
+    Down,
+    Left,
+    Right,
+    Enter,
+}
+
+/// What the main loop has to do with the controller after a button press
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+    None,
+    /// The value edited at the position was accepted and has to be written to the controller
+    Write((i32, i32), f32),
+    /// The user confirmed committing the settings to the controller NVM
+    StoreNvm,
+    /// The profile in the slot has to be written to the controller
+    LoadProfile(usize),
+    /// The current setpoints have to be stored in the profile slot
+    SaveProfile(usize),
+    /// The user asked to switch the output off if it is on (or coming up), and on otherwise
+    ToggleOutput,
+    /// The user confirmed margining the rail of the column (0 for the core, 1 for the memory)
+    Margin(usize, Level),
+}
+
+// Implements navigation across the microcontroller for the user input
+impl Navigation {
+    const X_MAX: i32 = 2;
+    const Y_MAX: i32 = 3;
+
+    pub fn get_position(&self) -> (i32, i32) {
+        self.position
+    }
+
+    /// Moves the cursor, clamped to the grid
+    pub fn set_position(&mut self, position: (i32, i32)) {
+        self.position = (
+            position.0.clamp(0, Self::X_MAX - 1),
+            position.1.clamp(0, Self::Y_MAX - 1),
+        );
+    }
+
+    pub fn get_point(&self) -> Point {
+        translate_point(self.position)
+    }
+
+    pub fn move_left(&mut self) {
+        if self.position.0 > 0 {
+            self.position.0 -= 1;
+        }
+    }
+
+    pub fn move_right(&mut self) {
+        if self.position.0 < Self::X_MAX - 1 {
+            self.position.0 += 1;
+        }
+    }
+
+    pub fn move_up(&mut self) {
+        if self.position.1 > 0 {
+            self.position.1 -= 1;
+        }
+    }
+
+    pub fn move_down(&mut self) {
+        if self.position.1 < Self::Y_MAX - 1 {
+            self.position.1 += 1;
+        }
+    }
+
+    pub fn change_mode(&mut self) {
+        match self.mode {
+            Mode::Navigation => self.mode = Mode::Update,
+            Mode::Update => self.mode = Mode::Navigation,
+            Mode::Confirm => self.mode = Mode::Navigation,
+            Mode::Profile => self.mode = Mode::Navigation,
+            Mode::Output => self.mode = Mode::Navigation,
+            Mode::Margin => self.mode = Mode::Navigation,
+        }
+    }
+
+    // Asks the user to confirm committing the settings to the controller NVM
+    pub fn confirm(&mut self) {
+        self.mode = Mode::Confirm;
+    }
+
+    pub fn get_mode(&self) -> &Mode {
+        &self.mode
+    }
+
+    /// Value being edited in update mode
+    pub fn get_value(&self) -> f32 {
+        self.value
+    }
+
+    /// Slot selected on the profile screen
+    pub fn get_profile(&self) -> usize {
+        self.profile
+    }
+
+    /// Level selected on the margin screen, not yet applied
+    pub fn get_margin(&self) -> Level {
+        self.margin
+    }
+
+    /// Limits values edited in update mode are kept within, the controller's envelope
+    pub fn set_envelope(&mut self, envelope: Envelope) {
+        self.envelope = envelope;
+    }
+
+    /// Handles one press of the front panel, returning what has to be sent to the controller
+    pub fn press(&mut self, button: Button, dev: &mut Device) -> Action {
+        match self.mode {
+            Mode::Navigation => match button {
+                // Up past the top row opens the profile screen
+                Button::Up if self.position.1 == 0 => self.mode = Mode::Profile,
+                // And left past the left column the output screen
+                Button::Left if self.position.0 == 0 => self.mode = Mode::Output,
+                // And down past the bottom row the margin screen of the column's rail
+                Button::Down if self.position.1 == Self::Y_MAX - 1 => {
+                    self.mode = Mode::Margin;
+                    self.margin = Level::Nominal;
+                }
+                Button::Up => self.move_up(),
+                Button::Down => self.move_down(),
+                Button::Right => self.move_right(),
+                Button::Left => self.move_left(),
+                Button::Enter => {
+                    if self.position.1 == 2 {
+                        // Temperature is read only, it instead opens the NVM commit prompt
+                        self.confirm();
+                        return Action::None;
+                    }
+                    self.change_mode();
+
+                    // temporary value that is being updated
+                    self.value = match self.position {
+                        (0, 0) => dev.core().get_voltage_setpoint(),
+                        (0, 1) => dev.core().get_current_limit(),
+                        (1, 0) => dev.mem().get_voltage_setpoint(),
+                        (1, 1) => dev.mem().get_current_limit(),
+                        (_, _) => 0., // Default condition that sound never match
+                    };
+                }
+            },
+            Mode::Update => {
+                let (step_small, step_large) = match self.position {
+                    (_, 0) => (0.005, 0.1),
+                    (_, 1) => (1., 10.),
+                    (_, _) => (0., 0.),
+                };
+                match button {
+                    Button::Up => self.value += step_small,
+                    Button::Down => self.value -= step_small,
+                    Button::Right => self.value += step_large,
+                    Button::Left => self.value -= step_large,
+                    Button::Enter => {
+                        self.change_mode();
+                        dev.store_value(self.position, self.value);
+                        return Action::Write(self.position, self.value);
+                    }
+                }
+                self.value = self.envelope.clamp(self.position, self.value);
+            }
+            Mode::Confirm => {
+                // Confirm with a different button than the one that opened the prompt so
+                // holding Enter can never commit by accident
+                self.change_mode();
+                if button == Button::Up {
+                    return Action::StoreNvm;
+                }
+            }
+            Mode::Profile => match button {
+                Button::Up => self.profile = self.profile.saturating_sub(1),
+                Button::Down => self.profile = (self.profile + 1).min(PROFILES - 1),
+                Button::Left => self.change_mode(),
+                Button::Enter => {
+                    self.change_mode();
+                    return Action::LoadProfile(self.profile);
+                }
+                Button::Right => {
+                    self.change_mode();
+                    return Action::SaveProfile(self.profile);
+                }
+            },
+            Mode::Output => {
+                self.change_mode();
+                if button == Button::Enter {
+                    return Action::ToggleOutput;
+                }
+            }
+            // The buttons are read by level, so the Down that opened the screen keeps arriving
+            // while held. Up and Down only select, a separate Enter margins the rail
+            Mode::Margin => match button {
+                Button::Up => {
+                    self.margin = match self.margin {
+                        Level::Low => Level::Nominal,
+                        _ => Level::High,
+                    }
+                }
+                Button::Down => {
+                    self.margin = match self.margin {
+                        Level::High => Level::Nominal,
+                        _ => Level::Low,
+                    }
+                }
+                Button::Enter => return Action::Margin(self.position.0 as usize, self.margin),
+                Button::Left | Button::Right => self.change_mode(),
+            },
+        }
+        Action::None
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Mode {
+    #[default]
+    Navigation,
+    Update,
+    Confirm,
+    /// Choosing a profile: Enter loads it, Right saves the current setpoints to it, Left goes
+    /// back
+    Profile,
+    /// Shows whether the output is on, Enter switches it and any other button goes back
+    Output,
+    /// Margining the rail of the column: Up and Down select low, nominal or high, Enter applies
+    /// the selection and Left or Right go back to the grid
+    Margin,
+}
+
+#[derive(Clone, Debug, Default, bincode::Decode, bincode::Encode)]
+pub struct Device {
+    core: Channel,
+    mem: Channel,
+}
+
+impl Device {
+    pub fn core(&mut self) -> &mut Channel {
+        &mut self.core
+    }
+    pub fn mem(&mut self) -> &mut Channel {
+        &mut self.mem
+    }
+    pub fn store_value(&mut self, point: (i32, i32), val: f32) {
+        let chan = match point {
+            (0, _) => self.core(),
+            (1, _) => self.mem(),
+            (_, _) => return,
+        };
+        match point {
+            (_, 0) => chan.set_voltage_setpoint(val),
+            (_, 1) => chan.set_current_limit(val),
+            (_, _) => (),
+        };
+    }
+}
+
+#[derive(Clone, Debug, Default, bincode::Decode, bincode::Encode)]
+pub struct Channel {
+    voltage: f32,
+    voltage_setpoint: f32,
+    current: f32,
+    current_limit: f32,
+    temperature: f32,
+    error: bool,
+    status: StatusWord,
+    phases: [PhaseTelemetry; MAX_PHASES],
+    phase_count: u8,
+}
+
+impl Channel {
+    pub fn get_voltage(&self) -> f32 {
+        self.voltage
+    }
+    pub fn set_voltage(&mut self, val: f32) {
+        self.voltage = val;
+    }
+    pub fn get_voltage_setpoint(&self) -> f32 {
+        self.voltage_setpoint
+    }
+    pub fn set_voltage_setpoint(&mut self, val: f32) {
+        self.voltage_setpoint = val;
+    }
+    pub fn get_current(&self) -> f32 {
+        self.current
+    }
+    pub fn set_current(&mut self, val: f32) {
+        self.current = val;
+    }
+    pub fn get_current_limit(&self) -> f32 {
+        self.current_limit
+    }
+    pub fn set_current_limit(&mut self, val: f32) {
+        self.current_limit = val;
+    }
+    pub fn get_temperature(&self) -> f32 {
+        self.temperature
+    }
+    pub fn set_temperature(&mut self, val: f32) {
+        self.temperature = val;
+    }
+    /// True when the last read of this channel from the controller failed
+    pub fn get_error(&self) -> bool {
+        self.error
+    }
+    pub fn set_error(&mut self, val: bool) {
+        self.error = val;
+    }
+    pub fn get_status(&self) -> StatusWord {
+        self.status
+    }
+    pub fn set_status(&mut self, val: StatusWord) {
+        self.status = val;
+    }
+    /// Per phase current and temperature, only the phases the controller reported
+    pub fn get_phases(&self) -> &[PhaseTelemetry] {
+        &self.phases[..self.phase_count as usize]
+    }
+    pub fn phases_mut(&mut self) -> &mut [PhaseTelemetry; MAX_PHASES] {
+        &mut self.phases
+    }
+    pub fn set_phase_count(&mut self, val: usize) {
+        self.phase_count = val.min(MAX_PHASES) as u8;
+    }
+}
+
+pub fn translate_point(point: (i32, i32)) -> Point {
+    Point::new(27 + 6 * 9 * point.0, 16 + 16 * point.1)
+}
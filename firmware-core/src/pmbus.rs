@@ -0,0 +1,943 @@
+use embedded_hal::i2c::{Error, ErrorKind, NoAcknowledgeSource};
+use pmbus_types_rs::slinear11;
+
+use crate::safety::{self, Envelope, Rejection, Violation};
+use crate::vrm_controller::TPSC536C7;
+use crate::vrm_status::{
+    StatusByte, StatusCml, StatusInput, StatusIout, StatusTemperature, StatusVout, StatusWord,
+};
+
+/// Errors that can occur while talking to the VRM controller
+#[derive(Clone, Copy, Debug, PartialEq, bincode::Decode, bincode::Encode)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum VrmError {
+    /// The controller did not acknowledge its address or a data byte
+    Nack,
+    /// Another master won arbitration on the bus
+    ArbitrationLoss,
+    /// The packet error code did not match the received data
+    PecMismatch,
+    /// The received data could not be converted to a valid value
+    InvalidData,
+    /// The controller does not support the requested command
+    UnsupportedCommand,
+    /// Any other bus level error (overrun, misplaced start/stop, etc.)
+    Bus,
+    /// The operation was refused because a fault is latched in STATUS_WORD
+    FaultLatched,
+    /// The controller stayed busy for longer than the operation allows
+    Timeout,
+    /// The write was refused for leaving the safety envelope, see safety::Envelope
+    OutOfEnvelope(Violation),
+}
+
+impl From<ErrorKind> for VrmError {
+    fn from(kind: ErrorKind) -> VrmError {
+        match kind {
+            ErrorKind::NoAcknowledge(NoAcknowledgeSource::Data) => VrmError::UnsupportedCommand,
+            ErrorKind::NoAcknowledge(_) => VrmError::Nack,
+            ErrorKind::ArbitrationLoss => VrmError::ArbitrationLoss,
+            _ => VrmError::Bus,
+        }
+    }
+}
+
+/// Measurements every PMBus controller reports for the paged channel
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Telemetry {
+    pub vout: f32,
+    pub iout: f32,
+    pub temperature: f32,
+}
+
+/// Current and temperature of a single power stage
+#[derive(Clone, Copy, Debug, Default, PartialEq, bincode::Decode, bincode::Encode)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PhaseTelemetry {
+    pub current: f32,
+    pub temperature: f32,
+}
+
+/// Most phases a single channel can have
+pub const MAX_PHASES: usize = 8;
+
+/// Operations shared by every PMBus multiphase controller
+///
+/// Implementors provide the paging and raw SMBus transactions, everything built from standard
+/// PMBus 1.3 commands comes for free and can be overridden where a controller differs
+pub trait PmbusDevice {
+    /// Selects the page (channel) that following commands apply to
+    fn select_page(&mut self, page: Page) -> Result<(), VrmError>;
+    /// Sends a command code with no data
+    fn send_byte(&mut self, cmd: u8) -> Result<(), VrmError>;
+    fn read_byte(&mut self, cmd: u8) -> Result<u8, VrmError>;
+    fn write_byte(&mut self, cmd: u8, val: u8) -> Result<(), VrmError>;
+    fn read_word(&mut self, cmd: u8) -> Result<u16, VrmError>;
+    fn write_word(&mut self, cmd: u8, val: u16) -> Result<(), VrmError>;
+    /// SMBus block read, the data (without the byte count) is placed in buf and returned
+    fn block_read<'b>(&mut self, cmd: u8, buf: &'b mut [u8]) -> Result<&'b [u8], VrmError>;
+    /// SMBus block write, the byte count is added in front of data
+    fn block_write(&mut self, cmd: u8, data: &[u8]) -> Result<(), VrmError>;
+    /// SMBus block write - block read process call, the response data is placed in buf
+    fn block_process_call<'b>(
+        &mut self,
+        cmd: u8,
+        data: &[u8],
+        buf: &'b mut [u8],
+    ) -> Result<&'b [u8], VrmError>;
+
+    /// Data format of VOUT-class commands on the paged channel
+    fn vout_mode(&mut self) -> Result<VoutMode, VrmError> {
+        VoutMode::from_bits(self.read_byte(Command::VoutMode.to_address())?)
+    }
+
+    /// Reads a VOUT-class register (VOUT_COMMAND, READ_VOUT, ...) in volts
+    fn read_vout_class(&mut self, cmd: u8) -> Result<f32, VrmError> {
+        let raw = self.read_word(cmd)?;
+        self.vout_mode()?.to_volts(raw)
+    }
+
+    /// Writes a VOUT-class register (VOUT_COMMAND, VOUT_MAX, ...) in volts
+    fn write_vout_class(&mut self, cmd: u8, volts: f32) -> Result<(), VrmError> {
+        let raw = self.vout_mode()?.from_volts(volts)?;
+        self.write_word(cmd, raw)
+    }
+
+    /// Reads a SLINEAR11 register (currents, temperatures, limits, ...)
+    fn read_linear11(&mut self, cmd: u8) -> Result<f32, VrmError> {
+        to_value(slinear11::to(self.read_word(cmd)?))
+    }
+
+    /// Writes a SLINEAR11 register (currents, temperatures, limits, ...)
+    fn write_linear11(&mut self, cmd: u8, val: f32) -> Result<(), VrmError> {
+        self.write_word(cmd, slinear11::from(val))
+    }
+
+    fn clear_faults(&mut self) -> Result<(), VrmError> {
+        self.send_byte(Command::ClearFaults.to_address())
+    }
+
+    // STATUS COMMANDS
+    /// Reads the summary status byte for the paged channel
+    fn status_byte(&mut self) -> Result<StatusByte, VrmError> {
+        Ok(StatusByte::from_bits(
+            self.read_byte(Command::StatusByte.to_address())?,
+        ))
+    }
+
+    /// Reads the full summary status word for the paged channel
+    fn status_word(&mut self) -> Result<StatusWord, VrmError> {
+        Ok(StatusWord::from_bits(
+            self.read_word(Command::StatusWord.to_address())?,
+        ))
+    }
+
+    /// Reads the output voltage status for the paged channel
+    fn status_vout(&mut self) -> Result<StatusVout, VrmError> {
+        Ok(StatusVout::from_bits(
+            self.read_byte(Command::StatusVout.to_address())?,
+        ))
+    }
+
+    /// Reads the output current status for the paged channel
+    fn status_iout(&mut self) -> Result<StatusIout, VrmError> {
+        Ok(StatusIout::from_bits(
+            self.read_byte(Command::StatusIout.to_address())?,
+        ))
+    }
+
+    /// Reads the input status (shared between channels)
+    fn status_input(&mut self) -> Result<StatusInput, VrmError> {
+        Ok(StatusInput::from_bits(
+            self.read_byte(Command::StatusInput.to_address())?,
+        ))
+    }
+
+    /// Reads the temperature status for the paged channel
+    fn status_temperature(&mut self) -> Result<StatusTemperature, VrmError> {
+        Ok(StatusTemperature::from_bits(
+            self.read_byte(Command::StatusTemperature.to_address())?,
+        ))
+    }
+
+    /// Reads the communication, logic and memory status
+    fn status_cml(&mut self) -> Result<StatusCml, VrmError> {
+        Ok(StatusCml::from_bits(
+            self.read_byte(Command::StatusCml.to_address())?,
+        ))
+    }
+
+    // IDENTIFICATION COMMANDS
+    /// Reads the manufacturer ID string into buf
+    fn mfr_id<'b>(&mut self, buf: &'b mut [u8]) -> Result<&'b [u8], VrmError> {
+        self.block_read(Command::MfrId.to_address(), buf)
+    }
+
+    /// Reads the manufacturer model string into buf
+    fn mfr_model<'b>(&mut self, buf: &'b mut [u8]) -> Result<&'b [u8], VrmError> {
+        self.block_read(Command::MfrModel.to_address(), buf)
+    }
+
+    /// Reads the manufacturer revision into buf
+    fn mfr_revision<'b>(&mut self, buf: &'b mut [u8]) -> Result<&'b [u8], VrmError> {
+        self.block_read(Command::MfrRevision.to_address(), buf)
+    }
+
+    // TELEMETRY
+    /// Reads output voltage, current and temperature of the paged channel
+    fn telemetry(&mut self) -> Result<Telemetry, VrmError> {
+        Ok(Telemetry {
+            vout: self.read_vout_class(Command::ReadVout.to_address())?,
+            iout: self.read_linear11(Command::ReadIout.to_address())?,
+            temperature: self.read_linear11(Command::ReadTemperature1.to_address())?,
+        })
+    }
+}
+
+/// Any PMBus 1.3 compliant controller, driven only through standard commands
+///
+/// Writes are checked against the safety envelope like those of the dedicated drivers, with
+/// VOUT_MODE read from the controller for every VOUT class write as nothing is cached
+pub struct GenericPmbus<I> {
+    address: u8,
+    i2c: I,
+    pec: bool,
+    // Page the controller was last switched to
+    page: Page,
+    envelope: Envelope,
+    rejected: Option<Rejection>,
+}
+
+impl<I: embedded_hal::i2c::I2c> GenericPmbus<I> {
+    /// Creates the driver, `pec` enables packet error checking on every transaction
+    pub fn new(i2c: I, address: u8, pec: bool) -> GenericPmbus<I> {
+        GenericPmbus {
+            address,
+            i2c,
+            pec,
+            page: Page::ChannelA,
+            envelope: Envelope::default(),
+            rejected: None,
+        }
+    }
+
+    /// Gives back the bus so a more specific driver can take over
+    pub fn release(self) -> I {
+        self.i2c
+    }
+
+    /// Limits every write is checked against
+    pub fn envelope(&self) -> &Envelope {
+        &self.envelope
+    }
+
+    pub fn set_envelope(&mut self, envelope: Envelope) {
+        self.envelope = envelope;
+    }
+
+    /// The last write the envelope refused, if any, clearing it
+    pub fn take_rejection(&mut self) -> Option<Rejection> {
+        self.rejected.take()
+    }
+
+    // Sends a write (command code first) once the envelope allows it, following PAGE
+    fn write(&mut self, data: &[u8]) -> Result<(), VrmError> {
+        let mode = match (data.first(), self.page) {
+            (Some(&cmd), Page::Both) if safety::needs_vout_mode(cmd) => {
+                // Each rail may use a different format
+                return Err(VrmError::InvalidData);
+            }
+            (Some(&cmd), _) if safety::needs_vout_mode(cmd) => Some(self.vout_mode()?),
+            _ => None,
+        };
+        if let Err(rejection) = self.envelope.check_write(self.page, data, mode) {
+            error!("Write Refused: {}", rejection);
+            self.rejected = Some(rejection);
+            return Err(VrmError::OutOfEnvelope(rejection.violation));
+        }
+        write_raw(&mut self.i2c, self.address, self.pec, data)?;
+        if let [cmd, page] = data {
+            if *cmd == Command::Page.to_address() {
+                self.page = match page {
+                    0x00 => Page::ChannelA,
+                    0x01 => Page::ChannelB,
+                    _ => Page::Both,
+                };
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<I: embedded_hal::i2c::I2c> PmbusDevice for GenericPmbus<I> {
+    fn select_page(&mut self, page: Page) -> Result<(), VrmError> {
+        self.write_byte(Command::Page.to_address(), page.to_bits())
+    }
+
+    fn send_byte(&mut self, cmd: u8) -> Result<(), VrmError> {
+        self.write(&[cmd])
+    }
+
+    fn read_byte(&mut self, cmd: u8) -> Result<u8, VrmError> {
+        let mut buf = [b'\0'; 1];
+        read_raw(&mut self.i2c, self.address, self.pec, cmd, &mut buf)?;
+        Ok(buf[0])
+    }
+
+    fn write_byte(&mut self, cmd: u8, val: u8) -> Result<(), VrmError> {
+        self.write(&[cmd, val])
+    }
+
+    fn read_word(&mut self, cmd: u8) -> Result<u16, VrmError> {
+        let mut buf = [b'\0'; 2];
+        read_raw(&mut self.i2c, self.address, self.pec, cmd, &mut buf)?;
+        Ok(to_u16(buf))
+    }
+
+    fn write_word(&mut self, cmd: u8, val: u16) -> Result<(), VrmError> {
+        let [low, high] = val.to_le_bytes();
+        self.write(&[cmd, low, high])
+    }
+
+    fn block_read<'b>(&mut self, cmd: u8, buf: &'b mut [u8]) -> Result<&'b [u8], VrmError> {
+        let count = block_read_raw(&mut self.i2c, self.address, self.pec, &[cmd], buf)?;
+        Ok(&buf[..count])
+    }
+
+    fn block_write(&mut self, cmd: u8, data: &[u8]) -> Result<(), VrmError> {
+        let mut buf = [b'\0'; MAX_TRANSACTION];
+        let len = block_message(cmd, data, &mut buf)?;
+        self.write(&buf[..len])
+    }
+
+    fn block_process_call<'b>(
+        &mut self,
+        cmd: u8,
+        data: &[u8],
+        buf: &'b mut [u8],
+    ) -> Result<&'b [u8], VrmError> {
+        let mut msg = [b'\0'; MAX_TRANSACTION];
+        let len = block_message(cmd, data, &mut msg)?;
+        let count = block_read_raw(&mut self.i2c, self.address, self.pec, &msg[..len], buf)?;
+        Ok(&buf[..count])
+    }
+}
+
+/// Controllers the firmware has a dedicated driver for
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ControllerKind {
+    Tps536c7,
+    Generic,
+}
+
+impl ControllerKind {
+    /// Picks a driver from the MFR_ID and MFR_MODEL strings reported by the controller. TI makes
+    /// other PMBus controllers too, so the model has to match as well
+    pub fn identify(id: &[u8], model: &[u8]) -> ControllerKind {
+        if id == b"TI" && model.starts_with(b"TPS536C7") {
+            ControllerKind::Tps536c7
+        } else {
+            ControllerKind::Generic
+        }
+    }
+
+    /// Reads MFR_ID and MFR_MODEL from dev and identifies it
+    pub fn read<D: PmbusDevice>(dev: &mut D) -> Result<ControllerKind, VrmError> {
+        let mut id = [b'\0'; MAX_BLOCK];
+        let mut model = [b'\0'; MAX_BLOCK];
+        let id = dev.mfr_id(&mut id)?;
+        let model = dev.mfr_model(&mut model)?;
+        Ok(ControllerKind::identify(id, model))
+    }
+}
+
+/// A controller whose driver was chosen at runtime from its MFR_ID and MFR_MODEL
+pub enum Controller<I> {
+    Tps536c7(TPSC536C7<I>),
+    Generic(GenericPmbus<I>),
+}
+
+impl<I: embedded_hal::i2c::I2c> Controller<I> {
+    /// Identifies the controller through the generic driver and switches to a dedicated one if
+    /// known
+    pub fn probe(i2c: I, address: u8, pec: bool) -> Controller<I> {
+        let mut generic = GenericPmbus::new(i2c, address, pec);
+        let kind = match ControllerKind::read(&mut generic) {
+            Ok(kind) => kind,
+            Err(err) => {
+                error!("Controller Probe Failed: {}", err);
+                ControllerKind::Generic
+            }
+        };
+        info!("Controller Probed: {}", kind);
+        match kind {
+            ControllerKind::Tps536c7 => {
+                Controller::Tps536c7(TPSC536C7::new(generic.release(), address, pec))
+            }
+            ControllerKind::Generic => Controller::Generic(generic),
+        }
+    }
+}
+
+/// Forwards a trait method to whichever driver was probed
+macro_rules! delegate {
+    ($self:ident, $dev:ident => $call:expr) => {
+        match $self {
+            Controller::Tps536c7($dev) => $call,
+            Controller::Generic($dev) => $call,
+        }
+    };
+}
+
+impl<I: embedded_hal::i2c::I2c> PmbusDevice for Controller<I> {
+    fn select_page(&mut self, page: Page) -> Result<(), VrmError> {
+        delegate!(self, dev => dev.select_page(page))
+    }
+    fn send_byte(&mut self, cmd: u8) -> Result<(), VrmError> {
+        delegate!(self, dev => dev.send_byte(cmd))
+    }
+    fn read_byte(&mut self, cmd: u8) -> Result<u8, VrmError> {
+        delegate!(self, dev => dev.read_byte(cmd))
+    }
+    fn write_byte(&mut self, cmd: u8, val: u8) -> Result<(), VrmError> {
+        delegate!(self, dev => dev.write_byte(cmd, val))
+    }
+    fn read_word(&mut self, cmd: u8) -> Result<u16, VrmError> {
+        delegate!(self, dev => dev.read_word(cmd))
+    }
+    fn write_word(&mut self, cmd: u8, val: u16) -> Result<(), VrmError> {
+        delegate!(self, dev => dev.write_word(cmd, val))
+    }
+    fn block_read<'b>(&mut self, cmd: u8, buf: &'b mut [u8]) -> Result<&'b [u8], VrmError> {
+        delegate!(self, dev => dev.block_read(cmd, buf))
+    }
+    fn block_write(&mut self, cmd: u8, data: &[u8]) -> Result<(), VrmError> {
+        delegate!(self, dev => dev.block_write(cmd, data))
+    }
+    fn block_process_call<'b>(
+        &mut self,
+        cmd: u8,
+        data: &[u8],
+        buf: &'b mut [u8],
+    ) -> Result<&'b [u8], VrmError> {
+        delegate!(self, dev => dev.block_process_call(cmd, data, buf))
+    }
+    fn vout_mode(&mut self) -> Result<VoutMode, VrmError> {
+        delegate!(self, dev => dev.vout_mode())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Command {
+    Page,
+    Operation,
+    OnOffConfig,
+    ClearFaults,
+    Phase,
+    StoreDefaultAll,
+    RestoreDefaultAll,
+    StoreUserAll,
+    RestoreUserAll,
+    VoutMode,
+    VOUTCommand,
+    VOUTMax,
+    VOUTMarginHigh,
+    VOUTMarginLow,
+    VOUTTransitionRate,
+    VOUTDroop,
+    VOUTMin,
+    FrequencySwitch,
+    VinOn,
+    VinOff,
+    IoutOCFaultLimit,
+    IoutOCWarnLimit,
+    OTFaultLimit,
+    OTWarnLimit,
+    VinOVFaultLimit,
+    VinUVFaultLimit,
+    TonDelay,
+    TonRise,
+    ToffDelay,
+    ToffFall,
+    StatusByte,
+    StatusWord,
+    StatusVout,
+    StatusIout,
+    StatusInput,
+    StatusTemperature,
+    StatusCml,
+    StatusMfrSpecific,
+    ReadVin,
+    ReadIin,
+    ReadIout,
+    ReadVout,
+    ReadTemperature1,
+    ReadTemperature2,
+    ReadPout,
+    ReadPin,
+    MfrId,
+    MfrModel,
+    MfrRevision,
+    /// MFR_SPECIFIC_00 + offset, built with Command::mfr_specific
+    MfrSpecific(MfrOffset),
+    StatusAll,
+    StatusExtended,
+}
+
+/// Offset of a MFR_SPECIFIC register, only ever within 0xD0 - 0xFD
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MfrOffset(u8);
+
+impl MfrOffset {
+    pub const MAX: u8 = 0x2D;
+
+    pub fn get(self) -> u8 {
+        self.0
+    }
+}
+
+impl Command {
+    /// MFR_SPECIFIC_00 + offset, offsets past MfrOffset::MAX are not MFR_SPECIFIC registers
+    pub fn mfr_specific(offset: u8) -> Result<Command, VrmError> {
+        if offset > MfrOffset::MAX {
+            return Err(VrmError::UnsupportedCommand);
+        }
+        Ok(Command::MfrSpecific(MfrOffset(offset)))
+    }
+
+    pub fn to_address(self) -> u8 {
+        match self {
+            Command::Page => 0x00,
+            Command::Operation => 0x01,
+            Command::OnOffConfig => 0x02,
+            Command::ClearFaults => 0x03,
+            Command::Phase => 0x04,
+            Command::StoreDefaultAll => 0x11,
+            Command::RestoreDefaultAll => 0x12,
+            Command::StoreUserAll => 0x15,
+            Command::RestoreUserAll => 0x16,
+            Command::VoutMode => 0x20,
+            Command::VOUTCommand => 0x21,
+            Command::VOUTMax => 0x24,
+            Command::VOUTMarginHigh => 0x25,
+            Command::VOUTMarginLow => 0x26,
+            Command::VOUTTransitionRate => 0x27,
+            Command::VOUTDroop => 0x28,
+            Command::VOUTMin => 0x2B,
+            Command::FrequencySwitch => 0x33,
+            Command::VinOn => 0x35,
+            Command::VinOff => 0x36,
+            Command::IoutOCFaultLimit => 0x46,
+            Command::IoutOCWarnLimit => 0x4A,
+            Command::OTFaultLimit => 0x4F,
+            Command::OTWarnLimit => 0x51,
+            Command::VinOVFaultLimit => 0x55,
+            Command::VinUVFaultLimit => 0x59,
+            Command::TonDelay => 0x60,
+            Command::TonRise => 0x61,
+            Command::ToffDelay => 0x64,
+            Command::ToffFall => 0x65,
+            Command::StatusByte => 0x78,
+            Command::StatusWord => 0x79,
+            Command::StatusVout => 0x7A,
+            Command::StatusIout => 0x7B,
+            Command::StatusInput => 0x7C,
+            Command::StatusTemperature => 0x7D,
+            Command::StatusCml => 0x7E,
+            Command::StatusMfrSpecific => 0x80,
+            Command::ReadVin => 0x88,
+            Command::ReadIin => 0x89,
+            Command::ReadVout => 0x8B,
+            Command::ReadIout => 0x8C,
+            Command::ReadTemperature1 => 0x8D,
+            Command::ReadTemperature2 => 0x8E,
+            Command::ReadPout => 0x96,
+            Command::ReadPin => 0x97,
+            Command::MfrId => 0x99,
+            Command::MfrModel => 0x9A,
+            Command::MfrRevision => 0x9B,
+            Command::MfrSpecific(offset) => 0xD0 + offset.0,
+            Command::StatusAll => 0xDB,
+            Command::StatusExtended => 0xDD,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, bincode::Decode, bincode::Encode)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Page {
+    ChannelA,
+    ChannelB,
+    Both,
+}
+
+impl Page {
+    /// Page of channel 0 (the core) or 1 (the memory), anything past 1 is the memory too
+    pub fn from_channel(channel: usize) -> Page {
+        match channel {
+            0 => Page::ChannelA,
+            _ => Page::ChannelB,
+        }
+    }
+
+    pub fn to_bits(self) -> u8 {
+        match self {
+            Page::ChannelA => 0x00,
+            Page::ChannelB => 0x01,
+            Page::Both => 0xFF,
+        }
+    }
+}
+
+/// Data format used by every VOUT-class command, as configured by VOUT_MODE (0x20)
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum VoutMode {
+    /// ULINEAR16 mantissa with the given (signed) exponent
+    Linear(i8),
+    /// VID code looked up in the given table
+    Vid(VidTable),
+    /// DIRECT format, requires COEFFICIENTS and is not supported
+    Direct,
+    /// IEEE half precision float, not supported
+    Ieee,
+}
+
+impl VoutMode {
+    pub fn from_bits(bits: u8) -> Result<VoutMode, VrmError> {
+        let param = bits & 0x1F;
+        match bits >> 5 {
+            0b000 => Ok(VoutMode::Linear(((param << 3) as i8) >> 3)), // sign extend 5 bits
+            0b001 => Ok(VoutMode::Vid(VidTable::from_code(param)?)),
+            0b010 => Ok(VoutMode::Direct),
+            0b011 => Ok(VoutMode::Ieee),
+            _ => Err(VrmError::InvalidData),
+        }
+    }
+
+    /// Converts a raw VOUT-class register value to volts
+    pub fn to_volts(&self, raw: u16) -> Result<f32, VrmError> {
+        match self {
+            VoutMode::Linear(exp) => to_value(scale(raw as f32, *exp)),
+            VoutMode::Vid(table) => Ok(table.to_volts(raw as u8)),
+            VoutMode::Direct | VoutMode::Ieee => Err(VrmError::UnsupportedCommand),
+        }
+    }
+
+    /// Converts volts to a raw VOUT-class register value
+    pub fn from_volts(&self, volts: f32) -> Result<u16, VrmError> {
+        if !volts.is_finite() || volts < 0. {
+            return Err(VrmError::InvalidData);
+        }
+        match self {
+            VoutMode::Linear(exp) => {
+                let raw = scale(volts, -*exp) + 0.5;
+                if raw > u16::MAX as f32 {
+                    return Err(VrmError::InvalidData);
+                }
+                Ok(raw as u16)
+            }
+            VoutMode::Vid(table) => Ok(table.from_volts(volts) as u16),
+            VoutMode::Direct | VoutMode::Ieee => Err(VrmError::UnsupportedCommand),
+        }
+    }
+}
+
+/// VID tables supported by the TPS536C7 in VID mode
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum VidTable {
+    /// VR12.0 / VR13 5 mV steps starting at 0.25 V
+    Vr12,
+    /// VR13 10 mV steps starting at 0.5 V
+    Vr13,
+    /// VR14 5 mV steps starting at 0.25 V
+    Vr14,
+    /// IMVP9 5 mV steps starting at 0.2 V
+    Imvp9,
+}
+
+impl VidTable {
+    /// Maps the VOUT_MODE parameter bits to a VID table
+    pub fn from_code(code: u8) -> Result<VidTable, VrmError> {
+        match code {
+            0x01 => Ok(VidTable::Vr12),
+            0x02 => Ok(VidTable::Vr13),
+            0x04 => Ok(VidTable::Vr14),
+            0x07 => Ok(VidTable::Imvp9),
+            _ => Err(VrmError::InvalidData),
+        }
+    }
+
+    /// Voltage of VID code 1 and the step between codes
+    fn base_step(&self) -> (f32, f32) {
+        match self {
+            VidTable::Vr12 => (0.25, 0.005),
+            VidTable::Vr13 => (0.5, 0.01),
+            VidTable::Vr14 => (0.25, 0.005),
+            VidTable::Imvp9 => (0.2, 0.005),
+        }
+    }
+
+    pub fn to_volts(&self, code: u8) -> f32 {
+        let (base, step) = self.base_step();
+        match code {
+            0 => 0., // VID code 0 turns the output off
+            _ => base + step * (code - 1) as f32,
+        }
+    }
+
+    pub fn from_volts(&self, volts: f32) -> u8 {
+        let (base, step) = self.base_step();
+        if volts < base {
+            return 0;
+        }
+        let code = (volts - base) / step + 1.5;
+        if code > u8::MAX as f32 {
+            u8::MAX
+        } else {
+            code as u8
+        }
+    }
+}
+
+/// What to do with faults while margined, see Operation
+#[derive(Clone, Copy, Debug, PartialEq, bincode::Decode, bincode::Encode)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum MarginFault {
+    /// Faults are handled as they would be at the nominal voltage
+    Act,
+    /// Faults the margin causes are ignored
+    Ignore,
+}
+
+/// State of a channel set through OPERATION (0x01)
+#[derive(Clone, Copy, Debug, PartialEq, bincode::Decode, bincode::Encode)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Operation {
+    /// On at VOUT_COMMAND
+    On,
+    /// Off, ramping down as set by TOFF_DELAY and TOFF_FALL
+    SoftOff,
+    /// Off, switching stops at once
+    ImmediateOff,
+    /// On at VOUT_MARGIN_HIGH
+    MarginHigh(MarginFault),
+    /// On at VOUT_MARGIN_LOW
+    MarginLow(MarginFault),
+}
+
+impl Operation {
+    pub fn to_bits(self) -> u8 {
+        let fault = |fault| match fault {
+            MarginFault::Ignore => 0x04,
+            MarginFault::Act => 0x08,
+        };
+        match self {
+            Operation::On => 0x80,
+            Operation::SoftOff => 0x40,
+            Operation::ImmediateOff => 0x00,
+            Operation::MarginHigh(action) => 0xA0 | fault(action),
+            Operation::MarginLow(action) => 0x90 | fault(action),
+        }
+    }
+
+    /// Decodes OPERATION, the bits the PMBus spec leaves reserved are ignored
+    pub fn from_bits(bits: u8) -> Result<Operation, VrmError> {
+        let fault = match bits & 0x0C {
+            0x08 => MarginFault::Act,
+            _ => MarginFault::Ignore,
+        };
+        match (bits & 0x80 != 0, bits & 0x30) {
+            (false, _) if bits & 0x40 != 0 => Ok(Operation::SoftOff),
+            (false, _) => Ok(Operation::ImmediateOff),
+            (true, 0x00) => Ok(Operation::On),
+            (true, 0x10) => Ok(Operation::MarginLow(fault)),
+            (true, 0x20) => Ok(Operation::MarginHigh(fault)),
+            _ => Err(VrmError::InvalidData),
+        }
+    }
+
+    /// True for the states with the output switched on
+    pub fn is_on(self) -> bool {
+        self.to_bits() & 0x80 != 0
+    }
+}
+
+/// Most data bytes an SMBus block transfer can carry
+pub const MAX_BLOCK: usize = 32;
+
+/// Longest transaction (command + byte count + 32 data bytes + PEC) the driver will send or receive
+pub const MAX_TRANSACTION: usize = MAX_BLOCK + 3;
+
+/// SMBus packet error code: CRC-8 with polynomial x^8 + x^2 + x + 1, continuing from crc
+pub fn pec(crc: u8, data: &[u8]) -> u8 {
+    let mut crc = crc;
+    for byte in data {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+/// Writes data (command code first) to the controller, appending the PEC byte if enabled
+pub fn write_raw<I: embedded_hal::i2c::I2c>(
+    i2c: &mut I,
+    address: u8,
+    use_pec: bool,
+    data: &[u8],
+) -> Result<(), VrmError> {
+    let mut buf = [b'\0'; MAX_TRANSACTION];
+    let msg = write_message(address, use_pec, data, &mut buf)?;
+    i2c.write(address, msg)
+        .map_err(|err| VrmError::from(err.kind()))
+}
+
+/// Reads the response to cmd into buf, reading and checking the PEC byte if enabled
+pub fn read_raw<I: embedded_hal::i2c::I2c>(
+    i2c: &mut I,
+    address: u8,
+    use_pec: bool,
+    cmd: u8,
+    buf: &mut [u8],
+) -> Result<(), VrmError> {
+    let mut resp = [b'\0'; MAX_TRANSACTION];
+    let resp = read_response(use_pec, buf.len(), &mut resp)?;
+    i2c.write_read(address, &[cmd], resp)
+        .map_err(|err| VrmError::from(err.kind()))?;
+    check_read(address, use_pec, cmd, resp, buf)
+}
+
+/// Builds the command code, byte count and data of a block write into buf, returning its length
+pub fn block_message(cmd: u8, data: &[u8], buf: &mut [u8]) -> Result<usize, VrmError> {
+    if data.len() > MAX_BLOCK || buf.len() < data.len() + 2 {
+        return Err(VrmError::InvalidData);
+    }
+    buf[0] = cmd;
+    buf[1] = data.len() as u8;
+    buf[2..2 + data.len()].copy_from_slice(data);
+    Ok(data.len() + 2)
+}
+
+/// Writes msg then reads a byte count prefixed block response, checking the PEC byte if enabled
+///
+/// Covers both a block read (msg is just the command code) and a block process call. The data is
+/// copied to the start of buf and its length returned, a count larger than buf is an error
+pub fn block_read_raw<I: embedded_hal::i2c::I2c>(
+    i2c: &mut I,
+    address: u8,
+    use_pec: bool,
+    msg: &[u8],
+    buf: &mut [u8],
+) -> Result<usize, VrmError> {
+    // The count is unknown up front, so read the longest block and ignore what trails it
+    let mut resp = [b'\0'; MAX_BLOCK + 2];
+    i2c.write_read(address, msg, &mut resp)
+        .map_err(|err| VrmError::from(err.kind()))?;
+    check_block(address, use_pec, msg, &resp, buf)
+}
+
+/// Copies data into buf with the PEC byte appended if enabled, returning the bytes to write
+///
+/// The framing is split from the bus access so the blocking and async drivers share it
+pub fn write_message<'b>(
+    address: u8,
+    use_pec: bool,
+    data: &[u8],
+    buf: &'b mut [u8; MAX_TRANSACTION],
+) -> Result<&'b [u8], VrmError> {
+    let len = if use_pec { data.len() + 1 } else { data.len() };
+    if len > MAX_TRANSACTION {
+        return Err(VrmError::InvalidData);
+    }
+    buf[..data.len()].copy_from_slice(data);
+    if use_pec {
+        buf[data.len()] = pec(pec(0, &[address << 1]), data);
+    }
+    Ok(&buf[..len])
+}
+
+/// Slice of resp a read of len data bytes should fill, one longer if a PEC byte follows
+pub fn read_response(
+    use_pec: bool,
+    len: usize,
+    resp: &mut [u8; MAX_TRANSACTION],
+) -> Result<&mut [u8], VrmError> {
+    let len = if use_pec { len + 1 } else { len };
+    if len > MAX_TRANSACTION {
+        return Err(VrmError::InvalidData);
+    }
+    Ok(&mut resp[..len])
+}
+
+/// Checks the PEC byte of a read response if enabled, then copies the data into buf
+pub fn check_read(
+    address: u8,
+    use_pec: bool,
+    cmd: u8,
+    resp: &[u8],
+    buf: &mut [u8],
+) -> Result<(), VrmError> {
+    let (data, received) = resp.split_at(buf.len());
+    if use_pec {
+        let expected = pec(pec(0, &[address << 1, cmd, address << 1 | 1]), data);
+        if received[0] != expected {
+            return Err(VrmError::PecMismatch);
+        }
+    }
+    buf.copy_from_slice(data);
+    Ok(())
+}
+
+/// Checks the count and PEC byte of a block response to msg, copying the data into buf
+pub fn check_block(
+    address: u8,
+    use_pec: bool,
+    msg: &[u8],
+    resp: &[u8; MAX_BLOCK + 2],
+    buf: &mut [u8],
+) -> Result<usize, VrmError> {
+    let count = resp[0] as usize;
+    if count > MAX_BLOCK || count > buf.len() {
+        return Err(VrmError::InvalidData);
+    }
+    if use_pec {
+        let crc = pec(pec(0, &[address << 1]), msg);
+        let expected = pec(pec(crc, &[address << 1 | 1]), &resp[..1 + count]);
+        if resp[1 + count] != expected {
+            return Err(VrmError::PecMismatch);
+        }
+    }
+    buf[..count].copy_from_slice(&resp[1..1 + count]);
+    Ok(count)
+}
+
+pub fn to_u16(val: [u8; 2]) -> u16 {
+    (val[1] as u16) << 8 | val[0] as u16
+}
+
+/// Rejects values that cannot have come from a valid register (NaN, inf)
+pub fn to_value(val: f32) -> Result<f32, VrmError> {
+    if val.is_finite() {
+        Ok(val)
+    } else {
+        Err(VrmError::InvalidData)
+    }
+}
+
+/// Multiplies val by 2^exp without needing std for powi
+fn scale(val: f32, exp: i8) -> f32 {
+    if exp >= 0 {
+        val * (1u32 << exp) as f32
+    } else {
+        val / (1u32 << -exp) as f32
+    }
+}
@@ -0,0 +1,258 @@
+// Named profiles, complete sets of setpoints to switch between in one go (eg "stock", "daily
+// OC" and "benchmark")
+//
+// The profiles are kept in flash with the rest of the settings. Loading one writes its setpoints
+// through ramp::Ramp::write_setpoint, the same path an edit accepted on the front panel takes.
+
+use pmbus_types_rs::slinear11;
+
+use crate::navigation::Device;
+use crate::pmbus::{Command, Page, PmbusDevice, VrmError};
+use crate::protocol::{Request, RequestError, Response};
+use crate::ramp::{self, Ramp};
+use crate::vrm_controller::TPSC536C7;
+
+/// Number of profile slots
+pub const PROFILES: usize = 4;
+
+/// Longest profile name in bytes
+pub const NAME_LEN: usize = 12;
+
+/// Printable ASCII name of a profile
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, bincode::Encode, bincode::Decode)]
+pub struct Name {
+    bytes: [u8; NAME_LEN],
+    len: u8,
+}
+
+impl Name {
+    /// None if name is empty, longer than NAME_LEN or not printable ASCII
+    pub fn new(name: &str) -> Option<Name> {
+        let valid = (1..=NAME_LEN).contains(&name.len())
+            && name
+                .bytes()
+                .all(|byte| byte.is_ascii_graphic() || byte == b' ');
+        if !valid {
+            return None;
+        }
+        let mut bytes = [0u8; NAME_LEN];
+        bytes[..name.len()].copy_from_slice(name.as_bytes());
+        Some(Name {
+            bytes,
+            len: name.len() as u8,
+        })
+    }
+
+    /// Name given to a profile saved from the front panel, "Profile 1" for slot 0
+    pub fn for_slot(slot: usize) -> Name {
+        let mut name = Name::new("Profile 0").unwrap();
+        name.bytes[8] = b'1' + (slot % 9) as u8;
+        name
+    }
+
+    pub fn as_str(&self) -> &str {
+        let len = (self.len as usize).min(NAME_LEN);
+        core::str::from_utf8(&self.bytes[..len]).unwrap_or("?")
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for Name {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(f, "{=str}", self.as_str());
+    }
+}
+
+/// Setpoints and limits a profile holds for one channel
+#[derive(Clone, Copy, Debug, Default, PartialEq, bincode::Encode, bincode::Decode)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct RailProfile {
+    /// VOUT_COMMAND in volts
+    pub voltage: f32,
+    /// IOUT_OC_FAULT_LIMIT in amps
+    pub current_limit: f32,
+    /// Rate voltage setpoint changes are ramped at in mV/ms, see ramp::Ramp
+    pub ramp_rate: f32,
+    /// OT_FAULT_LIMIT in °C
+    pub ot_limit: f32,
+}
+
+/// A named profile
+#[derive(Clone, Copy, Debug, Default, PartialEq, bincode::Encode, bincode::Decode)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Profile {
+    pub name: Name,
+    pub core: RailProfile,
+    pub mem: RailProfile,
+}
+
+impl Profile {
+    /// Reads the current setpoints and limits of both channels from the controller, and the
+    /// ramp rates from ramp
+    pub fn read<I: embedded_hal::i2c::I2c>(
+        controller: &mut TPSC536C7<I>,
+        ramp: &Ramp,
+        name: Name,
+    ) -> Result<Profile, VrmError> {
+        let mut read = |channel, page| -> Result<RailProfile, VrmError> {
+            let c = controller.page(page)?;
+            Ok(RailProfile {
+                voltage: c.vout_command().read()?,
+                current_limit: c.iout_oc_fault_limit().read()?,
+                ramp_rate: ramp.rate(channel),
+                ot_limit: c.ot_fault_limit().read()?,
+            })
+        };
+        Ok(Profile {
+            name,
+            core: read(0, Page::ChannelA)?,
+            mem: read(1, Page::ChannelB)?,
+        })
+    }
+
+    /// Writes the profile to the controller. Every value is checked first, so a profile with
+    /// any value the controller would refuse leaves both channels as they were
+    ///
+    /// The limits and ramp rate go first, so the new voltage is ramped to at the profile's rate
+    /// and under its limits
+    pub fn apply<I: embedded_hal::i2c::I2c>(
+        &self,
+        controller: &mut TPSC536C7<I>,
+        ramp: &mut Ramp,
+        dev: &mut Device,
+    ) -> Result<(), RequestError> {
+        let rails = [
+            (0, Page::ChannelA, &self.core),
+            (1, Page::ChannelB, &self.mem),
+        ];
+        for (_, page, rail) in rails {
+            ramp::check_rate(rail.ramp_rate)?;
+            rail.check(controller, page).map_err(RequestError::Vrm)?;
+        }
+        for (channel, page, rail) in rails {
+            ramp.set_rate(channel as usize, rail.ramp_rate)?;
+            let c = controller.page(page).map_err(RequestError::Vrm)?;
+            c.ot_fault_limit()
+                .write(rail.ot_limit)
+                .map_err(RequestError::Vrm)?;
+            for (row, val) in [(1, rail.current_limit), (0, rail.voltage)] {
+                ramp.write_setpoint(controller, (channel, row), val)
+                    .map_err(RequestError::Vrm)?;
+                dev.store_value((channel, row), val);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl RailProfile {
+    // Checks the limits and voltage against the envelope of page, as the words that would be
+    // written
+    fn check<I: embedded_hal::i2c::I2c>(
+        &self,
+        controller: &mut TPSC536C7<I>,
+        page: Page,
+    ) -> Result<(), VrmError> {
+        controller.select_page(page)?;
+        let vout = controller.vout_mode()?.from_volts(self.voltage)?;
+        for (cmd, raw) in [
+            (Command::OTFaultLimit, slinear11::from(self.ot_limit)),
+            (
+                Command::IoutOCFaultLimit,
+                slinear11::from(self.current_limit),
+            ),
+            (Command::VOUTCommand, vout),
+        ] {
+            let [lo, hi] = raw.to_le_bytes();
+            controller.check_write(&[cmd.to_address(), lo, hi])?;
+        }
+        Ok(())
+    }
+}
+
+/// The profile slots
+#[derive(Clone, Copy, Debug, Default, PartialEq, bincode::Encode, bincode::Decode)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Profiles {
+    slots: [Option<Profile>; PROFILES],
+}
+
+impl Profiles {
+    pub fn get(&self, slot: usize) -> Option<&Profile> {
+        self.slots.get(slot)?.as_ref()
+    }
+
+    /// Name of the profile in each slot, None for an empty slot
+    pub fn names(&self) -> [Option<Name>; PROFILES] {
+        self.slots.map(|slot| slot.map(|profile| profile.name))
+    }
+
+    /// Puts profile in slot, replacing what was there
+    pub fn set(&mut self, slot: usize, profile: Profile) -> Result<(), RequestError> {
+        *self.slots.get_mut(slot).ok_or(RequestError::NoProfile)? = Some(profile);
+        Ok(())
+    }
+
+    /// Empties slot, returning the profile it held
+    pub fn delete(&mut self, slot: usize) -> Result<Profile, RequestError> {
+        self.slots
+            .get_mut(slot)
+            .and_then(Option::take)
+            .ok_or(RequestError::NoProfile)
+    }
+
+    /// Writes the profile in slot to the controller
+    pub fn load<I: embedded_hal::i2c::I2c>(
+        &self,
+        slot: usize,
+        controller: &mut TPSC536C7<I>,
+        ramp: &mut Ramp,
+        dev: &mut Device,
+    ) -> Result<(), RequestError> {
+        let profile = self.get(slot).ok_or(RequestError::NoProfile)?;
+        info!("Loading Profile {}: {}", slot, profile.name);
+        profile.apply(controller, ramp, dev)
+    }
+
+    /// Stores the controller's current setpoints in slot, named name or, if that is None, keeping
+    /// the name of the profile it replaces
+    pub fn save<I: embedded_hal::i2c::I2c>(
+        &mut self,
+        slot: usize,
+        name: Option<Name>,
+        controller: &mut TPSC536C7<I>,
+        ramp: &Ramp,
+    ) -> Result<(), RequestError> {
+        let name = name
+            .or_else(|| self.get(slot).map(|profile| profile.name))
+            .unwrap_or_else(|| Name::for_slot(slot));
+        let profile = Profile::read(controller, ramp, name).map_err(RequestError::Vrm)?;
+        self.set(slot, profile)
+    }
+
+    /// Handles the profile requests, None for any other request
+    pub fn handle<I: embedded_hal::i2c::I2c>(
+        &mut self,
+        request: &Request,
+        controller: &mut TPSC536C7<I>,
+        ramp: &mut Ramp,
+        dev: &mut Device,
+    ) -> Option<Response> {
+        let result = match *request {
+            Request::ListProfiles => Ok(Response::Profiles(self.names())),
+            Request::GetProfile(slot) => self
+                .get(slot as usize)
+                .map(|profile| Response::Profile(*profile))
+                .ok_or(RequestError::NoProfile),
+            Request::LoadProfile(slot) => self
+                .load(slot as usize, controller, ramp, dev)
+                .map(|()| Response::Ack),
+            Request::SaveProfile { slot, name } => self
+                .save(slot as usize, Some(name), controller, ramp)
+                .map(|()| Response::Ack),
+            Request::DeleteProfile(slot) => self.delete(slot as usize).map(|_| Response::Ack),
+            _ => return None,
+        };
+        Some(result.unwrap_or_else(Response::Error))
+    }
+}
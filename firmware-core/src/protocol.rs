@@ -0,0 +1,459 @@
+// Framed protocol spoken over the USB CDC serial port
+//
+// Every frame is COBS encoded and ends with a 0x00 delimiter, so a reader that starts mid frame
+// or loses bytes resynchronises at the next delimiter. Decoded, a frame is
+//
+//     version: u8 | seq: u16 | message | crc: u16 (little endian)
+//
+// with the header and message encoded with bincode (standard config) and the CRC-16/CCITT-FALSE
+// covering everything before it. Replies carry the sequence number of the request they answer,
+// telemetry frames use a separate counter of the firmware's own.
+
+use bincode::error::{DecodeError, EncodeError};
+
+use crate::margin::{Level, MarginStatus, SweepConfig, SweepStep};
+use crate::navigation::Device;
+use crate::pmbus::{VrmError, MAX_BLOCK};
+use crate::profile::{Name, Profile, PROFILES};
+use crate::ramp::RampEvent;
+use crate::sequence::State;
+use crate::vrm_status::StatusWord;
+
+/// Version of the message layout, bumped whenever a message changes incompatibly
+pub const VERSION: u8 = 1;
+
+/// Largest encoded frame (delimiter included) either side sends
+pub const MAX_FRAME: usize = 512;
+
+/// Most bytes a single register read returns
+pub const MAX_READ: usize = MAX_BLOCK;
+
+/// Confirmation byte a host has to send with an NVM command for it to be carried out
+pub const NVM_CONFIRM: u8 = 0xA5;
+
+/// Frame delimiter, COBS guarantees it never shows up inside a frame
+const DELIMITER: u8 = 0x00;
+/// Encoded size of the CRC at the end of a frame
+const CRC_LEN: usize = 2;
+
+/// A request from the host
+#[derive(Clone, Copy, Debug, PartialEq, bincode::Encode, bincode::BorrowDecode)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Request<'a> {
+    /// Checks the link, answered with Response::Pong
+    Ping,
+    /// Raw PMBus write (command code first) to channel A (0) or B (1)
+    Write {
+        page: u8,
+        data: &'a [u8],
+    },
+    /// Store / restore of the controller NVM, only carried out when confirm is NVM_CONFIRM
+    Nvm {
+        cmd: u8,
+        confirm: u8,
+    },
+    /// Reads len bytes of register cmd from channel A (0) or B (1), answered with
+    /// Response::Register
+    Read {
+        page: u8,
+        cmd: u8,
+        len: u8,
+        format: Format,
+    },
+    /// Starts (or reconfigures) streaming Message::Sample frames
+    StartStream(StreamConfig),
+    StopStream,
+    /// Names of the profile slots, answered with Response::Profiles
+    ListProfiles,
+    /// Profile in a slot, answered with Response::Profile
+    GetProfile(u8),
+    /// Writes the profile in a slot to the controller
+    LoadProfile(u8),
+    /// Stores the controller's current setpoints in a slot, replacing its profile
+    SaveProfile {
+        slot: u8,
+        name: Name,
+    },
+    DeleteProfile(u8),
+    /// Rate in mV/ms voltage setpoint changes of channel A (0) or B (1) are ramped at
+    SetRampRate {
+        page: u8,
+        rate: f32,
+    },
+    /// Brings both rails up (true) or down (false) in sequence
+    SetOutput(bool),
+    /// Where the power sequence is, answered with Response::Output
+    GetOutput,
+    /// Moves channel A (0) or B (1) to a margin or back to nominal, only while the output is on
+    SetMargin {
+        page: u8,
+        level: Level,
+    },
+    /// Margin of channel A (0) or B (1) in percent, MIN_PERCENT to MAX_PERCENT of margin
+    SetMarginPercent {
+        page: u8,
+        percent: f32,
+    },
+    StartMarginSweep(SweepConfig),
+    StopMarginSweep,
+    /// Margin settings and sweep progress, answered with Response::Margin
+    GetMargin,
+    /// Telemetry of a step of the last margin sweep, answered with Response::SweepStep
+    GetSweepStep(u8),
+    /// The last ramp that aborted or failed, cleared once read, answered with
+    /// Response::RampAbort
+    GetRampAbort,
+}
+
+/// What a telemetry stream samples and how often
+#[derive(Clone, Copy, Debug, Default, PartialEq, bincode::Encode, bincode::Decode)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct StreamConfig {
+    /// Samples per second, MIN_RATE_HZ to MAX_RATE_HZ
+    pub rate_hz: u16,
+    /// Fields to read, a combination of the StreamConfig field bits
+    pub fields: u8,
+    pub core: bool,
+    pub mem: bool,
+}
+
+impl StreamConfig {
+    pub const MIN_RATE_HZ: u16 = 10;
+    pub const MAX_RATE_HZ: u16 = 1000;
+
+    // Field bits
+    pub const VOUT: u8 = 1 << 0;
+    pub const IOUT: u8 = 1 << 1;
+    pub const TEMPERATURE: u8 = 1 << 2;
+    /// VOUT_COMMAND and IOUT_OC_FAULT_LIMIT
+    pub const SETPOINTS: u8 = 1 << 3;
+    pub const STATUS: u8 = 1 << 4;
+    pub const ALL: u8 = 0x1F;
+
+    pub fn has(&self, field: u8) -> bool {
+        self.fields & field != 0
+    }
+
+    /// Time between samples in microseconds, None if the rate is out of range
+    pub fn period_us(&self) -> Option<u32> {
+        (Self::MIN_RATE_HZ..=Self::MAX_RATE_HZ)
+            .contains(&self.rate_hz)
+            .then(|| 1_000_000 / self.rate_hz as u32)
+    }
+}
+
+/// One streamed sample, fields that were not requested (or whose read failed) are None
+#[derive(Clone, Copy, Debug, Default, PartialEq, bincode::Encode, bincode::Decode)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Sample {
+    /// Microseconds on the firmware's monotonic clock when the sample was due, wraps at u32
+    pub tick_us: u32,
+    /// Samples dropped since the stream started, because reading fell behind or USB was full
+    pub overruns: u32,
+    pub core: Option<ChannelSample>,
+    pub mem: Option<ChannelSample>,
+}
+
+/// The values of one channel in a Sample
+#[derive(Clone, Copy, Debug, Default, PartialEq, bincode::Encode, bincode::Decode)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ChannelSample {
+    pub vout: Option<f32>,
+    pub iout: Option<f32>,
+    pub temperature: Option<f32>,
+    pub vout_setpoint: Option<f32>,
+    pub current_limit: Option<f32>,
+    pub status: Option<StatusWord>,
+    /// A read failed, the remaining fields of the channel were skipped
+    pub error: bool,
+}
+
+/// How the firmware should decode a register it read, Vout and Linear11 need 2 bytes
+#[derive(Clone, Copy, Debug, PartialEq, bincode::Encode, bincode::Decode)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Format {
+    /// Only the raw bytes are returned
+    Raw,
+    /// ULINEAR16 / VID as set by the channel's VOUT_MODE, in volts
+    Vout,
+    /// SLINEAR11, in the register's unit (A, °C, W, ...)
+    Linear11,
+}
+
+/// Raw bytes of a register and its value decoded in the requested format
+#[derive(Clone, Copy, Debug, PartialEq, bincode::Encode, bincode::Decode)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Register {
+    data: [u8; MAX_READ],
+    len: u8,
+    pub value: Option<f32>,
+}
+
+impl Register {
+    /// Copies data (at most MAX_READ bytes) into a register reply
+    pub fn new(data: &[u8], value: Option<f32>) -> Register {
+        let len = data.len().min(MAX_READ);
+        let mut reg = Register {
+            data: [0; MAX_READ],
+            len: len as u8,
+            value,
+        };
+        reg.data[..len].copy_from_slice(&data[..len]);
+        reg
+    }
+
+    /// The bytes read, in the order the controller sent them
+    pub fn data(&self) -> &[u8] {
+        &self.data[..(self.len as usize).min(MAX_READ)]
+    }
+}
+
+/// The firmware's answer to a request
+#[derive(Clone, Copy, Debug, PartialEq, bincode::Encode, bincode::Decode)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Response {
+    Pong,
+    /// The request was carried out
+    Ack,
+    Register(Register),
+    /// Name of the profile in each slot, None for an empty slot
+    Profiles([Option<Name>; PROFILES]),
+    Profile(Profile),
+    Error(RequestError),
+    Output(State),
+    Margin(MarginStatus),
+    SweepStep(SweepStep),
+    /// None if no ramp aborted or failed since the last Request::GetRampAbort
+    RampAbort(Option<RampEvent>),
+}
+
+/// Why a request was not carried out
+#[derive(Clone, Copy, Debug, PartialEq, bincode::Encode, bincode::Decode)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum RequestError {
+    /// The frame was sent with a protocol version the firmware does not speak
+    UnsupportedVersion(u8),
+    /// The frame did not decode to a request
+    Malformed,
+    /// An NVM request without the confirmation byte
+    NotConfirmed,
+    /// The request is not allowed through this path (eg raw NVM commands)
+    Refused,
+    /// A read length of 0, more than MAX_READ or not matching the format
+    InvalidLength,
+    /// A stream rate outside StreamConfig::MIN_RATE_HZ to MAX_RATE_HZ
+    InvalidRate,
+    /// The profile slot is empty or does not exist
+    NoProfile,
+    /// A ramp rate outside ramp::MIN_RATE to MAX_RATE
+    InvalidRampRate,
+    /// The controller reported an error
+    Vrm(VrmError),
+    /// A margin percentage or sweep dwell out of range, a sweep of no rail or a sweep step that
+    /// was not logged
+    InvalidMargin,
+    /// The request needs the output to be on
+    OutputOff,
+}
+
+/// Everything that can travel inside a frame
+#[derive(Debug, bincode::Encode, bincode::BorrowDecode)]
+pub enum Message<'a> {
+    Request(Request<'a>),
+    Response(Response),
+    /// Unsolicited device state the firmware sends periodically
+    Telemetry(Device),
+    /// Sample of a stream started with Request::StartStream, the frame sequence number counts
+    /// samples
+    Sample(Sample),
+}
+
+/// A decoded frame
+#[derive(Debug)]
+pub struct Frame<'a> {
+    pub seq: u16,
+    pub message: Message<'a>,
+}
+
+/// Why a frame could not be encoded or decoded
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum FrameError {
+    /// The frame does not fit in the buffer
+    Overflow,
+    /// The COBS encoding is broken, usually bytes were lost
+    Cobs,
+    /// The CRC did not match the frame contents
+    Crc,
+    /// The frame was encoded with another protocol version, the sequence number is still valid
+    Version { version: u8, seq: u16 },
+    /// The contents are not a valid message
+    Decode,
+}
+
+impl From<EncodeError> for FrameError {
+    fn from(err: EncodeError) -> FrameError {
+        match err {
+            EncodeError::UnexpectedEnd => FrameError::Overflow,
+            _ => FrameError::Decode,
+        }
+    }
+}
+
+impl From<DecodeError> for FrameError {
+    fn from(_: DecodeError) -> FrameError {
+        FrameError::Decode
+    }
+}
+
+/// Encodes a message into a complete frame (delimiter included), returning its length
+pub fn encode_frame(seq: u16, message: &Message, buf: &mut [u8]) -> Result<usize, FrameError> {
+    let mut raw = [0u8; MAX_FRAME];
+    let config = bincode::config::standard();
+    let mut len = bincode::encode_into_slice((VERSION, seq), &mut raw, config)?;
+    len += bincode::encode_into_slice(message, &mut raw[len..], config)?;
+    if len + CRC_LEN > raw.len() {
+        return Err(FrameError::Overflow);
+    }
+    let crc = crc16(&raw[..len]);
+    raw[len..len + CRC_LEN].copy_from_slice(&crc.to_le_bytes());
+    len += CRC_LEN;
+
+    let len = cobs_encode(&raw[..len], buf).ok_or(FrameError::Overflow)?;
+    *buf.get_mut(len).ok_or(FrameError::Overflow)? = DELIMITER;
+    Ok(len + 1)
+}
+
+/// Decodes a frame in place, buf is everything between two delimiters
+pub fn decode_frame(buf: &mut [u8]) -> Result<Frame<'_>, FrameError> {
+    let len = cobs_decode(buf).ok_or(FrameError::Cobs)?;
+    if len < CRC_LEN {
+        return Err(FrameError::Cobs);
+    }
+    let (data, crc) = buf[..len].split_at(len - CRC_LEN);
+    if crc16(data).to_le_bytes() != crc {
+        return Err(FrameError::Crc);
+    }
+
+    let config = bincode::config::standard();
+    let ((version, seq), used): ((u8, u16), usize) =
+        bincode::borrow_decode_from_slice(data, config)?;
+    if version != VERSION {
+        return Err(FrameError::Version { version, seq });
+    }
+    let (message, _) = bincode::borrow_decode_from_slice(&data[used..], config)?;
+    Ok(Frame { seq, message })
+}
+
+/// Collects received bytes until a delimiter completes a frame
+pub struct FrameReader {
+    buf: [u8; MAX_FRAME],
+    len: usize,
+    overflow: bool,
+}
+
+impl Default for FrameReader {
+    fn default() -> FrameReader {
+        FrameReader {
+            buf: [0; MAX_FRAME],
+            len: 0,
+            overflow: false,
+        }
+    }
+}
+
+impl FrameReader {
+    /// True between frames, when no bytes of a frame have been received yet
+    pub fn at_boundary(&self) -> bool {
+        self.len == 0 && !self.overflow
+    }
+
+    /// Adds a received byte, returning the decoded frame once its delimiter arrives
+    pub fn push(&mut self, byte: u8) -> Option<Result<Frame<'_>, FrameError>> {
+        if byte != DELIMITER {
+            match self.buf.get_mut(self.len) {
+                Some(slot) => {
+                    *slot = byte;
+                    self.len += 1;
+                }
+                None => self.overflow = true,
+            }
+            return None;
+        }
+
+        let len = core::mem::take(&mut self.len);
+        if core::mem::take(&mut self.overflow) {
+            return Some(Err(FrameError::Overflow));
+        }
+        if len == 0 {
+            // Back to back delimiters, hosts send one up front to flush a partial frame
+            return None;
+        }
+        Some(decode_frame(&mut self.buf[..len]))
+    }
+}
+
+/// CRC-16/CCITT-FALSE: polynomial 0x1021, initial value 0xFFFF
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0xFFFFu16;
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+// Consistent overhead byte stuffing, replaces every zero so 0x00 can delimit frames. Returns
+// None if out is too small
+fn cobs_encode(data: &[u8], out: &mut [u8]) -> Option<usize> {
+    let mut code_at = 0;
+    let mut len = 1;
+    let mut code = 1u8;
+    *out.get_mut(code_at)? = 0;
+    for &byte in data {
+        if byte != 0 {
+            *out.get_mut(len)? = byte;
+            len += 1;
+            code += 1;
+        }
+        if byte == 0 || code == 0xFF {
+            out[code_at] = code;
+            code_at = len;
+            *out.get_mut(code_at)? = 0;
+            len += 1;
+            code = 1;
+        }
+    }
+    out[code_at] = code;
+    Some(len)
+}
+
+// Reverses cobs_encode in place, returning the decoded length or None if the encoding is broken
+fn cobs_decode(buf: &mut [u8]) -> Option<usize> {
+    let mut read = 0;
+    let mut write = 0;
+    while read < buf.len() {
+        let code = buf[read] as usize;
+        if code == 0 || read + code > buf.len() {
+            return None;
+        }
+        read += 1;
+        for _ in 1..code {
+            buf[write] = buf[read];
+            write += 1;
+            read += 1;
+        }
+        // A block shorter than 254 bytes stood for a zero, unless it ends the frame
+        if code != 0xFF && read < buf.len() {
+            buf[write] = 0;
+            write += 1;
+        }
+    }
+    Some(write)
+}
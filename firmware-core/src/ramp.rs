@@ -0,0 +1,285 @@
+// Soft start of voltage setpoint changes
+//
+// A new VOUT_COMMAND written in one go moves the rail at the controller's full slew rate, which
+// for a big edit is hundreds of millivolts at once. Instead the setpoint is walked to its target
+// at a set rate in mV/ms, one step every STEP_US from the main loop's clock. STATUS_WORD is read
+// after every step and any fault bit ends the ramp where it is.
+//
+// Front panel edits, SCPI commands, raw USB writes to VOUT_COMMAND and profile loads all go
+// through here. Restoring the settings at boot does not, as the outputs are still off then. The
+// target is checked against the safety envelope up front, so a ramp is never started only to be
+// refused part of the way there. A ramp that aborts or fails is kept until the host fetches it
+// with Request::GetRampAbort, as the request that started it was answered long before.
+
+use crate::control::write_setpoint;
+use crate::pmbus::{to_u16, Command, Page, PmbusDevice, VrmError};
+use crate::protocol::{Request, RequestError, Response};
+use crate::vrm_controller::TPSC536C7;
+use crate::vrm_status::StatusWord;
+
+/// Rate both channels start out with, in mV/ms
+pub const DEFAULT_RATE: f32 = 1.0;
+/// Slowest and fastest rates that can be set, in mV/ms
+pub const MIN_RATE: f32 = 0.1;
+pub const MAX_RATE: f32 = 100.;
+
+/// Time between steps in microseconds
+pub const STEP_US: u32 = 1000;
+
+/// How a ramp ended, channel A is the core and channel B the memory
+#[derive(Clone, Copy, Debug, PartialEq, bincode::Decode, bincode::Encode)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum RampEvent {
+    /// The setpoint reached its target
+    Done(Page),
+    /// The controller reported a fault after a step, the setpoint was left where it was
+    Aborted(Page, StatusWord),
+    /// A step could not be written or checked
+    Failed(Page, VrmError),
+}
+
+impl RampEvent {
+    pub fn page(&self) -> Page {
+        match *self {
+            RampEvent::Done(page) | RampEvent::Aborted(page, _) | RampEvent::Failed(page, _) => {
+                page
+            }
+        }
+    }
+
+    /// Short description for the display
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RampEvent::Done(_) => "Done",
+            RampEvent::Aborted(_, status) if status.contains(StatusWord::VOUT_OV) => "Overvoltage",
+            RampEvent::Aborted(_, status) if status.contains(StatusWord::IOUT_OC) => "Overcurrent",
+            RampEvent::Aborted(_, status) if status.contains(StatusWord::TEMPERATURE) => "Overtemp",
+            RampEvent::Aborted(_, status) if status.contains(StatusWord::VIN_UV) => "Input UV",
+            RampEvent::Aborted(_, status) if status.contains(StatusWord::CML) => "Comm fault",
+            RampEvent::Aborted(..) => "Fault",
+            RampEvent::Failed(..) => "Write failed",
+        }
+    }
+}
+
+/// Ramps the voltage setpoint of each channel towards a target
+#[derive(Debug)]
+pub struct Ramp {
+    // mV/ms, per channel
+    rates: [f32; 2],
+    active: [Option<Active>; 2],
+    // The last ramp that aborted or failed, until the host fetches it
+    aborted: Option<RampEvent>,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Active {
+    from: f32,
+    target: f32,
+    // Setpoint last written
+    value: f32,
+    // Clock when value was written, None until the first poll
+    last: Option<u32>,
+}
+
+impl Default for Ramp {
+    fn default() -> Ramp {
+        Ramp {
+            rates: [DEFAULT_RATE; 2],
+            active: [None; 2],
+            aborted: None,
+        }
+    }
+}
+
+/// Refuses rates outside MIN_RATE to MAX_RATE
+pub fn check_rate(rate: f32) -> Result<(), RequestError> {
+    if !(MIN_RATE..=MAX_RATE).contains(&rate) {
+        return Err(RequestError::InvalidRampRate);
+    }
+    Ok(())
+}
+
+impl Ramp {
+    /// Rate of channel (0 for the core, 1 for the memory) in mV/ms
+    pub fn rate(&self, channel: usize) -> f32 {
+        self.rates[channel.min(1)]
+    }
+
+    pub fn set_rate(&mut self, channel: usize, rate: f32) -> Result<(), RequestError> {
+        check_rate(rate)?;
+        self.rates[channel.min(1)] = rate;
+        Ok(())
+    }
+
+    /// Starts moving the voltage setpoint of channel to target, from where a running ramp got to
+    /// or else from the current VOUT_COMMAND
+    pub fn start<D: PmbusDevice>(
+        &mut self,
+        controller: &mut D,
+        channel: usize,
+        target: f32,
+    ) -> Result<(), VrmError> {
+        let channel = channel.min(1);
+        let from = match self.active[channel] {
+            Some(active) => active.value,
+            None => {
+                controller.select_page(Page::from_channel(channel))?;
+                controller.read_vout_class(Command::VOUTCommand.to_address())?
+            }
+        };
+        info!("Ramp {}: {} V to {} V", channel, from, target);
+        self.active[channel] = Some(Active {
+            from,
+            target,
+            value: from,
+            last: None,
+        });
+        Ok(())
+    }
+
+    /// Writes a setpoint like control::write_setpoint, except that voltages are ramped to
+    pub fn write_setpoint<I: embedded_hal::i2c::I2c>(
+        &mut self,
+        controller: &mut TPSC536C7<I>,
+        position: (i32, i32),
+        val: f32,
+    ) -> Result<(), VrmError> {
+        match position {
+            (channel @ 0..=1, 0) => {
+                let channel = channel as usize;
+                controller.select_page(Page::from_channel(channel))?;
+                let [lo, hi] = controller.vout_mode()?.from_volts(val)?.to_le_bytes();
+                controller.check_write(&[Command::VOUTCommand.to_address(), lo, hi])?;
+                self.start(controller, channel, val)
+            }
+            _ => write_setpoint(controller, position, val),
+        }
+    }
+
+    /// Stops every ramp, leaving the setpoints where they got to
+    pub fn stop(&mut self) {
+        self.active = [None; 2];
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.active.iter().any(Option::is_some)
+    }
+
+    /// Voltage channel is being ramped to
+    pub fn target(&self, channel: usize) -> Option<f32> {
+        Some(self.active.get(channel)?.as_ref()?.target)
+    }
+
+    /// How far along the ramp of channel is, from 0 to 1
+    pub fn progress(&self, channel: usize) -> Option<f32> {
+        let active = self.active.get(channel)?.as_ref()?;
+        let span = active.target - active.from;
+        if span == 0. {
+            return Some(1.);
+        }
+        Some(((active.value - active.from) / span).clamp(0., 1.))
+    }
+
+    /// Writes the next step of every channel that is due one, returning how a ramp ended. With
+    /// both ending at once the second is reported by the next poll
+    pub fn poll<D: PmbusDevice>(&mut self, controller: &mut D, now: u32) -> Option<RampEvent> {
+        for channel in 0..2 {
+            let rate = self.rates[channel];
+            let Some(active) = &mut self.active[channel] else {
+                continue;
+            };
+            let Some(last) = active.last else {
+                active.last = Some(now);
+                continue;
+            };
+            let elapsed = now.wrapping_sub(last);
+            if elapsed < STEP_US {
+                continue;
+            }
+
+            // mV/ms times us is uV, the setpoint is in volts
+            let step = rate * elapsed as f32 / 1_000_000.;
+            active.value = if active.target > active.value {
+                (active.value + step).min(active.target)
+            } else {
+                (active.value - step).max(active.target)
+            };
+            active.last = Some(now);
+            let value = active.value;
+            let done = value == active.target;
+
+            let page = Page::from_channel(channel);
+            let event = match step_to(controller, page, value) {
+                Ok(status) if status.is_faulted() => {
+                    error!("Ramp {} Aborted: {}", channel, status);
+                    Some(RampEvent::Aborted(page, status))
+                }
+                Ok(_) if done => {
+                    info!("Ramp {} Done", channel);
+                    Some(RampEvent::Done(page))
+                }
+                Ok(_) => None,
+                Err(err) => {
+                    error!("Ramp {} Failed: {}", channel, err);
+                    Some(RampEvent::Failed(page, err))
+                }
+            };
+            if event.is_some() {
+                self.active[channel] = None;
+                if !matches!(event, Some(RampEvent::Done(_))) {
+                    self.aborted = event;
+                }
+                return event;
+            }
+        }
+        None
+    }
+
+    /// The last ramp that aborted or failed, cleared once taken
+    pub fn take_abort(&mut self) -> Option<RampEvent> {
+        self.aborted.take()
+    }
+
+    /// Handles the ramp requests and raw writes to VOUT_COMMAND, which are ramped to instead.
+    /// None for any other request
+    pub fn handle<I: embedded_hal::i2c::I2c>(
+        &mut self,
+        request: &Request,
+        controller: &mut TPSC536C7<I>,
+    ) -> Option<Response> {
+        let result = match *request {
+            Request::SetRampRate { page, rate } => self.set_rate(page as usize, rate),
+            Request::GetRampAbort => return Some(Response::RampAbort(self.take_abort())),
+            Request::Write {
+                page,
+                data: &[cmd, lo, hi],
+            } if cmd == Command::VOUTCommand.to_address() => {
+                let channel = page.min(1) as usize;
+                controller
+                    .select_page(Page::from_channel(channel))
+                    .and_then(|()| controller.check_write(&[cmd, lo, hi]))
+                    .and_then(|()| controller.vout_mode())
+                    .and_then(|mode| mode.to_volts(to_u16([lo, hi])))
+                    .and_then(|target| self.start(controller, channel, target))
+                    .map_err(RequestError::Vrm)
+            }
+            _ => return None,
+        };
+        Some(match result {
+            Ok(()) => Response::Ack,
+            Err(err) => Response::Error(err),
+        })
+    }
+}
+
+// Writes one step of the setpoint, returning the status the controller reports after it
+fn step_to<D: PmbusDevice>(
+    controller: &mut D,
+    page: Page,
+    value: f32,
+) -> Result<StatusWord, VrmError> {
+    controller.select_page(page)?;
+    controller.write_vout_class(Command::VOUTCommand.to_address(), value)?;
+    controller.status_word()
+}
@@ -0,0 +1,251 @@
+// Hard limits on what the controller can be told to do, whichever path the request came from
+//
+// TPSC536C7 checks every write against its Envelope before the write goes out on the bus, so
+// front panel edits, SCPI commands, raw USB register writes, profiles, ramps and restored
+// settings are all held to the same limits. Writes to the VOUT class setpoints have to stay
+// between the rail's minimum and maximum voltage, the current limits below its maximum current
+// and the temperature limits below its maximum temperature. Writes that would change how the
+// output voltage is read back from those setpoints (VOUT_MODE, VOUT_TRIM, VOUT_CAL_OFFSET) are
+// refused outright.
+//
+// The controller's own VOUT_MAX and VOUT_MIN are programmed to the envelope at boot, see
+// TPSC536C7::apply_envelope, so the output stays inside it whatever the firmware gets wrong.
+
+use pmbus_types_rs::slinear11;
+
+use crate::pmbus::{to_u16, to_value, Command, Page, VoutMode};
+
+/// VOUT_TRIM and VOUT_CAL_OFFSET, which offset the output from VOUT_COMMAND
+const VOUT_OFFSETS: [u8; 2] = [0x22, 0x23];
+
+/// Limits of one rail
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Limits {
+    /// Lowest voltage setpoint in volts
+    pub vout_min: f32,
+    /// Highest voltage setpoint in volts
+    pub vout_max: f32,
+    /// Highest current limit in amps
+    pub iout_max: f32,
+    /// Highest temperature limit in °C
+    pub temp_max: f32,
+}
+
+/// Limits of both rails
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Envelope {
+    pub core: Limits,
+    pub mem: Limits,
+}
+
+impl Default for Envelope {
+    fn default() -> Envelope {
+        Envelope {
+            core: Limits {
+                vout_min: 0.5,
+                vout_max: 1.25,
+                iout_max: 250.,
+                temp_max: 125.,
+            },
+            mem: Limits {
+                vout_min: 1.0,
+                vout_max: 1.5,
+                iout_max: 80.,
+                temp_max: 125.,
+            },
+        }
+    }
+}
+
+/// Why a write was refused
+#[derive(Clone, Copy, Debug, PartialEq, bincode::Decode, bincode::Encode)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Violation {
+    /// A voltage setpoint above the rail's maximum
+    VoltageHigh,
+    /// A voltage setpoint below the rail's minimum
+    VoltageLow,
+    /// A current limit above the rail's maximum
+    CurrentLimit,
+    /// A temperature limit above the rail's maximum
+    TempLimit,
+    /// A write to a register that is never allowed, or one whose value could not be decoded
+    Protected,
+    /// A current or temperature limit below 0, which would trip the rail at once
+    NegativeLimit,
+}
+
+impl Violation {
+    /// Short description for the display
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Violation::VoltageHigh => "V above max",
+            Violation::VoltageLow => "V below min",
+            Violation::CurrentLimit => "I lim too high",
+            Violation::TempLimit => "T lim too high",
+            Violation::Protected => "Not allowed",
+            Violation::NegativeLimit => "Limit below 0",
+        }
+    }
+}
+
+/// A refused write, page is the rail whose limit it broke
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Rejection {
+    pub page: Page,
+    pub cmd: u8,
+    pub violation: Violation,
+    /// Decoded value of the write, 0 for a protected register
+    pub value: f32,
+}
+
+impl Envelope {
+    /// Limits of a rail, the core's for channel A and the memory's for channel B
+    pub fn limits(&self, page: Page) -> &Limits {
+        match page {
+            Page::ChannelA => &self.core,
+            _ => &self.mem,
+        }
+    }
+
+    /// Keeps a value edited on the front panel inside the envelope, position is (channel, row)
+    pub fn clamp(&self, position: (i32, i32), val: f32) -> f32 {
+        let limits = match position.0 {
+            0 => &self.core,
+            _ => &self.mem,
+        };
+        match position.1 {
+            0 => val.clamp(limits.vout_min, limits.vout_max),
+            1 => val.clamp(0., limits.iout_max),
+            _ => val,
+        }
+    }
+
+    /// Checks a write (command code first) to page, mode is the VOUT_MODE of the page and only
+    /// needed for the VOUT class commands. Writes to both pages have to suit both rails
+    ///
+    /// Limits are compared after going through the register format, so a limit that the format
+    /// cannot hold exactly can itself still be written
+    pub fn check_write(
+        &self,
+        page: Page,
+        data: &[u8],
+        mode: Option<VoutMode>,
+    ) -> Result<(), Rejection> {
+        let Some(&cmd) = data.first() else {
+            return Ok(());
+        };
+        let Some(class) = Class::of(cmd) else {
+            return Ok(());
+        };
+        let pages: &[Page] = match page {
+            Page::Both => &[Page::ChannelA, Page::ChannelB],
+            _ => &[page],
+        };
+        let reject = |page, violation, value| Rejection {
+            page,
+            cmd,
+            violation,
+            value,
+        };
+
+        let raw = match (class, data) {
+            (Class::Protected, _) => return Err(reject(pages[0], Violation::Protected, 0.)),
+            (_, &[_, lo, hi]) => to_u16([lo, hi]),
+            // Anything but a whole word cannot be checked
+            _ => return Err(reject(pages[0], Violation::Protected, 0.)),
+        };
+        let value = match class {
+            Class::Vout => mode
+                .ok_or(())
+                .and_then(|mode| mode.to_volts(raw).map_err(|_| ())),
+            _ => to_value(slinear11::to(raw)).map_err(|_| ()),
+        };
+        let Ok(value) = value else {
+            return Err(reject(pages[0], Violation::Protected, 0.));
+        };
+
+        for &page in pages {
+            let limits = self.limits(page);
+            let violation = match class {
+                Class::Vout => {
+                    let quantised = |limit| quantise_vout(mode, limit);
+                    if value > quantised(limits.vout_max) {
+                        Some(Violation::VoltageHigh)
+                    } else if value < quantised(limits.vout_min) {
+                        Some(Violation::VoltageLow)
+                    } else {
+                        None
+                    }
+                }
+                Class::Current | Class::Temp if value < 0. => Some(Violation::NegativeLimit),
+                Class::Current if value > quantise_linear11(limits.iout_max) => {
+                    Some(Violation::CurrentLimit)
+                }
+                Class::Temp if value > quantise_linear11(limits.temp_max) => {
+                    Some(Violation::TempLimit)
+                }
+                _ => None,
+            };
+            if let Some(violation) = violation {
+                return Err(reject(page, violation, value));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// True for the commands check_write needs the VOUT_MODE of the page for
+pub fn needs_vout_mode(cmd: u8) -> bool {
+    matches!(Class::of(cmd), Some(Class::Vout))
+}
+
+// What a guarded register holds
+#[derive(Clone, Copy)]
+enum Class {
+    Vout,
+    Current,
+    Temp,
+    Protected,
+}
+
+impl Class {
+    // None for registers the envelope does not cover
+    fn of(cmd: u8) -> Option<Class> {
+        let vout = [
+            Command::VOUTCommand,
+            Command::VOUTMax,
+            Command::VOUTMin,
+            Command::VOUTMarginHigh,
+            Command::VOUTMarginLow,
+        ];
+        let current = [Command::IoutOCFaultLimit, Command::IoutOCWarnLimit];
+        let temp = [Command::OTFaultLimit, Command::OTWarnLimit];
+        let is = |cmds: &[Command]| cmds.iter().any(|c| c.to_address() == cmd);
+        if is(&vout) {
+            Some(Class::Vout)
+        } else if is(&current) {
+            Some(Class::Current)
+        } else if is(&temp) {
+            Some(Class::Temp)
+        } else if cmd == Command::VoutMode.to_address() || VOUT_OFFSETS.contains(&cmd) {
+            Some(Class::Protected)
+        } else {
+            None
+        }
+    }
+}
+
+// The voltage the register ends up holding when limit is written in mode
+fn quantise_vout(mode: Option<VoutMode>, limit: f32) -> f32 {
+    mode.and_then(|mode| mode.to_volts(mode.from_volts(limit).ok()?).ok())
+        .unwrap_or(limit)
+}
+
+// The value the register ends up holding when limit is written in SLINEAR11
+fn quantise_linear11(limit: f32) -> f32 {
+    slinear11::to(slinear11::from(limit))
+}
@@ -0,0 +1,419 @@
+// Line oriented SCPI interface on the USB CDC port, next to the framed protocol
+//
+// The port starts out speaking the framed protocol. Sending HANDSHAKE where a frame would start
+// switches it to text, one command per line, and a 0x00 byte (which never shows up in text and
+// which framed hosts send ahead of every request anyway) switches it back. Keywords are case
+// insensitive and take their long or short form, eg VOLTage:CORE or VOLT:CORE
+//
+//     *IDN?                       identification
+//     *CLS                        clears the error queue
+//     VOLT:CORE 1.050             voltage setpoint, VOLT:MEM for the memory channel
+//     VOLT:CORE?
+//     CURR:LIM:MEM 40             current limit (IOUT_OC_FAULT_LIMIT)
+//     CURR:LIM:MEM?
+//     MEAS:VOLT:CORE?             READ_VOUT, MEAS:CURR and MEAS:TEMP for the other readings
+//     OUTP ON                     output of both channels, ON / OFF / 1 / 0
+//     OUTP?                       1 once both rails are up
+//     SYST:ERR?                   oldest queued error, 0,"No error" once empty
+//
+// Setpoints go through the same handlers as the front panel buttons, so voltages are ramped to
+// and VOLT? answers with the target of a running ramp. Setpoints outside the safety envelope
+// are refused with -222 Data out of range. Commands that fail queue an error for SYST:ERR? and
+// are otherwise silent, queries always answer with one line. OUTP switches the rails through the
+// power sequencer, see sequence.rs, so they come up memory first and go down core first.
+
+use core::fmt::Write;
+
+use crate::navigation::Device;
+use crate::pmbus::{Page, VrmError};
+use crate::ramp::Ramp;
+use crate::sequence::Sequencer;
+use crate::vrm_controller::TPSC536C7;
+
+/// Switches the port to text when it starts a frame. Request frames start with a COBS code byte
+/// of at most 5 (the message tag is a zero within the first five bytes), so they never look like
+/// it
+pub const HANDSHAKE: u8 = 0x16;
+
+/// Longest command line, longer lines are dropped with an input buffer overrun
+pub const MAX_LINE: usize = 64;
+
+/// Errors SYST:ERR? can hold before the newest is replaced by a queue overflow
+const ERROR_QUEUE: usize = 8;
+
+/// Answer to *IDN?: manufacturer, model, serial number, firmware version
+const IDENTITY: &str = concat!(
+    "Overclocking Club,gpu-external-power-supply,Prototype-1,",
+    env!("CARGO_PKG_VERSION")
+);
+
+/// A SCPI error, reported through SYST:ERR?
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ScpiError {
+    /// The line is not valid ASCII or could not be split into a command
+    Syntax,
+    /// A number was expected but not given
+    DataType,
+    /// A command that takes no parameter was given one
+    ParameterNotAllowed,
+    MissingParameter,
+    /// The command is not known
+    UndefinedHeader,
+    /// The parameter is not valid for the command (eg a negative setpoint)
+    IllegalParameter,
+    /// The setpoint is outside the safety envelope, see safety::Envelope
+    DataOutOfRange,
+    /// The controller reported an error
+    Vrm(VrmError),
+    /// More errors came in than the queue holds
+    QueueOverflow,
+    /// A line longer than MAX_LINE
+    InputOverrun,
+}
+
+impl ScpiError {
+    /// The standard SCPI error number
+    pub fn code(&self) -> i16 {
+        match self {
+            ScpiError::Syntax => -102,
+            ScpiError::DataType => -104,
+            ScpiError::ParameterNotAllowed => -108,
+            ScpiError::MissingParameter => -109,
+            ScpiError::UndefinedHeader => -113,
+            ScpiError::IllegalParameter => -224,
+            ScpiError::DataOutOfRange => -222,
+            ScpiError::Vrm(_) => -300,
+            ScpiError::QueueOverflow => -350,
+            ScpiError::InputOverrun => -363,
+        }
+    }
+
+    /// The standard SCPI error description
+    pub fn message(&self) -> &'static str {
+        match self {
+            ScpiError::Syntax => "Syntax error",
+            ScpiError::DataType => "Data type error",
+            ScpiError::ParameterNotAllowed => "Parameter not allowed",
+            ScpiError::MissingParameter => "Missing parameter",
+            ScpiError::UndefinedHeader => "Undefined header",
+            ScpiError::IllegalParameter => "Illegal parameter value",
+            ScpiError::DataOutOfRange => "Data out of range",
+            ScpiError::Vrm(_) => "Device-specific error",
+            ScpiError::QueueOverflow => "Queue overflow",
+            ScpiError::InputOverrun => "Input buffer overrun",
+        }
+    }
+}
+
+/// A parsed command line
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Command {
+    Identify,
+    ClearStatus,
+    /// Writes a setpoint, position is the (channel, row) of the front panel grid
+    Set((i32, i32), f32),
+    /// Reads back a setpoint, position as for Set
+    QuerySet((i32, i32)),
+    Measure(Measurement, Page),
+    Output(bool),
+    QueryOutput,
+    QueryError,
+}
+
+/// What a MEASure query reads
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Measurement {
+    Voltage,
+    Current,
+    Temperature,
+}
+
+/// Parses one command line, without its line ending
+pub fn parse(line: &str) -> Result<Command, ScpiError> {
+    let line = line.trim();
+    let (header, param) = match line.split_once(|c: char| c.is_ascii_whitespace()) {
+        Some((header, param)) => (header, Some(param.trim())),
+        None => (line, None),
+    };
+    let (header, query) = match header.strip_suffix('?') {
+        Some(header) => (header, true),
+        None => (header, false),
+    };
+    let mut keys = header.strip_prefix(':').unwrap_or(header).split(':');
+    let mut next = || keys.next().unwrap_or("");
+
+    let command = match next() {
+        key if key.eq_ignore_ascii_case("*IDN") && query => Command::Identify,
+        key if key.eq_ignore_ascii_case("*CLS") && !query => Command::ClearStatus,
+        key if keyword(key, "VOLTAGE", 4) => setpoint(channel(next())?, 0, query, param)?,
+        key if keyword(key, "CURRENT", 4) => {
+            if !keyword(next(), "LIMIT", 3) {
+                return Err(ScpiError::UndefinedHeader);
+            }
+            setpoint(channel(next())?, 1, query, param)?
+        }
+        key if keyword(key, "MEASURE", 4) && query => {
+            let key = next();
+            let measurement = if keyword(key, "VOLTAGE", 4) {
+                Measurement::Voltage
+            } else if keyword(key, "CURRENT", 4) {
+                Measurement::Current
+            } else if keyword(key, "TEMPERATURE", 4) {
+                Measurement::Temperature
+            } else {
+                return Err(ScpiError::UndefinedHeader);
+            };
+            Command::Measure(measurement, Page::from_channel(channel(next())? as usize))
+        }
+        key if keyword(key, "OUTPUT", 4) && query => Command::QueryOutput,
+        key if keyword(key, "OUTPUT", 4) => match param.ok_or(ScpiError::MissingParameter)? {
+            param if param.eq_ignore_ascii_case("ON") || param == "1" => Command::Output(true),
+            param if param.eq_ignore_ascii_case("OFF") || param == "0" => Command::Output(false),
+            _ => return Err(ScpiError::IllegalParameter),
+        },
+        key if keyword(key, "SYSTEM", 4) && query => {
+            if !keyword(next(), "ERROR", 3) {
+                return Err(ScpiError::UndefinedHeader);
+            }
+            Command::QueryError
+        }
+        _ => return Err(ScpiError::UndefinedHeader),
+    };
+    // Anything left over is a keyword this command does not have
+    if !next().is_empty() {
+        return Err(ScpiError::UndefinedHeader);
+    }
+    // Only setpoint and output commands take a parameter
+    if param.is_some() && !matches!(command, Command::Set(..) | Command::Output(_)) {
+        return Err(ScpiError::ParameterNotAllowed);
+    }
+    Ok(command)
+}
+
+// True if key is the long form of a keyword or its first short characters
+fn keyword(key: &str, long: &str, short: usize) -> bool {
+    key.eq_ignore_ascii_case(long) || key.eq_ignore_ascii_case(&long[..short])
+}
+
+// Channel column of the front panel grid, 0 for CORE and 1 for MEM
+fn channel(key: &str) -> Result<i32, ScpiError> {
+    if key.eq_ignore_ascii_case("CORE") {
+        Ok(0)
+    } else if keyword(key, "MEMORY", 3) {
+        Ok(1)
+    } else {
+        Err(ScpiError::UndefinedHeader)
+    }
+}
+
+// A setpoint write or query of the given front panel row
+fn setpoint(
+    channel: i32,
+    row: i32,
+    query: bool,
+    param: Option<&str>,
+) -> Result<Command, ScpiError> {
+    if query {
+        return Ok(Command::QuerySet((channel, row)));
+    }
+    let value: f32 = param
+        .ok_or(ScpiError::MissingParameter)?
+        .parse()
+        .map_err(|_| ScpiError::DataType)?;
+    if !value.is_finite() || value < 0. {
+        return Err(ScpiError::IllegalParameter);
+    }
+    Ok(Command::Set((channel, row), value))
+}
+
+/// Command interpreter, holds the error queue between lines
+#[derive(Debug, Default)]
+pub struct Scpi {
+    errors: [Option<ScpiError>; ERROR_QUEUE],
+}
+
+impl Scpi {
+    /// Runs one command line, a query's answer (ending in a newline) is written to reply
+    pub fn execute<I: embedded_hal::i2c::I2c, W: Write>(
+        &mut self,
+        line: &str,
+        controller: &mut TPSC536C7<I>,
+        ramp: &mut Ramp,
+        seq: &mut Sequencer,
+        dev: &mut Device,
+        reply: &mut W,
+    ) {
+        let result = parse(line).and_then(|cmd| self.run(cmd, controller, ramp, seq, dev, reply));
+        if let Err(err) = result {
+            warn!("SCPI: {}", err);
+            self.push_error(err);
+        }
+    }
+
+    /// Queues an error for SYST:ERR?, eg from reading the line
+    pub fn push_error(&mut self, err: ScpiError) {
+        match self.errors.iter_mut().position(|slot| slot.is_none()) {
+            Some(idx) => self.errors[idx] = Some(err),
+            // The newest error is replaced, as the standard asks
+            None => self.errors[ERROR_QUEUE - 1] = Some(ScpiError::QueueOverflow),
+        }
+    }
+
+    /// Takes the oldest queued error
+    pub fn pop_error(&mut self) -> Option<ScpiError> {
+        let err = self.errors[0].take();
+        self.errors.rotate_left(1);
+        err
+    }
+
+    // A full reply buffer only cuts the answer short, so write errors are ignored
+    fn run<I: embedded_hal::i2c::I2c, W: Write>(
+        &mut self,
+        cmd: Command,
+        controller: &mut TPSC536C7<I>,
+        ramp: &mut Ramp,
+        seq: &mut Sequencer,
+        dev: &mut Device,
+        reply: &mut W,
+    ) -> Result<(), ScpiError> {
+        match cmd {
+            Command::Identify => {
+                let _ = writeln!(reply, "{}", IDENTITY);
+            }
+            Command::ClearStatus => self.errors = Default::default(),
+            Command::Set(position, value) => {
+                ramp.write_setpoint(controller, position, value)
+                    .map_err(|err| match err {
+                        VrmError::OutOfEnvelope(_) => ScpiError::DataOutOfRange,
+                        err => ScpiError::Vrm(err),
+                    })?;
+                dev.store_value(position, value);
+            }
+            Command::QuerySet(position) => {
+                let c = controller
+                    .page(Page::from_channel(position.0 as usize))
+                    .map_err(ScpiError::Vrm)?;
+                let value = match (position.1, ramp.target(position.0 as usize)) {
+                    (0, Some(target)) => Ok(target),
+                    (0, None) => c.vout_command().read(),
+                    _ => c.iout_oc_fault_limit().read(),
+                };
+                let _ = writeln!(reply, "{:.4}", value.map_err(ScpiError::Vrm)?);
+            }
+            Command::Measure(measurement, page) => {
+                let c = controller.page(page).map_err(ScpiError::Vrm)?;
+                let value = match measurement {
+                    Measurement::Voltage => c.read_vout(),
+                    Measurement::Current => c.read_iout(),
+                    Measurement::Temperature => c.read_temperature_1(),
+                };
+                let _ = writeln!(reply, "{:.4}", value.map_err(ScpiError::Vrm)?);
+            }
+            Command::Output(true) => seq.power_up(controller).map_err(ScpiError::Vrm)?,
+            Command::Output(false) => seq.power_down(controller).map_err(ScpiError::Vrm)?,
+            Command::QueryOutput => {
+                let _ = writeln!(reply, "{}", seq.is_on() as u8);
+            }
+            Command::QueryError => {
+                let _ = match self.pop_error() {
+                    // The controller's error is added as the standard's device dependent info
+                    Some(ScpiError::Vrm(err)) => {
+                        writeln!(reply, "-300,\"Device-specific error;{:?}\"", err)
+                    }
+                    Some(err) => writeln!(reply, "{},\"{}\"", err.code(), err.message()),
+                    None => writeln!(reply, "0,\"No error\""),
+                };
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Collects received bytes until a line ending completes a command
+pub struct LineReader {
+    buf: [u8; MAX_LINE],
+    len: usize,
+    overflow: bool,
+}
+
+impl Default for LineReader {
+    fn default() -> LineReader {
+        LineReader {
+            buf: [0; MAX_LINE],
+            len: 0,
+            overflow: false,
+        }
+    }
+}
+
+impl LineReader {
+    /// Adds a received byte, returning the line once its newline arrives. Carriage returns are
+    /// dropped and empty lines skipped
+    pub fn push(&mut self, byte: u8) -> Option<Result<&str, ScpiError>> {
+        match byte {
+            b'\r' => return None,
+            b'\n' => (),
+            _ => {
+                match self.buf.get_mut(self.len) {
+                    Some(slot) => {
+                        *slot = byte;
+                        self.len += 1;
+                    }
+                    None => self.overflow = true,
+                }
+                return None;
+            }
+        }
+
+        let len = core::mem::take(&mut self.len);
+        if core::mem::take(&mut self.overflow) {
+            return Some(Err(ScpiError::InputOverrun));
+        }
+        if len == 0 {
+            return None;
+        }
+        Some(core::str::from_utf8(&self.buf[..len]).map_err(|_| ScpiError::Syntax))
+    }
+
+    /// Drops a partly received line
+    pub fn clear(&mut self) {
+        self.len = 0;
+        self.overflow = false;
+    }
+}
+
+/// Fixed size buffer a reply is formatted into, anything past its end is cut off
+pub struct Reply {
+    buf: [u8; MAX_LINE],
+    len: usize,
+}
+
+impl Default for Reply {
+    fn default() -> Reply {
+        Reply {
+            buf: [0; MAX_LINE],
+            len: 0,
+        }
+    }
+}
+
+impl Reply {
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+}
+
+impl Write for Reply {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        let count = s.len().min(MAX_LINE - self.len);
+        self.buf[self.len..self.len + count].copy_from_slice(&s.as_bytes()[..count]);
+        self.len += count;
+        if count < s.len() {
+            return Err(core::fmt::Error);
+        }
+        Ok(())
+    }
+}
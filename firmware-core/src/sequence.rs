@@ -0,0 +1,289 @@
+// Power sequencing of the two rails
+//
+// GPUs expect the memory rail to be up before the core comes up and to stay up until the core is
+// down. The sequencer switches the rails one at a time through OPERATION and waits for the
+// controller's VR_READY output of each (AVR_READY for the core, BVR_READY for the memory) before
+// moving on:
+//
+//     Off -> MemRamp -> CoreRamp -> On            power up
+//     On -> CoreDown -> MemDown -> Off            power down
+//
+// Each rail is turned off immediately rather than soft off, so switching the output off from the
+// panel or USB kills the rails about as quickly as unplugging the board while keeping the order.
+// A rail that does not come ready (or does not drop) within the timeout, or loses VR_READY while
+// it should be up, ends the sequence in Fault with both rails turned off at once, core first.
+// The memory rail's enable pin (BVR_EN) is driven from mem_enable() next to its OPERATION, so a
+// rail the sequencer considers off is off twice over.
+
+use crate::pmbus::{Command, Operation, Page, PmbusDevice, VrmError};
+use crate::protocol::{Request, RequestError, Response};
+
+/// Time a rail has to report VR_READY after being turned on, in microseconds
+pub const READY_TIMEOUT_US: u32 = 50_000;
+/// Time a rail has to drop VR_READY after being turned off, in microseconds
+pub const OFF_TIMEOUT_US: u32 = 50_000;
+
+/// ON_OFF_CONFIG of the core: on / off by OPERATION only
+const ON_OFF_CORE: u8 = 0x1A;
+/// ON_OFF_CONFIG of the memory: on only with both OPERATION and the (active high) BVR_EN pin
+const ON_OFF_MEM: u8 = 0x1E;
+
+/// Levels of the VR_READY inputs
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Ready {
+    /// AVR_READY
+    pub core: bool,
+    /// BVR_READY
+    pub mem: bool,
+}
+
+/// Where the sequence is
+#[derive(Clone, Copy, Debug, Default, PartialEq, bincode::Decode, bincode::Encode)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum State {
+    /// Both rails off
+    #[default]
+    Off,
+    /// The memory rail was turned on, waiting for BVR_READY
+    MemRamp,
+    /// The core was turned on, waiting for AVR_READY
+    CoreRamp,
+    /// Both rails up
+    On,
+    /// The core was turned off, waiting for AVR_READY to drop
+    CoreDown,
+    /// The memory rail was turned off, waiting for BVR_READY to drop
+    MemDown,
+    /// The sequence failed and both rails were turned off
+    Fault(SequenceFault),
+}
+
+impl State {
+    /// Short description for the display
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            State::Off => "Off",
+            State::MemRamp => "Vmem up",
+            State::CoreRamp => "Vcore up",
+            State::On => "On",
+            State::CoreDown => "Vcore down",
+            State::MemDown => "Vmem down",
+            State::Fault(_) => "Fault",
+        }
+    }
+}
+
+/// Why a sequence failed
+#[derive(Clone, Copy, Debug, PartialEq, bincode::Decode, bincode::Encode)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SequenceFault {
+    /// The rail did not report VR_READY in time after being turned on
+    ReadyTimeout(Page),
+    /// The rail still reported VR_READY a while after being turned off
+    OffTimeout(Page),
+    /// The rail dropped VR_READY while it should have been up
+    LostReady(Page),
+    /// OPERATION could not be written
+    Vrm(VrmError),
+}
+
+/// Brings the rails up and down in order
+#[derive(Debug, Default)]
+pub struct Sequencer {
+    state: State,
+    // Clock when the state was entered, None until the first poll in it
+    since: Option<u32>,
+}
+
+impl Sequencer {
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    /// True once both rails are up
+    pub fn is_on(&self) -> bool {
+        self.state == State::On
+    }
+
+    /// Level to drive the memory rail's enable pin to, high from the start of the power up
+    /// until the memory rail is turned off
+    pub fn mem_enable(&self) -> bool {
+        matches!(
+            self.state,
+            State::MemRamp | State::CoreRamp | State::On | State::CoreDown
+        )
+    }
+
+    /// Turns both rails off and hands their on / off control to OPERATION (and BVR_EN for the
+    /// memory), ready for power_up
+    pub fn init<D: PmbusDevice>(&mut self, controller: &mut D) -> Result<(), VrmError> {
+        // Off before ON_OFF_CONFIG, which could otherwise bring both rails up together
+        for (page, config) in [(Page::ChannelA, ON_OFF_CORE), (Page::ChannelB, ON_OFF_MEM)] {
+            write_operation(controller, page, Operation::ImmediateOff)?;
+            controller.write_byte(Command::OnOffConfig.to_address(), config)?;
+        }
+        self.enter(State::Off);
+        Ok(())
+    }
+
+    /// Starts bringing the rails up, memory first. Carries on from a power down that has not
+    /// finished yet
+    pub fn power_up<D: PmbusDevice>(&mut self, controller: &mut D) -> Result<(), VrmError> {
+        match self.state {
+            State::MemRamp | State::CoreRamp | State::On => Ok(()),
+            _ => {
+                info!("Power Up");
+                self.switch(controller, Page::ChannelB, Operation::On, State::MemRamp)
+            }
+        }
+    }
+
+    /// Brings the rails down if they are up or coming up, and up otherwise
+    pub fn toggle<D: PmbusDevice>(&mut self, controller: &mut D) -> Result<(), VrmError> {
+        match self.state {
+            State::MemRamp | State::CoreRamp | State::On => self.power_down(controller),
+            _ => self.power_up(controller),
+        }
+    }
+
+    /// Starts bringing the rails down, core first
+    pub fn power_down<D: PmbusDevice>(&mut self, controller: &mut D) -> Result<(), VrmError> {
+        match self.state {
+            State::MemRamp | State::CoreRamp | State::On => {
+                info!("Power Down");
+                self.switch(
+                    controller,
+                    Page::ChannelA,
+                    Operation::ImmediateOff,
+                    State::CoreDown,
+                )
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Answers the output requests, None for requests that are not for the sequencer
+    pub fn handle<D: PmbusDevice>(
+        &mut self,
+        request: &Request,
+        controller: &mut D,
+    ) -> Option<Response> {
+        let result = match *request {
+            Request::SetOutput(true) => self.power_up(controller),
+            Request::SetOutput(false) => self.power_down(controller),
+            Request::GetOutput => return Some(Response::Output(self.state)),
+            _ => return None,
+        };
+        Some(match result {
+            Ok(()) => Response::Ack,
+            Err(err) => Response::Error(RequestError::Vrm(err)),
+        })
+    }
+
+    /// Moves the sequence on from the VR_READY levels, returning the new state when it changed
+    pub fn poll<D: PmbusDevice>(
+        &mut self,
+        controller: &mut D,
+        ready: Ready,
+        now: u32,
+    ) -> Option<State> {
+        let Some(since) = self.since else {
+            self.since = Some(now);
+            return None;
+        };
+        let elapsed = now.wrapping_sub(since);
+
+        let before = self.state;
+        let result = match self.state {
+            State::MemRamp if ready.mem => {
+                self.switch(controller, Page::ChannelA, Operation::On, State::CoreRamp)
+            }
+            State::MemRamp if elapsed >= READY_TIMEOUT_US => {
+                self.fault(controller, SequenceFault::ReadyTimeout(Page::ChannelB))
+            }
+            State::CoreRamp if !ready.mem => {
+                self.fault(controller, SequenceFault::LostReady(Page::ChannelB))
+            }
+            State::CoreRamp if ready.core => {
+                self.enter(State::On);
+                Ok(())
+            }
+            State::CoreRamp if elapsed >= READY_TIMEOUT_US => {
+                self.fault(controller, SequenceFault::ReadyTimeout(Page::ChannelA))
+            }
+            State::On if !ready.core => {
+                self.fault(controller, SequenceFault::LostReady(Page::ChannelA))
+            }
+            State::On if !ready.mem => {
+                self.fault(controller, SequenceFault::LostReady(Page::ChannelB))
+            }
+            State::CoreDown if !ready.core => self.switch(
+                controller,
+                Page::ChannelB,
+                Operation::ImmediateOff,
+                State::MemDown,
+            ),
+            State::CoreDown if elapsed >= OFF_TIMEOUT_US => {
+                self.fault(controller, SequenceFault::OffTimeout(Page::ChannelA))
+            }
+            State::MemDown if !ready.mem => {
+                self.enter(State::Off);
+                Ok(())
+            }
+            State::MemDown if elapsed >= OFF_TIMEOUT_US => {
+                self.fault(controller, SequenceFault::OffTimeout(Page::ChannelB))
+            }
+            _ => Ok(()),
+        };
+        if let Err(err) = result {
+            error!("Sequence Failed: {}", err);
+        }
+        (self.state != before).then_some(self.state)
+    }
+
+    // Writes OPERATION of page and moves to next, or to Fault if the write failed
+    fn switch<D: PmbusDevice>(
+        &mut self,
+        controller: &mut D,
+        page: Page,
+        operation: Operation,
+        next: State,
+    ) -> Result<(), VrmError> {
+        match write_operation(controller, page, operation) {
+            Ok(()) => {
+                self.enter(next);
+                Ok(())
+            }
+            Err(err) => self
+                .fault(controller, SequenceFault::Vrm(err))
+                .and(Err(err)),
+        }
+    }
+
+    // Turns both rails off straight away, core first, and stops in Fault
+    fn fault<D: PmbusDevice>(
+        &mut self,
+        controller: &mut D,
+        fault: SequenceFault,
+    ) -> Result<(), VrmError> {
+        error!("Sequence Fault: {}", fault);
+        self.enter(State::Fault(fault));
+        write_operation(controller, Page::ChannelA, Operation::ImmediateOff)?;
+        write_operation(controller, Page::ChannelB, Operation::ImmediateOff)
+    }
+
+    fn enter(&mut self, state: State) {
+        self.state = state;
+        self.since = None;
+    }
+}
+
+fn write_operation<D: PmbusDevice>(
+    controller: &mut D,
+    page: Page,
+    operation: Operation,
+) -> Result<(), VrmError> {
+    controller.select_page(page)?;
+    controller.write_byte(Command::Operation.to_address(), operation.to_bits())
+}
@@ -0,0 +1,343 @@
+// Settings kept across power cycles in a reserved region of flash
+//
+// Every save appends a fixed size record to a log spread over the region, so each erase block
+// is only erased once the log has filled it and wrapped around to it again. A record is
+//
+//     magic: [u8; 2] | version: u8 | len: u8 | seq: u32 (little endian) | settings | ... | crc: u16
+//
+// with the settings bincode encoded and the CRC-16 (the one the USB frames use) covering the rest
+// of the slot. At boot the valid record with the highest sequence number wins, so a write cut
+// short by a power loss only costs the save it was doing. With a single erase block the old
+// records are erased right before the log wraps, a power loss in between loses the settings, so
+// the region should be at least two blocks: the block erased next never holds the newest record.
+//
+// Erasing stalls the CPU (and with it the I2C bus and the sequencer) for as long as the flash
+// takes, so saves are rare: poll only writes settings that differ from the newest record once
+// they have stayed the same for SETTLE_US, a setpoint being tuned or a ramp in progress does not
+// wear the flash. Each block takes block / SLOT records before the log moves on to the next one.
+
+use core::ops::Range;
+
+use embedded_storage::nor_flash::{NorFlash, NorFlashError, NorFlashErrorKind};
+
+use crate::control::write_setpoint;
+use crate::margin::Margin;
+use crate::navigation::{Device, Navigation};
+use crate::pmbus::VrmError;
+use crate::profile::Profiles;
+use crate::protocol::crc16;
+use crate::ramp::Ramp;
+use crate::vrm_controller::TPSC536C7;
+
+/// Layout version of Settings, records of any other version are ignored
+pub const VERSION: u8 = 4;
+
+/// Bytes each record takes up in flash
+pub const SLOT: usize = 256;
+
+/// Time changed settings have to stay the same before poll saves them, in microseconds
+pub const SETTLE_US: u32 = 5_000_000;
+
+/// Marks the start of a record
+const MAGIC: [u8; 2] = *b"PS";
+/// Magic, version, length and sequence number
+const HEADER_LEN: usize = 8;
+const CRC_LEN: usize = 2;
+
+/// Everything restored at boot
+#[derive(Clone, Copy, Debug, Default, PartialEq, bincode::Encode, bincode::Decode)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Settings {
+    pub core: RailSettings,
+    pub mem: RailSettings,
+    pub profiles: Profiles,
+    pub preferences: Preferences,
+}
+
+/// Setpoints of one channel
+#[derive(Clone, Copy, Debug, Default, PartialEq, bincode::Encode, bincode::Decode)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct RailSettings {
+    /// VOUT_COMMAND in volts
+    pub voltage: f32,
+    /// IOUT_OC_FAULT_LIMIT in amps
+    pub current_limit: f32,
+}
+
+/// Front panel and ramp preferences kept alongside the setpoints
+#[derive(Clone, Copy, Debug, Default, PartialEq, bincode::Encode, bincode::Decode)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Preferences {
+    /// Front panel cursor, (channel, row)
+    pub cursor: (u8, u8),
+    /// Ramp rate of each channel in mV/ms, see ramp::Ramp
+    pub ramp_rates: [f32; 2],
+    /// Margin of each channel in percent, see margin::Margin
+    pub margin_percent: [f32; 2],
+}
+
+impl Preferences {
+    /// Takes the cursor of nav, the ramp rates and the margins
+    pub fn capture(nav: &Navigation, ramp: &Ramp, margin: &Margin) -> Preferences {
+        let (x, y) = nav.get_position();
+        Preferences {
+            cursor: (x as u8, y as u8),
+            ramp_rates: [ramp.rate(0), ramp.rate(1)],
+            margin_percent: [margin.percent(0), margin.percent(1)],
+        }
+    }
+
+    /// Moves the cursor back to where it was and sets the ramp rates and margins again. A rate
+    /// or margin out of range keeps the default
+    pub fn apply(&self, nav: &mut Navigation, ramp: &mut Ramp, margin: &mut Margin) {
+        nav.set_position((self.cursor.0 as i32, self.cursor.1 as i32));
+        for channel in 0..2 {
+            let _ = ramp.set_rate(channel, self.ramp_rates[channel]);
+            let _ = margin.set_percent(channel, self.margin_percent[channel]);
+        }
+    }
+}
+
+impl Settings {
+    /// Takes the setpoints of dev, the profiles and preferences, None while the last read of a
+    /// channel failed as its setpoints are not known
+    pub fn capture(
+        dev: &mut Device,
+        profiles: &Profiles,
+        preferences: Preferences,
+    ) -> Option<Settings> {
+        if dev.core().get_error() || dev.mem().get_error() {
+            return None;
+        }
+        Some(Settings {
+            core: RailSettings {
+                voltage: dev.core().get_voltage_setpoint(),
+                current_limit: dev.core().get_current_limit(),
+            },
+            mem: RailSettings {
+                voltage: dev.mem().get_voltage_setpoint(),
+                current_limit: dev.mem().get_current_limit(),
+            },
+            profiles: *profiles,
+            preferences,
+        })
+    }
+
+    /// Writes the setpoints to the controller, the way the front panel does. Stops at the first
+    /// setpoint the controller refuses
+    pub fn apply<I: embedded_hal::i2c::I2c>(
+        &self,
+        controller: &mut TPSC536C7<I>,
+        dev: &mut Device,
+    ) -> Result<(), VrmError> {
+        for (channel, rail) in [(0, &self.core), (1, &self.mem)] {
+            for (row, val) in [(0, rail.voltage), (1, rail.current_limit)] {
+                write_setpoint(controller, (channel, row), val)?;
+                dev.store_value((channel, row), val);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Why the settings could not be loaded or saved
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SettingsError {
+    /// The region is not made of whole erase blocks, or a slot is not a whole number of writes
+    NotAligned,
+    /// The region lies outside the flash
+    OutOfBounds,
+    /// Any other error of the flash driver
+    Flash,
+    /// The settings do not fit in a slot
+    Encode,
+}
+
+impl<E: NorFlashError> From<E> for SettingsError {
+    fn from(err: E) -> SettingsError {
+        match err.kind() {
+            NorFlashErrorKind::NotAligned => SettingsError::NotAligned,
+            NorFlashErrorKind::OutOfBounds => SettingsError::OutOfBounds,
+            _ => SettingsError::Flash,
+        }
+    }
+}
+
+/// Wear leveled settings log in a region of flash
+///
+/// The region and block size are offsets and lengths as the flash driver counts them. block is
+/// the size the flash actually erases at once, which on parts with uneven sectors can be more
+/// than the driver's ERASE_SIZE
+#[derive(Debug)]
+pub struct SettingsStore {
+    region: Range<u32>,
+    block: u32,
+    // Offset of the slot the next record goes to
+    next: u32,
+    // Sequence number of the newest record
+    seq: u32,
+    // The newest record, saving it again is skipped
+    saved: Option<Settings>,
+    // Settings waiting to settle before poll saves them, and since when
+    pending: Option<(Settings, u32)>,
+}
+
+impl SettingsStore {
+    pub fn new(region: Range<u32>, block: u32) -> SettingsStore {
+        SettingsStore {
+            next: region.start,
+            region,
+            block,
+            seq: 0,
+            saved: None,
+            pending: None,
+        }
+    }
+
+    /// Scans the log for the newest valid record, None if there is none
+    pub fn load<F: NorFlash>(&mut self, flash: &mut F) -> Result<Option<Settings>, SettingsError> {
+        self.check::<F>()?;
+        let mut newest: Option<(u32, u32, Settings)> = None;
+        let mut slot = [0u8; SLOT];
+        for offset in (self.region.start..self.region.end).step_by(SLOT) {
+            flash.read(offset, &mut slot)?;
+            let Some((seq, settings)) = decode(&slot) else {
+                continue;
+            };
+            if !matches!(newest, Some((newest, ..)) if newest >= seq) {
+                newest = Some((seq, offset, settings));
+            }
+        }
+
+        let Some((seq, offset, settings)) = newest else {
+            return Ok(None);
+        };
+        self.seq = seq;
+        self.next = offset + SLOT as u32;
+        self.saved = Some(settings);
+        Ok(Some(settings))
+    }
+
+    /// Saves settings once they have been the same for SETTLE_US, returning true when a record
+    /// was written. Called with the current settings on every pass
+    pub fn poll<F: NorFlash>(
+        &mut self,
+        flash: &mut F,
+        settings: &Settings,
+        now: u32,
+    ) -> Result<bool, SettingsError> {
+        if self.saved.as_ref() == Some(settings) {
+            self.pending = None;
+            return Ok(false);
+        }
+        match self.pending {
+            Some((pending, since)) if pending == *settings => {
+                if now.wrapping_sub(since) < SETTLE_US {
+                    return Ok(false);
+                }
+            }
+            _ => {
+                self.pending = Some((*settings, now));
+                return Ok(false);
+            }
+        }
+        self.pending = None;
+        self.save(flash, settings)?;
+        Ok(true)
+    }
+
+    /// Appends settings to the log, unless they match the newest record
+    ///
+    /// Erasing a block takes the flash a while (a quarter to half a second for a 16 KB sector of
+    /// the STM32F401, seconds for the large ones), which happens once every block size / SLOT
+    /// saves
+    pub fn save<F: NorFlash>(
+        &mut self,
+        flash: &mut F,
+        settings: &Settings,
+    ) -> Result<(), SettingsError> {
+        self.check::<F>()?;
+        if self.saved.as_ref() == Some(settings) {
+            return Ok(());
+        }
+        let seq = self.seq.wrapping_add(1);
+        let record = encode(seq, settings)?;
+
+        // Slots after the newest record are normally still erased, a used one is left over from
+        // a cut off write. The block the log runs into next holds the oldest records
+        let mut slot = [0u8; SLOT];
+        loop {
+            if self.next >= self.region.end {
+                self.next = self.region.start;
+            }
+            flash.read(self.next, &mut slot)?;
+            if slot.iter().all(|&byte| byte == 0xFF) {
+                break;
+            }
+            if (self.next - self.region.start).is_multiple_of(self.block) {
+                flash.erase(self.next, self.next + self.block)?;
+                break;
+            }
+            self.next += SLOT as u32;
+        }
+
+        flash.write(self.next, &record)?;
+        self.next += SLOT as u32;
+        self.seq = seq;
+        self.saved = Some(*settings);
+        Ok(())
+    }
+
+    // The region has to be whole blocks the flash can erase, each holding whole slots
+    fn check<F: NorFlash>(&self) -> Result<(), SettingsError> {
+        let (start, end) = (self.region.start, self.region.end);
+        let aligned = self.block > 0
+            && (self.block as usize).is_multiple_of(F::ERASE_SIZE)
+            && (self.block as usize).is_multiple_of(SLOT)
+            && start.is_multiple_of(self.block)
+            && end.is_multiple_of(self.block)
+            && end > start
+            && SLOT.is_multiple_of(F::WRITE_SIZE)
+            && SLOT.is_multiple_of(F::READ_SIZE);
+        if aligned {
+            Ok(())
+        } else {
+            Err(SettingsError::NotAligned)
+        }
+    }
+}
+
+// Builds the record stored for settings
+fn encode(seq: u32, settings: &Settings) -> Result<[u8; SLOT], SettingsError> {
+    let mut slot = [0u8; SLOT];
+    let len = bincode::encode_into_slice(
+        settings,
+        &mut slot[HEADER_LEN..SLOT - CRC_LEN],
+        bincode::config::standard(),
+    )
+    .map_err(|_| SettingsError::Encode)?;
+    slot[..2].copy_from_slice(&MAGIC);
+    slot[2] = VERSION;
+    slot[3] = len as u8;
+    slot[4..HEADER_LEN].copy_from_slice(&seq.to_le_bytes());
+    let crc = crc16(&slot[..SLOT - CRC_LEN]);
+    slot[SLOT - CRC_LEN..].copy_from_slice(&crc.to_le_bytes());
+    Ok(slot)
+}
+
+// The sequence number and settings of a record, None for an erased, torn or foreign slot
+fn decode(slot: &[u8; SLOT]) -> Option<(u32, Settings)> {
+    let (data, crc) = slot.split_at(SLOT - CRC_LEN);
+    if data[..2] != MAGIC || data[2] != VERSION || crc16(data).to_le_bytes() != crc {
+        return None;
+    }
+    let len = (data[3] as usize).min(data.len() - HEADER_LEN);
+    let seq = u32::from_le_bytes([data[4], data[5], data[6], data[7]]);
+    let (settings, _) = bincode::decode_from_slice(
+        &data[HEADER_LEN..HEADER_LEN + len],
+        bincode::config::standard(),
+    )
+    .ok()?;
+    Some((seq, settings))
+}
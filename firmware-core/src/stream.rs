@@ -0,0 +1,128 @@
+use crate::pmbus::{Command, Page, PmbusDevice, VrmError};
+use crate::protocol::{ChannelSample, Request, RequestError, Response, Sample, StreamConfig};
+
+/// Schedules the samples of a host configured telemetry stream
+///
+/// Times are microseconds on a free running, wrapping u32 clock
+#[derive(Debug, Default)]
+pub struct Streamer {
+    config: Option<StreamConfig>,
+    period: u32,
+    next_due: u32,
+    overruns: u32,
+    seq: u16,
+}
+
+impl Streamer {
+    /// Handles the stream requests, None for any other request
+    pub fn handle(&mut self, request: &Request, now: u32) -> Option<Response> {
+        let result = match *request {
+            Request::StartStream(config) => self.start(config, now),
+            Request::StopStream => {
+                self.stop();
+                Ok(())
+            }
+            _ => return None,
+        };
+        Some(match result {
+            Ok(()) => Response::Ack,
+            Err(err) => Response::Error(err),
+        })
+    }
+
+    pub fn start(&mut self, config: StreamConfig, now: u32) -> Result<(), RequestError> {
+        self.period = config.period_us().ok_or(RequestError::InvalidRate)?;
+        info!("Stream: {} Hz, Fields {:#X}", config.rate_hz, config.fields);
+        self.config = Some(config);
+        self.next_due = now;
+        self.overruns = 0;
+        self.seq = 0;
+        Ok(())
+    }
+
+    pub fn stop(&mut self) {
+        self.config = None;
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.config.is_some()
+    }
+
+    /// Checks whether a sample is due, returning the tick it was due at. Whole periods that
+    /// went by without a poll are counted as overruns
+    pub fn poll(&mut self, now: u32) -> Option<u32> {
+        self.config?;
+        // Wrapping difference, negative while the next sample is still ahead
+        let late = now.wrapping_sub(self.next_due);
+        if (late as i32) < 0 {
+            return None;
+        }
+        let missed = late / self.period;
+        self.overruns = self.overruns.saturating_add(missed);
+        let due = self.next_due.wrapping_add(missed * self.period);
+        self.next_due = due.wrapping_add(self.period);
+        Some(due)
+    }
+
+    /// Reads a sample of the configured channels and fields, returning it with its frame
+    /// sequence number
+    pub fn sample<D: PmbusDevice>(&mut self, controller: &mut D, tick: u32) -> (u16, Sample) {
+        let config = self.config.unwrap_or_default();
+        let read = |controller: &mut D, enabled: bool, page: Page| {
+            enabled.then(|| read_channel(controller, &config, page))
+        };
+        let sample = Sample {
+            tick_us: tick,
+            overruns: self.overruns,
+            core: read(controller, config.core, Page::ChannelA),
+            mem: read(controller, config.mem, Page::ChannelB),
+        };
+        let seq = self.seq;
+        self.seq = self.seq.wrapping_add(1);
+        (seq, sample)
+    }
+
+    /// Counts a sample that could not be sent
+    pub fn dropped(&mut self) {
+        self.overruns = self.overruns.saturating_add(1);
+    }
+}
+
+/// Reads the configured fields of one channel, flagging it if a read failed
+pub fn read_channel<D: PmbusDevice>(
+    controller: &mut D,
+    config: &StreamConfig,
+    page: Page,
+) -> ChannelSample {
+    let mut chan = ChannelSample::default();
+    chan.error = read_fields(controller, config, page, &mut chan).is_err();
+    chan
+}
+
+// Fills in the configured fields, stopping at the first failed transaction
+fn read_fields<D: PmbusDevice>(
+    controller: &mut D,
+    config: &StreamConfig,
+    page: Page,
+    chan: &mut ChannelSample,
+) -> Result<(), VrmError> {
+    controller.select_page(page)?;
+    if config.has(StreamConfig::VOUT) {
+        chan.vout = Some(controller.read_vout_class(Command::ReadVout.to_address())?);
+    }
+    if config.has(StreamConfig::IOUT) {
+        chan.iout = Some(controller.read_linear11(Command::ReadIout.to_address())?);
+    }
+    if config.has(StreamConfig::TEMPERATURE) {
+        chan.temperature = Some(controller.read_linear11(Command::ReadTemperature1.to_address())?);
+    }
+    if config.has(StreamConfig::SETPOINTS) {
+        chan.vout_setpoint = Some(controller.read_vout_class(Command::VOUTCommand.to_address())?);
+        chan.current_limit =
+            Some(controller.read_linear11(Command::IoutOCFaultLimit.to_address())?);
+    }
+    if config.has(StreamConfig::STATUS) {
+        chan.status = Some(controller.status_word()?);
+    }
+    Ok(())
+}
@@ -0,0 +1,32 @@
+use embedded_hal::delay::DelayNs;
+use pmbus_types_rs::slinear11;
+
+use crate::pmbus::{
+    block_message, block_read_raw, read_raw, to_u16, to_value, write_raw, Command, Operation, Page,
+    PhaseTelemetry, PmbusDevice, VoutMode, VrmError, MAX_TRANSACTION,
+};
+use crate::safety::{self, Envelope, Rejection};
+use crate::vrm_status::{ChannelStatus, StatusByte};
+
+tps536c7_driver! {
+    bus: embedded_hal::i2c::I2c,
+    delay: DelayNs,
+    raw: [impl<I: embedded_hal::i2c::I2c> PmbusDevice for TPSC536C7<I>],
+    async: [],
+    await: [],
+}
+
+// Bus transactions the driver is built on, the PEC framing lives in pmbus
+impl<I: embedded_hal::i2c::I2c> TPSC536C7<I> {
+    fn bus_write(&mut self, data: &[u8]) -> Result<(), VrmError> {
+        write_raw(&mut self.i2c, self.address, self.pec, data)
+    }
+
+    fn bus_read(&mut self, cmd: u8, buf: &mut [u8]) -> Result<(), VrmError> {
+        read_raw(&mut self.i2c, self.address, self.pec, cmd, buf)
+    }
+
+    fn bus_block(&mut self, msg: &[u8], buf: &mut [u8]) -> Result<usize, VrmError> {
+        block_read_raw(&mut self.i2c, self.address, self.pec, msg, buf)
+    }
+}
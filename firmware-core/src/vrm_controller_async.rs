@@ -0,0 +1,106 @@
+// Async variant of the TPS536C7 driver, enabled with the `async` feature
+//
+// Same command API as vrm_controller::TPSC536C7 over embedded_hal_async::i2c::I2c, so bus
+// transactions yield to the executor instead of stalling the UI and USB. Both drivers are expanded
+// from tps536c7_driver!, only the bus transactions and what PmbusDevice gives the blocking driver
+// for free are written here.
+
+use embedded_hal::i2c::Error;
+use embedded_hal_async::delay::DelayNs;
+use pmbus_types_rs::slinear11;
+
+use crate::pmbus::{
+    block_message, check_block, check_read, read_response, to_u16, to_value, write_message,
+    Command, Operation, Page, PhaseTelemetry, Telemetry, VoutMode, VrmError, MAX_BLOCK,
+    MAX_TRANSACTION,
+};
+use crate::safety::{self, Envelope, Rejection};
+use crate::vrm_status::{ChannelStatus, StatusByte, StatusWord};
+
+tps536c7_driver! {
+    bus: embedded_hal_async::i2c::I2c,
+    delay: DelayNs,
+    raw: [impl<I: embedded_hal_async::i2c::I2c> TPSC536C7<I>] pub,
+    async: [async],
+    await: [.await],
+}
+
+impl<I: embedded_hal_async::i2c::I2c> TPSC536C7<I> {
+    /// Gives back the bus, e.g. to hand it to the blocking driver
+    pub fn release(self) -> I {
+        self.i2c
+    }
+
+    // Appends the PEC byte if enabled
+    async fn bus_write(&mut self, data: &[u8]) -> Result<(), VrmError> {
+        let mut buf = [b'\0'; MAX_TRANSACTION];
+        let msg = write_message(self.address, self.pec, data, &mut buf)?;
+        self.i2c
+            .write(self.address, msg)
+            .await
+            .map_err(|err| VrmError::from(err.kind()))
+    }
+
+    // Reads and checks the PEC byte if enabled
+    async fn bus_read(&mut self, cmd: u8, buf: &mut [u8]) -> Result<(), VrmError> {
+        let mut resp = [b'\0'; MAX_TRANSACTION];
+        let resp = read_response(self.pec, buf.len(), &mut resp)?;
+        self.i2c
+            .write_read(self.address, &[cmd], resp)
+            .await
+            .map_err(|err| VrmError::from(err.kind()))?;
+        check_read(self.address, self.pec, cmd, resp, buf)
+    }
+
+    async fn bus_block(&mut self, msg: &[u8], buf: &mut [u8]) -> Result<usize, VrmError> {
+        // The count is unknown up front, so read the longest block and ignore what trails it
+        let mut resp = [b'\0'; MAX_BLOCK + 2];
+        self.i2c
+            .write_read(self.address, msg, &mut resp)
+            .await
+            .map_err(|err| VrmError::from(err.kind()))?;
+        check_block(self.address, self.pec, msg, &resp, buf)
+    }
+
+    pub async fn clear_faults(&mut self) -> Result<(), VrmError> {
+        self.send_byte(Command::ClearFaults.to_address()).await
+    }
+
+    // STATUS COMMANDS
+    /// Reads the summary status byte for the paged channel
+    pub async fn status_byte(&mut self) -> Result<StatusByte, VrmError> {
+        Ok(StatusByte::from_bits(
+            self.read_byte(Command::StatusByte.to_address()).await?,
+        ))
+    }
+
+    /// Reads the full summary status word for the paged channel
+    pub async fn status_word(&mut self) -> Result<StatusWord, VrmError> {
+        Ok(StatusWord::from_bits(
+            self.read_word(Command::StatusWord.to_address()).await?,
+        ))
+    }
+
+    // IDENTIFICATION
+    pub async fn mfr_id<'b>(&mut self, buf: &'b mut [u8]) -> Result<&'b [u8], VrmError> {
+        self.block_read(Command::MfrId.to_address(), buf).await
+    }
+
+    pub async fn mfr_model<'b>(&mut self, buf: &'b mut [u8]) -> Result<&'b [u8], VrmError> {
+        self.block_read(Command::MfrModel.to_address(), buf).await
+    }
+
+    pub async fn mfr_revision<'b>(&mut self, buf: &'b mut [u8]) -> Result<&'b [u8], VrmError> {
+        self.block_read(Command::MfrRevision.to_address(), buf)
+            .await
+    }
+
+    /// Reads the output voltage, current and temperature of the paged channel
+    pub async fn telemetry(&mut self) -> Result<Telemetry, VrmError> {
+        Ok(Telemetry {
+            vout: self.read_vout().await?,
+            iout: self.read_iout().await?,
+            temperature: self.read_temperature_1().await?,
+        })
+    }
+}
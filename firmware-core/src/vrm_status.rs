@@ -0,0 +1,205 @@
+/// An abstracted way to generate PMBus status registers as bitflag types.
+///
+/// Type, Underlying integer, { Flag name = bit position, ... }
+macro_rules! status_register {
+    ($(#[$meta:meta])* $type:ident, $bits:ty, { $($(#[$fmeta:meta])* $flag:ident = $bit:expr),* $(,)? }) => {
+        $(#[$meta])*
+        #[derive(Clone, Copy, Debug, Default, PartialEq, bincode::Decode, bincode::Encode)]
+        pub struct $type($bits);
+
+        impl $type {
+            $(
+                $(#[$fmeta])*
+                pub const $flag: $bits = 1 << $bit;
+            )*
+
+            pub fn from_bits(bits: $bits) -> Self {
+                Self(bits)
+            }
+
+            pub fn bits(&self) -> $bits {
+                self.0
+            }
+
+            /// True if any of the bits in `flags` are set
+            pub fn contains(&self, flags: $bits) -> bool {
+                self.0 & flags != 0
+            }
+        }
+
+        #[cfg(feature = "defmt")]
+        impl defmt::Format for $type {
+            fn format(&self, f: defmt::Formatter) {
+                defmt::write!(f, "{=str}(", stringify!($type));
+                $(
+                    if self.contains(Self::$flag) {
+                        defmt::write!(f, " {=str}", stringify!($flag));
+                    }
+                )*
+                defmt::write!(f, " )");
+            }
+        }
+    };
+}
+
+status_register!(
+    /// STATUS_BYTE (0x78), the low byte of STATUS_WORD
+    StatusByte, u8, {
+        NONE_OF_THE_ABOVE = 0,
+        CML = 1,
+        TEMPERATURE = 2,
+        VIN_UV = 3,
+        IOUT_OC = 4,
+        VOUT_OV = 5,
+        OFF = 6,
+        BUSY = 7,
+    }
+);
+
+status_register!(
+    /// STATUS_WORD (0x79), summary of every status sub-register for the paged channel
+    StatusWord, u16, {
+        NONE_OF_THE_ABOVE = 0,
+        CML = 1,
+        TEMPERATURE = 2,
+        VIN_UV = 3,
+        IOUT_OC = 4,
+        VOUT_OV = 5,
+        OFF = 6,
+        BUSY = 7,
+        UNKNOWN = 8,
+        OTHER = 9,
+        FANS = 10,
+        /// Set when the output is NOT power good
+        POWER_GOOD_N = 11,
+        MFR_SPECIFIC = 12,
+        INPUT = 13,
+        IOUT_POUT = 14,
+        VOUT = 15,
+    }
+);
+
+impl StatusWord {
+    /// Bits that indicate a latched fault or warning (not just the output being off or busy)
+    pub const FAULT_MASK: u16 = Self::CML
+        | Self::TEMPERATURE
+        | Self::VIN_UV
+        | Self::IOUT_OC
+        | Self::VOUT_OV
+        | Self::UNKNOWN
+        | Self::OTHER
+        | Self::FANS
+        | Self::MFR_SPECIFIC
+        | Self::INPUT
+        | Self::IOUT_POUT
+        | Self::VOUT;
+
+    pub fn status_byte(&self) -> StatusByte {
+        StatusByte::from_bits(self.0 as u8)
+    }
+
+    pub fn is_faulted(&self) -> bool {
+        self.contains(Self::FAULT_MASK)
+    }
+
+    pub fn is_power_good(&self) -> bool {
+        !self.contains(Self::POWER_GOOD_N)
+    }
+}
+
+status_register!(
+    /// STATUS_VOUT (0x7A)
+    StatusVout, u8, {
+        VOUT_TRACKING_ERROR = 0,
+        TOFF_MAX_WARN = 1,
+        TON_MAX_FAULT = 2,
+        VOUT_MAX_MIN_WARN = 3,
+        VOUT_UV_FAULT = 4,
+        VOUT_UV_WARN = 5,
+        VOUT_OV_WARN = 6,
+        VOUT_OV_FAULT = 7,
+    }
+);
+
+status_register!(
+    /// STATUS_IOUT (0x7B)
+    StatusIout, u8, {
+        POUT_OP_WARN = 0,
+        POUT_OP_FAULT = 1,
+        POWER_LIMIT = 2,
+        CURRENT_SHARE_FAULT = 3,
+        IOUT_UC_FAULT = 4,
+        IOUT_OC_WARN = 5,
+        IOUT_OC_LV_FAULT = 6,
+        IOUT_OC_FAULT = 7,
+    }
+);
+
+status_register!(
+    /// STATUS_INPUT (0x7C)
+    StatusInput, u8, {
+        PIN_OP_WARN = 0,
+        IIN_OC_WARN = 1,
+        IIN_OC_FAULT = 2,
+        UNIT_OFF_LOW_VIN = 3,
+        VIN_UV_FAULT = 4,
+        VIN_UV_WARN = 5,
+        VIN_OV_WARN = 6,
+        VIN_OV_FAULT = 7,
+    }
+);
+
+status_register!(
+    /// STATUS_TEMPERATURE (0x7D)
+    StatusTemperature, u8, {
+        UT_FAULT = 4,
+        UT_WARN = 5,
+        OT_WARN = 6,
+        OT_FAULT = 7,
+    }
+);
+
+status_register!(
+    /// STATUS_CML (0x7E), communication, logic and memory faults
+    StatusCml, u8, {
+        OTHER_MEMORY_FAULT = 0,
+        OTHER_COMM_FAULT = 1,
+        PROCESSOR_FAULT = 3,
+        MEMORY_FAULT = 4,
+        PEC_FAILED = 5,
+        INVALID_DATA = 6,
+        INVALID_COMMAND = 7,
+    }
+);
+
+/// Every status register for a single channel, as returned by STATUS_ALL
+#[derive(Clone, Copy, Debug, Default, PartialEq, bincode::Decode, bincode::Encode)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ChannelStatus {
+    pub word: StatusWord,
+    pub vout: StatusVout,
+    pub iout: StatusIout,
+    pub input: StatusInput,
+    pub temperature: StatusTemperature,
+    pub cml: StatusCml,
+    pub mfr_specific: u8,
+}
+
+impl ChannelStatus {
+    /// Number of bytes one channel occupies in the STATUS_ALL block
+    pub const LENGTH: usize = 8;
+
+    /// Decodes one channel from STATUS_ALL: STATUS_WORD (little endian), then STATUS_VOUT,
+    /// STATUS_IOUT, STATUS_INPUT, STATUS_TEMPERATURE, STATUS_CML and STATUS_MFR_SPECIFIC
+    pub fn from_bytes(buf: &[u8; Self::LENGTH]) -> ChannelStatus {
+        ChannelStatus {
+            word: StatusWord::from_bits(u16::from_le_bytes([buf[0], buf[1]])),
+            vout: StatusVout::from_bits(buf[2]),
+            iout: StatusIout::from_bits(buf[3]),
+            input: StatusInput::from_bits(buf[4]),
+            temperature: StatusTemperature::from_bits(buf[5]),
+            cml: StatusCml::from_bits(buf[6]),
+            mfr_specific: buf[7],
+        }
+    }
+}
@@ -0,0 +1,109 @@
+#![cfg(feature = "async")]
+
+use std::future::Future;
+use std::pin::pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+
+use embedded_hal::i2c::{ErrorType, I2c, Operation};
+use firmware_core::pmbus::{Page, VrmError};
+use firmware_core::vrm_controller_async::TPSC536C7;
+use tps536c7_simulator::{Channel, Fault, Tps536c7, ADDRESS};
+
+/// Exposes the blocking simulator as an async bus, every transaction completes immediately
+struct AsyncBus(Tps536c7);
+
+impl ErrorType for AsyncBus {
+    type Error = <Tps536c7 as ErrorType>::Error;
+}
+
+impl embedded_hal_async::i2c::I2c for AsyncBus {
+    async fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        self.0.transaction(address, operations)
+    }
+}
+
+struct NoDelay;
+
+impl embedded_hal_async::delay::DelayNs for NoDelay {
+    async fn delay_ns(&mut self, _ns: u32) {}
+}
+
+struct NoopWaker;
+
+impl Wake for NoopWaker {
+    fn wake(self: Arc<Self>) {}
+}
+
+/// Polls a future that never has to wait to completion
+fn block_on<F: Future>(future: F) -> F::Output {
+    let waker = Waker::from(Arc::new(NoopWaker));
+    let mut cx = Context::from_waker(&waker);
+    let mut future = pin!(future);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+    }
+}
+
+fn controller(sim: Tps536c7) -> TPSC536C7<AsyncBus> {
+    let mut controller = TPSC536C7::new(AsyncBus(sim), ADDRESS, true);
+    block_on(controller.init()).unwrap();
+    controller
+}
+
+#[test]
+fn reads_telemetry() {
+    let mut sim = Tps536c7::default();
+    sim.set_load(Channel::B, 30.);
+    let mut controller = controller(sim);
+
+    let telemetry = block_on(async {
+        controller.page(Page::ChannelB).await?;
+        controller.telemetry().await
+    })
+    .unwrap();
+
+    assert!((telemetry.vout - 1.35).abs() < 0.01);
+    assert!((telemetry.iout - 30.).abs() < 0.01);
+}
+
+#[test]
+fn writes_setpoints() {
+    let mut controller = controller(Tps536c7::default());
+
+    let vout = block_on(async {
+        controller.ch_a().await?.vout_command().write(1.05).await?;
+        controller.vout_command().read().await
+    })
+    .unwrap();
+
+    assert!((vout - 1.05).abs() < 0.01);
+}
+
+#[test]
+fn walks_the_phases() {
+    let mut controller = controller(Tps536c7::default());
+    let mut phases = [Default::default(); 8];
+
+    let count = block_on(controller.read_phase_telemetry(Page::ChannelA, &mut phases)).unwrap();
+
+    assert_eq!(count, 6);
+}
+
+#[test]
+fn nvm_store_is_refused_while_faulted() {
+    let mut sim = Tps536c7::default();
+    sim.inject_fault(Channel::A, Fault::OverCurrent);
+    let mut controller = controller(sim);
+
+    assert_eq!(
+        block_on(controller.store_default_all(&mut NoDelay)),
+        Err(VrmError::FaultLatched)
+    );
+}
@@ -0,0 +1,37 @@
+// Fixtures shared by the integration tests, pulled in with `mod common;`. host-lib and host
+// include this file too, so every test drives the simulator the same way
+#![allow(dead_code)]
+
+use embedded_hal::delay::DelayNs;
+use firmware_core::pmbus::Page;
+use firmware_core::vrm_controller::TPSC536C7;
+use tps536c7_simulator::{Tps536c7, ADDRESS};
+
+/// The simulator has no notion of time, so waiting is a no-op
+pub struct NoDelay;
+
+impl DelayNs for NoDelay {
+    fn delay_ns(&mut self, _ns: u32) {}
+}
+
+/// An initialised driver for sim, with PEC
+pub fn controller(sim: Tps536c7) -> TPSC536C7<Tps536c7> {
+    let mut controller = TPSC536C7::new(sim, ADDRESS, true);
+    controller.init().unwrap();
+    controller
+}
+
+/// True while OPERATION has the output of page on
+pub fn is_on(controller: &mut TPSC536C7<Tps536c7>, page: Page) -> bool {
+    controller
+        .page(page)
+        .and_then(|c| c.read_operation())
+        .is_ok_and(|operation| operation.is_on())
+}
+
+pub fn assert_close(actual: f32, expected: f32) {
+    assert!(
+        (actual - expected).abs() < 0.01,
+        "{actual} is not close to {expected}"
+    );
+}
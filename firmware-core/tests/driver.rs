@@ -0,0 +1,378 @@
+mod common;
+
+use common::{assert_close, controller, NoDelay};
+use firmware_core::control::{handle_request, update_phase_read, update_vrm_read, write_setpoint};
+use firmware_core::navigation::Device;
+use firmware_core::pmbus::{
+    Command, Controller, ControllerKind, MarginFault, Operation, Page, PmbusDevice, VrmError,
+};
+use firmware_core::protocol::{Format, Register, Request, RequestError, Response, NVM_CONFIRM};
+use firmware_core::vrm_controller::TPSC536C7;
+use tps536c7_simulator::{Channel, Fault, Tps536c7, ADDRESS};
+
+#[test]
+fn identifies_as_a_tps536c7() {
+    let mut controller = controller(Tps536c7::default());
+    assert_eq!(
+        ControllerKind::read(&mut controller),
+        Ok(ControllerKind::Tps536c7)
+    );
+    assert!(matches!(
+        Controller::probe(Tps536c7::default(), ADDRESS, true),
+        Controller::Tps536c7(_)
+    ));
+}
+
+#[test]
+fn other_ti_controllers_only_get_the_generic_driver() {
+    assert_eq!(
+        ControllerKind::identify(b"TI", b"TPS536C7B1"),
+        ControllerKind::Tps536c7
+    );
+    assert_eq!(
+        ControllerKind::identify(b"TI", b"TPS53688"),
+        ControllerKind::Generic
+    );
+    assert_eq!(
+        ControllerKind::identify(b"TIX", b"TPS536C7"),
+        ControllerKind::Generic
+    );
+
+    let mut sim = Tps536c7::default();
+    sim.set_mfr_model(b"TPS53681");
+    assert!(matches!(
+        Controller::probe(sim, ADDRESS, true),
+        Controller::Generic(_)
+    ));
+}
+
+#[test]
+fn reads_telemetry_of_both_pages() {
+    let mut sim = Tps536c7::default();
+    sim.set_load(Channel::A, 100.);
+    sim.set_load(Channel::B, 20.);
+    let mut controller = controller(sim);
+
+    let core = controller
+        .page(Page::ChannelA)
+        .unwrap()
+        .telemetry()
+        .unwrap();
+    assert_close(core.vout, 0.9);
+    assert_close(core.iout, 100.);
+    assert_close(core.temperature, 50.);
+
+    let mem = controller
+        .page(Page::ChannelB)
+        .unwrap()
+        .telemetry()
+        .unwrap();
+    assert_close(mem.vout, 1.35);
+    assert_close(mem.iout, 20.);
+}
+
+#[test]
+fn setpoint_writes_reach_the_controller() {
+    let mut controller = controller(Tps536c7::default());
+
+    write_setpoint(&mut controller, (1, 0), 1.25).unwrap();
+    write_setpoint(&mut controller, (0, 1), 150.).unwrap();
+
+    assert_close(controller.ch_b().unwrap().read_vout().unwrap(), 1.25);
+    assert_close(
+        controller
+            .ch_a()
+            .unwrap()
+            .iout_oc_fault_limit()
+            .read()
+            .unwrap(),
+        150.,
+    );
+}
+
+#[test]
+fn vid_mode_setpoints_round_trip() {
+    let mut sim = Tps536c7::default();
+    // VR13 table, 10 mV steps
+    sim.set_vout_mode(Channel::B, 0x22);
+    let mut controller = controller(sim);
+
+    controller
+        .ch_b()
+        .unwrap()
+        .vout_command()
+        .write(1.2)
+        .unwrap();
+    assert_close(controller.vout_command().read().unwrap(), 1.2);
+}
+
+#[test]
+fn operation_switches_one_channel() {
+    let mut controller = controller(Tps536c7::default());
+
+    let c = controller.ch_a().unwrap();
+    c.operation(Operation::ImmediateOff).unwrap();
+    assert_eq!(c.read_operation().unwrap(), Operation::ImmediateOff);
+    assert_close(c.read_vout().unwrap(), 0.);
+    let c = controller.ch_b().unwrap();
+    assert_eq!(c.read_operation().unwrap(), Operation::On);
+    assert_close(c.read_vout().unwrap(), 1.35);
+
+    for operation in [
+        Operation::On,
+        Operation::SoftOff,
+        Operation::ImmediateOff,
+        Operation::MarginHigh(MarginFault::Act),
+        Operation::MarginHigh(MarginFault::Ignore),
+        Operation::MarginLow(MarginFault::Act),
+        Operation::MarginLow(MarginFault::Ignore),
+    ] {
+        assert_eq!(Operation::from_bits(operation.to_bits()), Ok(operation));
+    }
+    assert_eq!(Operation::from_bits(0xB0), Err(VrmError::InvalidData));
+}
+
+#[test]
+fn update_vrm_read_fills_the_device() {
+    let mut sim = Tps536c7::default();
+    sim.set_load(Channel::A, 50.);
+    let mut controller = controller(sim);
+    let mut dev = Device::default();
+
+    update_vrm_read(&mut dev, &mut controller);
+
+    assert!(!dev.core().get_error());
+    assert_close(dev.core().get_voltage_setpoint(), 0.9);
+    assert_close(dev.core().get_current(), 50.);
+    assert_close(dev.core().get_current_limit(), 200.);
+    assert_close(dev.mem().get_current_limit(), 60.);
+    assert!(dev.core().get_status().is_power_good());
+}
+
+#[test]
+fn failed_reads_flag_the_channel() {
+    let mut sim = Tps536c7::default();
+    // Only the first transaction, selecting channel A, fails
+    sim.inject_nack(1);
+    let mut controller = TPSC536C7::new(sim, ADDRESS, true);
+    let mut dev = Device::default();
+
+    update_vrm_read(&mut dev, &mut controller);
+
+    assert!(dev.core().get_error());
+    assert!(!dev.mem().get_error());
+}
+
+#[test]
+fn over_current_trips_the_output() {
+    let mut sim = Tps536c7::default();
+    sim.set_load(Channel::A, 250.);
+    let mut controller = controller(sim);
+    let mut dev = Device::default();
+
+    update_vrm_read(&mut dev, &mut controller);
+
+    let status = dev.core().get_status();
+    assert!(status.is_faulted());
+    assert!(!status.is_power_good());
+    assert_close(dev.core().get_voltage(), 0.);
+    assert!(!dev.mem().get_status().is_faulted());
+}
+
+#[test]
+fn phase_walk_stops_at_the_fitted_phases() {
+    let mut sim = Tps536c7::default();
+    sim.set_load(Channel::A, 60.);
+    let mut controller = controller(sim);
+    let mut dev = Device::default();
+
+    update_phase_read(&mut dev, &mut controller);
+
+    assert_eq!(dev.core().get_phases().len(), 6);
+    assert_eq!(dev.mem().get_phases().len(), 2);
+    assert_close(dev.core().get_phases()[0].current, 10.);
+    // PHASE is put back so the rail totals are read again
+    assert_close(controller.ch_a().unwrap().read_iout().unwrap(), 60.);
+}
+
+#[test]
+fn mfr_specific_offsets_stop_at_the_last_register() {
+    assert_eq!(Command::mfr_specific(0x2D).unwrap().to_address(), 0xFD);
+    assert_eq!(
+        Command::mfr_specific(0x30),
+        Err(VrmError::UnsupportedCommand)
+    );
+
+    // Refused before anything is sent
+    let mut controller = controller(Tps536c7::default());
+    assert_eq!(
+        controller.mfr_specific(0xFF).write(0x1234),
+        Err(VrmError::UnsupportedCommand)
+    );
+    assert_eq!(
+        controller.mfr_specific(0x2E).read(),
+        Err(VrmError::UnsupportedCommand)
+    );
+}
+
+#[test]
+fn nvm_store_is_refused_while_faulted() {
+    let mut sim = Tps536c7::default();
+    sim.inject_fault(Channel::B, Fault::OverTemperature);
+    let mut controller = controller(sim);
+
+    assert_eq!(
+        controller.store_default_all(&mut NoDelay),
+        Err(VrmError::FaultLatched)
+    );
+
+    controller.ch_ab().unwrap().clear_faults().unwrap();
+    assert_eq!(controller.store_default_all(&mut NoDelay), Ok(()));
+}
+
+#[test]
+fn restore_brings_back_the_stored_setpoint() {
+    let mut controller = controller(Tps536c7::default());
+    controller
+        .ch_a()
+        .unwrap()
+        .vout_command()
+        .write(1.0)
+        .unwrap();
+    controller.store_default_all(&mut NoDelay).unwrap();
+
+    controller
+        .ch_a()
+        .unwrap()
+        .vout_command()
+        .write(0.8)
+        .unwrap();
+    controller.restore_default_all(&mut NoDelay).unwrap();
+
+    assert_close(
+        controller.ch_a().unwrap().vout_command().read().unwrap(),
+        1.0,
+    );
+}
+
+#[test]
+fn writes_without_pec_are_rejected_when_required() {
+    // A driver without PEC talking to a controller that insists on it
+    let mut sim = Tps536c7::default();
+    sim.require_pec(true);
+    let mut controller = TPSC536C7::new(sim, ADDRESS, false);
+
+    assert_eq!(
+        controller.page(Page::ChannelB).map(|_| ()),
+        Err(VrmError::UnsupportedCommand)
+    );
+}
+
+#[test]
+fn usb_requests_are_answered() {
+    let mut controller = controller(Tps536c7::default());
+    let mut request = |request| handle_request(&mut controller, &mut NoDelay, &request);
+
+    assert_eq!(request(Request::Ping), Response::Pong);
+    // VOUT_COMMAND = 1.0 V in the default 2^-9 linear mode
+    assert_eq!(
+        request(Request::Write {
+            page: 1,
+            data: &[0x21, 0x00, 0x02]
+        }),
+        Response::Ack
+    );
+    assert_eq!(
+        request(Request::Nvm {
+            cmd: 0x11,
+            confirm: 0x00
+        }),
+        Response::Error(RequestError::NotConfirmed)
+    );
+    assert_eq!(
+        request(Request::Write {
+            page: 0,
+            data: &[0x11]
+        }),
+        Response::Error(RequestError::Refused)
+    );
+    assert_eq!(
+        request(Request::Nvm {
+            cmd: 0x11,
+            confirm: NVM_CONFIRM
+        }),
+        Response::Ack
+    );
+
+    assert_close(
+        controller.ch_b().unwrap().vout_command().read().unwrap(),
+        1.0,
+    );
+}
+
+#[test]
+fn usb_reads_return_raw_and_decoded_values() {
+    let mut sim = Tps536c7::default();
+    sim.set_load(Channel::A, 50.);
+    let mut controller = controller(sim);
+    let mut read = |page, cmd, len, format| {
+        handle_request(
+            &mut controller,
+            &mut NoDelay,
+            &Request::Read {
+                page,
+                cmd,
+                len,
+                format,
+            },
+        )
+    };
+
+    // VOUT_COMMAND of channel B, 1.35 V in 2^-9 steps
+    let Response::Register(reg) = read(1, 0x21, 2, Format::Vout) else {
+        panic!("Expected a register");
+    };
+    assert_eq!(reg.data(), 691u16.to_le_bytes());
+    assert_close(reg.value.unwrap(), 1.35);
+
+    // READ_IOUT of channel A
+    let Response::Register(reg) = read(0, 0x8C, 2, Format::Linear11) else {
+        panic!("Expected a register");
+    };
+    assert_close(reg.value.unwrap(), 50.);
+
+    // VOUT_MODE as a single raw byte
+    assert_eq!(
+        read(0, 0x20, 1, Format::Raw),
+        Response::Register(Register::new(&[0x17], None))
+    );
+
+    assert_eq!(
+        read(0, 0x8C, 1, Format::Linear11),
+        Response::Error(RequestError::InvalidLength)
+    );
+    assert_eq!(
+        read(0, 0x8C, 0, Format::Raw),
+        Response::Error(RequestError::InvalidLength)
+    );
+}
+
+#[test]
+fn failed_usb_reads_report_the_bus_error() {
+    let mut controller = controller(Tps536c7::default());
+
+    // 0xFE is not implemented, the controller NACKs it
+    assert_eq!(
+        handle_request(
+            &mut controller,
+            &mut NoDelay,
+            &Request::Read {
+                page: 0,
+                cmd: 0xFE,
+                len: 2,
+                format: Format::Raw
+            }
+        ),
+        Response::Error(RequestError::Vrm(VrmError::UnsupportedCommand))
+    );
+}
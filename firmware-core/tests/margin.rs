@@ -0,0 +1,155 @@
+mod common;
+
+use common::controller;
+use firmware_core::margin::{Level, Margin, SweepConfig, MAX_PERCENT};
+use firmware_core::pmbus::{Command, Page, PmbusDevice};
+use firmware_core::protocol::{Request, RequestError, Response};
+use firmware_core::sequence::{Ready, Sequencer};
+use firmware_core::vrm_controller::TPSC536C7;
+use tps536c7_simulator::Tps536c7;
+
+const BOTH: Ready = Ready {
+    core: true,
+    mem: true,
+};
+
+/// A controller with both rails brought up by the sequencer
+fn powered() -> (TPSC536C7<Tps536c7>, Sequencer) {
+    let mut controller = controller(Tps536c7::default());
+    let mut seq = Sequencer::default();
+    seq.init(&mut controller).unwrap();
+    seq.power_up(&mut controller).unwrap();
+    for now in 0..8 {
+        seq.poll(&mut controller, BOTH, now * 100);
+    }
+    assert!(seq.is_on());
+    (controller, seq)
+}
+
+fn vout(controller: &mut TPSC536C7<Tps536c7>, page: Page) -> f32 {
+    controller.select_page(page).unwrap();
+    controller
+        .read_vout_class(Command::ReadVout.to_address())
+        .unwrap()
+}
+
+#[test]
+fn margins_move_the_rail_around_its_setpoint() {
+    let (mut controller, seq) = powered();
+    let mut margin = Margin::default();
+
+    margin
+        .set_level(&mut controller, &seq, 0, Level::High)
+        .unwrap();
+    assert!((vout(&mut controller, Page::ChannelA) - 0.945).abs() < 0.005);
+    margin.set_percent(1, 10.).unwrap();
+    margin
+        .set_level(&mut controller, &seq, 1, Level::Low)
+        .unwrap();
+    assert!((vout(&mut controller, Page::ChannelB) - 1.215).abs() < 0.005);
+    assert_eq!(margin.level(1), Level::Low);
+
+    margin
+        .set_level(&mut controller, &seq, 0, Level::Nominal)
+        .unwrap();
+    assert!((vout(&mut controller, Page::ChannelA) - 0.9).abs() < 0.005);
+    // The setpoint itself is left alone
+    controller.select_page(Page::ChannelB).unwrap();
+    let setpoint = controller
+        .read_vout_class(Command::VOUTCommand.to_address())
+        .unwrap();
+    assert!((setpoint - 1.35).abs() < 0.005);
+}
+
+#[test]
+fn margining_needs_the_output_on() {
+    let (mut controller, mut seq) = powered();
+    let mut margin = Margin::default();
+    margin
+        .set_level(&mut controller, &seq, 0, Level::High)
+        .unwrap();
+
+    seq.power_down(&mut controller).unwrap();
+    margin.poll(&mut controller, &seq, 0);
+    assert_eq!(margin.level(0), Level::Nominal);
+    assert_eq!(
+        margin.set_level(&mut controller, &seq, 0, Level::Low),
+        Err(RequestError::OutputOff)
+    );
+    assert_eq!(
+        margin.set_percent(0, MAX_PERCENT + 1.),
+        Err(RequestError::InvalidMargin)
+    );
+}
+
+#[test]
+fn a_sweep_logs_every_step_and_ends_at_nominal() {
+    let (mut controller, seq) = powered();
+    let mut margin = Margin::default();
+    let config = SweepConfig {
+        core: true,
+        mem: false,
+        dwell_ms: 10,
+    };
+
+    assert_eq!(
+        margin.handle(&Request::StartMarginSweep(config), &mut controller, &seq),
+        Some(Response::Ack)
+    );
+    let mut now = 0;
+    let mut steps = [None; 3];
+    for step in &mut steps {
+        // The first poll of a step starts its dwell
+        assert_eq!(margin.poll(&mut controller, &seq, now), None);
+        assert_eq!(margin.poll(&mut controller, &seq, now + 9_000), None);
+        now += 10_000;
+        *step = margin.poll(&mut controller, &seq, now);
+    }
+    assert!(!margin.is_sweeping());
+
+    let levels = steps.map(|step| step.unwrap().level);
+    assert_eq!(levels, [Level::Low, Level::High, Level::Nominal]);
+    let vout = steps.map(|step| step.unwrap().sample.vout.unwrap());
+    assert!(vout[0] < 0.86 && vout[1] > 0.94 && (vout[2] - 0.9).abs() < 0.005);
+
+    let status = margin.status();
+    assert_eq!((status.sweep, status.logged), (None, 3));
+    assert_eq!(
+        margin.handle(&Request::GetSweepStep(1), &mut controller, &seq),
+        Some(Response::SweepStep(steps[1].unwrap()))
+    );
+    assert_eq!(
+        margin.handle(&Request::GetSweepStep(3), &mut controller, &seq),
+        Some(Response::Error(RequestError::InvalidMargin))
+    );
+}
+
+#[test]
+fn stopping_a_sweep_returns_to_nominal() {
+    let (mut controller, seq) = powered();
+    let mut margin = Margin::default();
+    let config = SweepConfig {
+        core: true,
+        mem: true,
+        dwell_ms: 1,
+    };
+    assert_eq!(
+        margin.start_sweep(&mut controller, &seq, config),
+        Err(RequestError::InvalidMargin)
+    );
+
+    margin
+        .start_sweep(
+            &mut controller,
+            &seq,
+            SweepConfig {
+                dwell_ms: 100,
+                ..config
+            },
+        )
+        .unwrap();
+    assert_eq!(margin.level(0), Level::Low);
+    margin.stop_sweep(&mut controller, &seq).unwrap();
+    assert_eq!(margin.level(0), Level::Nominal);
+    assert!((vout(&mut controller, Page::ChannelA) - 0.9).abs() < 0.005);
+}
@@ -0,0 +1,272 @@
+use firmware_core::margin::Level;
+use firmware_core::navigation::{Action, Button, Device, Mode, Navigation};
+use firmware_core::profile::PROFILES;
+use firmware_core::safety::Envelope;
+
+fn press_all(nav: &mut Navigation, dev: &mut Device, buttons: &[Button]) -> Action {
+    let mut action = Action::None;
+    for &button in buttons {
+        action = nav.press(button, dev);
+    }
+    action
+}
+
+#[test]
+fn movement_stays_inside_the_grid() {
+    let mut nav = Navigation::default();
+    let mut dev = Device::default();
+
+    // Up from the top row, left from the left column and down from the bottom row open other
+    // screens instead, see below
+    press_all(&mut nav, &mut dev, &[Button::Left]);
+    assert_eq!(nav.get_position(), (0, 0));
+    press_all(&mut nav, &mut dev, &[Button::Left]);
+
+    press_all(&mut nav, &mut dev, &[Button::Right; 4]);
+    press_all(&mut nav, &mut dev, &[Button::Down; 2]);
+    assert_eq!(nav.get_position(), (1, 2));
+    assert_eq!(*nav.get_mode(), Mode::Navigation);
+}
+
+#[test]
+fn enter_edits_the_setpoint_not_the_reading() {
+    let mut nav = Navigation::default();
+    let mut dev = Device::default();
+    dev.core().set_voltage(0.85);
+    dev.core().set_voltage_setpoint(0.9);
+
+    assert_eq!(nav.press(Button::Enter, &mut dev), Action::None);
+    assert_eq!(*nav.get_mode(), Mode::Update);
+    assert_eq!(nav.get_value(), 0.9);
+}
+
+#[test]
+fn update_mode_steps_and_writes_on_enter() {
+    let mut nav = Navigation::default();
+    let mut dev = Device::default();
+    dev.core().set_voltage_setpoint(1.0);
+
+    let action = press_all(
+        &mut nav,
+        &mut dev,
+        &[
+            Button::Enter,
+            Button::Up,
+            Button::Right,
+            Button::Down,
+            Button::Enter,
+        ],
+    );
+
+    let Action::Write(position, val) = action else {
+        panic!("expected a write, got {action:?}");
+    };
+    assert_eq!(position, (0, 0));
+    assert!((val - 1.1).abs() < 1e-5);
+    assert!((dev.core().get_voltage_setpoint() - 1.1).abs() < 1e-5);
+    assert_eq!(*nav.get_mode(), Mode::Navigation);
+}
+
+#[test]
+fn current_limit_uses_amp_steps() {
+    let mut nav = Navigation::default();
+    let mut dev = Device::default();
+    dev.mem().set_current_limit(40.);
+
+    let action = press_all(
+        &mut nav,
+        &mut dev,
+        &[
+            Button::Right,
+            Button::Down,
+            Button::Enter,
+            Button::Left,
+            Button::Up,
+            Button::Enter,
+        ],
+    );
+
+    assert_eq!(action, Action::Write((1, 1), 31.));
+    assert_eq!(dev.mem().get_current_limit(), 31.);
+    assert_eq!(dev.core().get_current_limit(), 0.);
+}
+
+#[test]
+fn temperature_opens_the_nvm_prompt() {
+    let mut nav = Navigation::default();
+    let mut dev = Device::default();
+
+    press_all(
+        &mut nav,
+        &mut dev,
+        &[Button::Down, Button::Down, Button::Enter],
+    );
+    assert_eq!(*nav.get_mode(), Mode::Confirm);
+
+    assert_eq!(nav.press(Button::Up, &mut dev), Action::StoreNvm);
+    assert_eq!(*nav.get_mode(), Mode::Navigation);
+    // The position is kept so the prompt can be opened again straight away
+    assert_eq!(nav.get_position(), (0, 2));
+}
+
+#[test]
+fn any_other_button_cancels_the_nvm_prompt() {
+    for button in [Button::Down, Button::Left, Button::Right, Button::Enter] {
+        let mut nav = Navigation::default();
+        let mut dev = Device::default();
+        press_all(
+            &mut nav,
+            &mut dev,
+            &[Button::Down, Button::Down, Button::Enter],
+        );
+
+        assert_eq!(nav.press(button, &mut dev), Action::None);
+        assert_eq!(*nav.get_mode(), Mode::Navigation);
+    }
+}
+
+#[test]
+fn up_from_the_top_row_opens_the_profile_screen() {
+    let mut nav = Navigation::default();
+    let mut dev = Device::default();
+
+    press_all(&mut nav, &mut dev, &[Button::Up]);
+    assert_eq!(*nav.get_mode(), Mode::Profile);
+    // The selection stays on the slots
+    press_all(
+        &mut nav,
+        &mut dev,
+        &[Button::Up, Button::Down, Button::Down],
+    );
+    assert_eq!(nav.get_profile(), 2);
+    press_all(&mut nav, &mut dev, &[Button::Down; 4]);
+    assert_eq!(nav.get_profile(), PROFILES - 1);
+
+    assert_eq!(
+        nav.press(Button::Enter, &mut dev),
+        Action::LoadProfile(PROFILES - 1)
+    );
+    assert_eq!(*nav.get_mode(), Mode::Navigation);
+    assert_eq!(nav.get_position(), (0, 0));
+}
+
+#[test]
+fn the_profile_screen_saves_with_right_and_closes_with_left() {
+    let mut nav = Navigation::default();
+    let mut dev = Device::default();
+
+    let action = press_all(
+        &mut nav,
+        &mut dev,
+        &[Button::Up, Button::Down, Button::Right],
+    );
+    assert_eq!(action, Action::SaveProfile(1));
+    assert_eq!(*nav.get_mode(), Mode::Navigation);
+
+    let action = press_all(&mut nav, &mut dev, &[Button::Up, Button::Left]);
+    assert_eq!(action, Action::None);
+    assert_eq!(*nav.get_mode(), Mode::Navigation);
+}
+
+#[test]
+fn left_from_the_left_column_toggles_the_output_on_enter() {
+    let mut nav = Navigation::default();
+    let mut dev = Device::default();
+
+    press_all(&mut nav, &mut dev, &[Button::Down, Button::Left]);
+    assert_eq!(*nav.get_mode(), Mode::Output);
+    assert_eq!(nav.press(Button::Enter, &mut dev), Action::ToggleOutput);
+    assert_eq!(*nav.get_mode(), Mode::Navigation);
+    assert_eq!(nav.get_position(), (0, 1));
+
+    let action = press_all(&mut nav, &mut dev, &[Button::Left, Button::Down]);
+    assert_eq!(action, Action::None);
+    assert_eq!(*nav.get_mode(), Mode::Navigation);
+}
+
+#[test]
+fn down_from_the_bottom_row_margins_the_rail_of_the_column_on_enter() {
+    let mut nav = Navigation::default();
+    let mut dev = Device::default();
+
+    press_all(
+        &mut nav,
+        &mut dev,
+        &[Button::Right, Button::Down, Button::Down],
+    );
+    assert_eq!(*nav.get_mode(), Mode::Navigation);
+    assert_eq!(nav.press(Button::Down, &mut dev), Action::None);
+    assert_eq!(*nav.get_mode(), Mode::Margin);
+
+    // Up and Down only select, the screen stays open while levels are applied
+    assert_eq!(nav.press(Button::Up, &mut dev), Action::None);
+    assert_eq!(nav.get_margin(), Level::High);
+    assert_eq!(
+        nav.press(Button::Enter, &mut dev),
+        Action::Margin(1, Level::High)
+    );
+    press_all(&mut nav, &mut dev, &[Button::Down, Button::Down]);
+    assert_eq!(
+        nav.press(Button::Enter, &mut dev),
+        Action::Margin(1, Level::Low)
+    );
+    assert_eq!(*nav.get_mode(), Mode::Margin);
+    assert_eq!(nav.press(Button::Left, &mut dev), Action::None);
+    assert_eq!(*nav.get_mode(), Mode::Navigation);
+    assert_eq!(nav.get_position(), (1, 2));
+}
+
+#[test]
+fn holding_down_across_the_bottom_row_never_margins() {
+    let mut nav = Navigation::default();
+    let mut dev = Device::default();
+
+    // A held button repeats on every UI tick
+    for _ in 0..10 {
+        assert_eq!(nav.press(Button::Down, &mut dev), Action::None);
+    }
+    assert_eq!(*nav.get_mode(), Mode::Margin);
+    assert_eq!(nav.get_margin(), Level::Low);
+    assert_eq!(
+        nav.press(Button::Enter, &mut dev),
+        Action::Margin(0, Level::Low)
+    );
+
+    // Reopening the screen starts back at nominal
+    press_all(&mut nav, &mut dev, &[Button::Left, Button::Down]);
+    assert_eq!(nav.get_margin(), Level::Nominal);
+}
+
+#[test]
+fn store_value_ignores_points_outside_the_grid() {
+    let mut dev = Device::default();
+    dev.store_value((2, 0), 5.);
+    dev.store_value((0, 2), 5.);
+    dev.store_value((1, 0), 1.2);
+
+    assert_eq!(dev.core().get_voltage_setpoint(), 0.);
+    assert_eq!(dev.core().get_temperature(), 0.);
+    assert_eq!(dev.mem().get_voltage_setpoint(), 1.2);
+}
+
+#[test]
+fn edited_values_stay_inside_the_envelope() {
+    let mut nav = Navigation::default();
+    let mut dev = Device::default();
+    let envelope = Envelope::default();
+    dev.core().set_voltage_setpoint(1.2);
+
+    let action = press_all(
+        &mut nav,
+        &mut dev,
+        &[Button::Enter, Button::Right, Button::Right, Button::Enter],
+    );
+    assert_eq!(action, Action::Write((0, 0), envelope.core.vout_max));
+
+    let action = press_all(
+        &mut nav,
+        &mut dev,
+        &[Button::Down, Button::Enter, Button::Left, Button::Enter],
+    );
+    assert_eq!(action, Action::Write((0, 1), 0.));
+}
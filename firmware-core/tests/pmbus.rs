@@ -0,0 +1,83 @@
+use firmware_core::pmbus::{
+    check_block, check_read, pec, write_message, VrmError, MAX_BLOCK, MAX_TRANSACTION,
+};
+
+// 0x60 on the bus: 0xC0 addresses it for a write, 0xC1 for a read. The expected PEC bytes below
+// come from a table driven CRC-8/SMBUS over the whole transaction, address bytes included
+const ADDRESS: u8 = 0x60;
+
+#[test]
+fn pec_matches_the_crc8_smbus_check_value() {
+    assert_eq!(pec(0, b"123456789"), 0xF4);
+    // Continuing from a partial CRC gives the same result
+    assert_eq!(pec(pec(0, b"1234"), b"56789"), 0xF4);
+    assert_eq!(pec(0, &[]), 0x00);
+}
+
+#[test]
+fn writes_append_the_pec_of_the_address_and_data() {
+    let mut buf = [0; MAX_TRANSACTION];
+
+    // SEND_BYTE CLEAR_FAULTS: 0xC0 0x03
+    assert_eq!(
+        write_message(ADDRESS, true, &[0x03], &mut buf),
+        Ok(&[0x03, 0xE4][..])
+    );
+    // WRITE_BYTE PAGE 1: 0xC0 0x00 0x01
+    assert_eq!(
+        write_message(ADDRESS, true, &[0x00, 0x01], &mut buf),
+        Ok(&[0x00, 0x01, 0x8A][..])
+    );
+    // WRITE_WORD VOUT_COMMAND 0x019A: 0xC0 0x21 0x9A 0x01
+    assert_eq!(
+        write_message(ADDRESS, true, &[0x21, 0x9A, 0x01], &mut buf),
+        Ok(&[0x21, 0x9A, 0x01, 0xE6][..])
+    );
+    // Nothing is appended without PEC
+    assert_eq!(
+        write_message(ADDRESS, false, &[0x21, 0x9A, 0x01], &mut buf),
+        Ok(&[0x21, 0x9A, 0x01][..])
+    );
+}
+
+#[test]
+fn reads_check_the_pec_over_both_address_bytes() {
+    let mut buf = [0; 2];
+
+    // READ_WORD READ_VOUT: 0xC0 0x8B, repeated start, 0xC1 0x33 0x01 PEC
+    assert_eq!(
+        check_read(ADDRESS, true, 0x8B, &[0x33, 0x01, 0xC3], &mut buf),
+        Ok(())
+    );
+    assert_eq!(buf, [0x33, 0x01]);
+
+    // The PEC of the same bytes with the R/W bit of the read address left clear
+    assert_eq!(
+        check_read(ADDRESS, true, 0x8B, &[0x33, 0x01, 0xA8], &mut buf),
+        Err(VrmError::PecMismatch)
+    );
+    // Or with a data bit flipped
+    assert_eq!(
+        check_read(ADDRESS, true, 0x8B, &[0x32, 0x01, 0xC3], &mut buf),
+        Err(VrmError::PecMismatch)
+    );
+}
+
+#[test]
+fn block_reads_check_the_pec_over_the_count_and_data() {
+    let mut buf = [0; MAX_BLOCK];
+    let mut resp = [0; MAX_BLOCK + 2];
+
+    // BLOCK_READ MFR_ID "TI": 0xC0 0x99, repeated start, 0xC1 0x02 0x54 0x49 PEC
+    resp[..4].copy_from_slice(&[0x02, b'T', b'I', 0x8E]);
+    assert_eq!(check_block(ADDRESS, true, &[0x99], &resp, &mut buf), Ok(2));
+    assert_eq!(&buf[..2], b"TI");
+
+    resp[3] = 0x8F;
+    assert_eq!(
+        check_block(ADDRESS, true, &[0x99], &resp, &mut buf),
+        Err(VrmError::PecMismatch)
+    );
+    // Without PEC the trailing byte is not looked at
+    assert_eq!(check_block(ADDRESS, false, &[0x99], &resp, &mut buf), Ok(2));
+}
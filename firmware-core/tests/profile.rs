@@ -0,0 +1,223 @@
+mod common;
+
+use common::{assert_close, controller};
+use firmware_core::navigation::Device;
+use firmware_core::pmbus::VrmError;
+use firmware_core::profile::{Name, Profile, Profiles, RailProfile, NAME_LEN, PROFILES};
+use firmware_core::protocol::{Request, RequestError, Response};
+use firmware_core::ramp::{Ramp, STEP_US};
+use firmware_core::safety::Violation;
+use firmware_core::vrm_controller::TPSC536C7;
+use tps536c7_simulator::Tps536c7;
+
+/// Polls the ramp until every setpoint reached its target
+fn settle(ramp: &mut Ramp, controller: &mut TPSC536C7<Tps536c7>) {
+    let mut now = 0;
+    while ramp.is_running() {
+        now += STEP_US;
+        ramp.poll(controller, now);
+    }
+}
+
+fn name(name: &str) -> Name {
+    Name::new(name).unwrap()
+}
+
+#[test]
+fn names_are_short_printable_ascii() {
+    assert_eq!(name("daily OC").as_str(), "daily OC");
+    assert_eq!(Name::new(&"a".repeat(NAME_LEN + 1)), None);
+    assert_eq!(Name::new(""), None);
+    assert_eq!(Name::new("tab\there"), None);
+    assert_eq!(Name::new("0.9V→1V"), None);
+    assert_eq!(Name::for_slot(0).as_str(), "Profile 1");
+}
+
+#[test]
+fn a_saved_profile_loads_back_onto_the_controller() {
+    let mut controller = controller(Tps536c7::default());
+    let mut dev = Device::default();
+    let mut ramp = Ramp::default();
+    let mut profiles = Profiles::default();
+    let c = controller.ch_a().unwrap();
+    c.vout_command().write(1.05).unwrap();
+    c.ot_fault_limit().write(100.).unwrap();
+    controller
+        .ch_b()
+        .unwrap()
+        .iout_oc_fault_limit()
+        .write(40.)
+        .unwrap();
+
+    profiles
+        .save(1, Some(name("bench")), &mut controller, &ramp)
+        .unwrap();
+    let core = controller.ch_a().unwrap();
+    core.vout_command().write(0.8).unwrap();
+    core.ot_fault_limit().write(125.).unwrap();
+    profiles
+        .load(1, &mut controller, &mut ramp, &mut dev)
+        .unwrap();
+    settle(&mut ramp, &mut controller);
+
+    let core = controller.ch_a().unwrap();
+    assert_close(core.vout_command().read().unwrap(), 1.05);
+    assert_close(core.ot_fault_limit().read().unwrap(), 100.);
+    let mem = controller.ch_b().unwrap();
+    assert_close(mem.iout_oc_fault_limit().read().unwrap(), 40.);
+    // The panel shows the loaded setpoints right away
+    assert_close(dev.core().get_voltage_setpoint(), 1.05);
+    assert_close(dev.mem().get_current_limit(), 40.);
+}
+
+#[test]
+fn saving_without_a_name_keeps_the_old_one() {
+    let mut controller = controller(Tps536c7::default());
+    let mut profiles = Profiles::default();
+    let ramp = Ramp::default();
+
+    profiles.save(0, None, &mut controller, &ramp).unwrap();
+    assert_eq!(profiles.get(0).unwrap().name, Name::for_slot(0));
+
+    profiles
+        .save(2, Some(name("stock")), &mut controller, &ramp)
+        .unwrap();
+    profiles.save(2, None, &mut controller, &ramp).unwrap();
+    assert_eq!(profiles.get(2).unwrap().name, name("stock"));
+}
+
+#[test]
+fn profile_values_are_written_through_to_each_channel() {
+    let mut controller = controller(Tps536c7::default());
+    let mut dev = Device::default();
+    let mut ramp = Ramp::default();
+    let rail = |voltage, current_limit| RailProfile {
+        voltage,
+        current_limit,
+        ramp_rate: 0.5,
+        ot_limit: 110.,
+    };
+    let profile = Profile {
+        name: name("daily OC"),
+        core: rail(1.1, 200.),
+        mem: rail(1.4, 50.),
+    };
+
+    profile.apply(&mut controller, &mut ramp, &mut dev).unwrap();
+    // The voltages are ramped to at the profile's rate
+    assert_eq!(ramp.rate(1), 0.5);
+    assert_eq!(ramp.target(1), Some(1.4));
+    settle(&mut ramp, &mut controller);
+
+    let read = Profile::read(&mut controller, &ramp, name("daily OC")).unwrap();
+    assert_eq!(read.mem.ramp_rate, 0.5);
+    let mem = controller.ch_b().unwrap();
+    assert_close(mem.vout_command().read().unwrap(), 1.4);
+    assert_close(mem.ot_fault_limit().read().unwrap(), 110.);
+}
+
+#[test]
+fn a_refused_value_leaves_both_channels_as_they_were() {
+    let mut controller = controller(Tps536c7::default());
+    let mut dev = Device::default();
+    let mut ramp = Ramp::default();
+    let before = Profile::read(&mut controller, &ramp, name("before")).unwrap();
+    let core = RailProfile {
+        voltage: 1.1,
+        current_limit: 200.,
+        ramp_rate: 0.5,
+        ot_limit: 110.,
+    };
+    // 2 V is above the memory's 1.5 V
+    let mem = RailProfile {
+        voltage: 2.0,
+        current_limit: 50.,
+        ..core
+    };
+    let profile = Profile {
+        name: name("too far"),
+        core,
+        mem,
+    };
+
+    assert_eq!(
+        profile.apply(&mut controller, &mut ramp, &mut dev),
+        Err(RequestError::Vrm(VrmError::OutOfEnvelope(
+            Violation::VoltageHigh
+        )))
+    );
+    assert!(!ramp.is_running());
+    assert_eq!(
+        Profile::read(&mut controller, &ramp, name("before")).unwrap(),
+        before
+    );
+
+    // As does a ramp rate out of range
+    let profile = Profile {
+        mem: RailProfile {
+            voltage: 1.4,
+            ramp_rate: 0.,
+            ..mem
+        },
+        ..profile
+    };
+    assert_eq!(
+        profile.apply(&mut controller, &mut ramp, &mut dev),
+        Err(RequestError::InvalidRampRate)
+    );
+    assert_eq!(
+        Profile::read(&mut controller, &ramp, name("before")).unwrap(),
+        before
+    );
+}
+
+#[test]
+fn requests_list_load_and_delete_profiles() {
+    let mut controller = controller(Tps536c7::default());
+    let mut dev = Device::default();
+    let mut ramp = Ramp::default();
+    let mut profiles = Profiles::default();
+    let mut handle = |request: Request| {
+        profiles
+            .handle(&request, &mut controller, &mut ramp, &mut dev)
+            .unwrap()
+    };
+
+    let save = Request::SaveProfile {
+        slot: 3,
+        name: name("stock"),
+    };
+    assert_eq!(handle(save), Response::Ack);
+    let mut names = [None; PROFILES];
+    names[3] = Some(name("stock"));
+    assert_eq!(handle(Request::ListProfiles), Response::Profiles(names));
+    assert!(matches!(
+        handle(Request::GetProfile(3)),
+        Response::Profile(Profile { name, .. }) if name.as_str() == "stock"
+    ));
+    assert_eq!(handle(Request::LoadProfile(3)), Response::Ack);
+
+    assert_eq!(handle(Request::DeleteProfile(3)), Response::Ack);
+    for request in [
+        Request::LoadProfile(3),
+        Request::GetProfile(3),
+        Request::DeleteProfile(3),
+        Request::LoadProfile(PROFILES as u8),
+    ] {
+        assert_eq!(handle(request), Response::Error(RequestError::NoProfile));
+    }
+}
+
+#[test]
+fn other_requests_are_left_to_the_caller() {
+    let mut controller = controller(Tps536c7::default());
+
+    let response = Profiles::default().handle(
+        &Request::Ping,
+        &mut controller,
+        &mut Ramp::default(),
+        &mut Device::default(),
+    );
+
+    assert_eq!(response, None);
+}
@@ -0,0 +1,171 @@
+use firmware_core::navigation::Device;
+use firmware_core::pmbus::VrmError;
+use firmware_core::profile::Name;
+use firmware_core::protocol::{
+    crc16, decode_frame, encode_frame, Frame, FrameError, FrameReader, Message, Request,
+    RequestError, Response, StreamConfig, MAX_FRAME, VERSION,
+};
+
+fn encode(seq: u16, message: &Message) -> Vec<u8> {
+    let mut buf = [0u8; MAX_FRAME];
+    let length = encode_frame(seq, message, &mut buf).unwrap();
+    buf[..length].to_vec()
+}
+
+/// Feeds bytes to a reader, collecting every frame it completes
+fn read_all(reader: &mut FrameReader, bytes: &[u8]) -> Vec<Result<(u16, String), FrameError>> {
+    let mut frames = Vec::new();
+    for &byte in bytes {
+        if let Some(frame) = reader.push(byte) {
+            frames.push(frame.map(|Frame { seq, message }| (seq, format!("{message:?}"))));
+        }
+    }
+    frames
+}
+
+#[test]
+fn crc_matches_the_check_value() {
+    assert_eq!(crc16(b"123456789"), 0x29B1);
+}
+
+#[test]
+fn frames_contain_a_single_delimiter() {
+    let request = Message::Request(Request::Write {
+        page: 0,
+        data: &[0x21, 0x00, 0x00, 0x02],
+    });
+    let frame = encode(0, &request);
+
+    assert_eq!(frame.iter().filter(|&&byte| byte == 0).count(), 1);
+    assert_eq!(frame.last(), Some(&0));
+}
+
+#[test]
+fn requests_round_trip() {
+    let requests = [
+        Request::Ping,
+        Request::Write {
+            page: 1,
+            data: &[0x21, 0x00, 0x02],
+        },
+        Request::Nvm {
+            cmd: 0x11,
+            confirm: 0xA5,
+        },
+        Request::StartStream(StreamConfig {
+            rate_hz: 1000,
+            fields: StreamConfig::ALL,
+            core: true,
+            mem: false,
+        }),
+        Request::StopStream,
+        Request::ListProfiles,
+        Request::SaveProfile {
+            slot: 2,
+            name: Name::new("daily OC").unwrap(),
+        },
+        Request::LoadProfile(2),
+    ];
+    for (seq, request) in requests.into_iter().enumerate() {
+        let mut frame = encode(seq as u16, &Message::Request(request));
+        let length = frame.len() - 1;
+
+        let decoded = decode_frame(&mut frame[..length]).unwrap();
+
+        assert_eq!(decoded.seq, seq as u16);
+        match decoded.message {
+            Message::Request(decoded) => assert_eq!(decoded, request),
+            other => panic!("Expected a request, got {other:?}"),
+        }
+    }
+}
+
+#[test]
+fn telemetry_fits_in_a_frame() {
+    let mut dev = Device::default();
+    dev.core().set_voltage(0.9);
+    dev.mem().set_current_limit(60.);
+    dev.mem().set_phase_count(2);
+
+    let mut frame = encode(7, &Message::Telemetry(dev));
+    let length = frame.len() - 1;
+
+    match decode_frame(&mut frame[..length]).unwrap().message {
+        Message::Telemetry(mut decoded) => {
+            assert_eq!(decoded.core().get_voltage(), 0.9);
+            assert_eq!(decoded.mem().get_current_limit(), 60.);
+            assert_eq!(decoded.mem().get_phases().len(), 2);
+        }
+        other => panic!("Expected telemetry, got {other:?}"),
+    }
+}
+
+#[test]
+fn corrupted_frames_fail_the_crc() {
+    let mut frame = encode(3, &Message::Response(Response::Ack));
+    let length = frame.len() - 1;
+    frame[length - 1] ^= 0x01;
+
+    assert_eq!(
+        decode_frame(&mut frame[..length]).unwrap_err(),
+        FrameError::Crc
+    );
+}
+
+#[test]
+fn reader_resynchronises_after_a_partial_frame() {
+    let ack = encode(1, &Message::Response(Response::Ack));
+    let error = encode(
+        2,
+        &Message::Response(Response::Error(RequestError::Vrm(VrmError::Nack))),
+    );
+    let mut reader = FrameReader::default();
+
+    // The tail of a frame that started before the host connected, then two whole frames split
+    // across reads
+    let mut bytes = ack[ack.len() / 2..].to_vec();
+    bytes.extend_from_slice(&ack);
+    bytes.extend_from_slice(&error);
+    let (first, second) = bytes.split_at(bytes.len() / 2);
+    let mut frames = read_all(&mut reader, first);
+    frames.extend(read_all(&mut reader, second));
+
+    assert_eq!(frames.len(), 3);
+    assert!(frames[0].is_err());
+    assert_eq!(frames[1], Ok((1, "Response(Ack)".into())));
+    assert_eq!(frames[2], Ok((2, "Response(Error(Vrm(Nack)))".into())));
+}
+
+#[test]
+fn reader_drops_oversized_frames() {
+    let mut reader = FrameReader::default();
+    let mut bytes = vec![0x01; MAX_FRAME + 1];
+    bytes.push(0);
+    bytes.extend(encode(5, &Message::Request(Request::Ping)));
+
+    let frames = read_all(&mut reader, &bytes);
+
+    assert_eq!(
+        frames,
+        [Err(FrameError::Overflow), Ok((5, "Request(Ping)".into()))]
+    );
+}
+
+#[test]
+fn other_versions_are_reported_with_their_sequence_number() {
+    // Header of a newer host, the message is never looked at
+    let mut raw = vec![VERSION + 1, 9];
+    raw.extend_from_slice(&crc16(&raw).to_le_bytes());
+    // No zeros to stuff, so the COBS encoding is a single block
+    assert!(!raw.contains(&0));
+    let mut frame = vec![raw.len() as u8 + 1];
+    frame.extend_from_slice(&raw);
+
+    assert_eq!(
+        decode_frame(&mut frame).unwrap_err(),
+        FrameError::Version {
+            version: VERSION + 1,
+            seq: 9
+        }
+    );
+}
@@ -0,0 +1,165 @@
+mod common;
+
+use common::controller;
+use firmware_core::pmbus::Page;
+use firmware_core::protocol::{Request, RequestError, Response};
+use firmware_core::ramp::{Ramp, RampEvent, DEFAULT_RATE, STEP_US};
+use firmware_core::vrm_controller::TPSC536C7;
+use firmware_core::vrm_status::StatusWord;
+use tps536c7_simulator::{Channel, Fault, Tps536c7};
+
+fn vout_command(controller: &mut TPSC536C7<Tps536c7>, page: Page) -> f32 {
+    controller
+        .page(page)
+        .unwrap()
+        .vout_command()
+        .read()
+        .unwrap()
+}
+
+#[test]
+fn setpoints_move_at_the_ramp_rate() {
+    let mut controller = controller(Tps536c7::default());
+    let mut ramp = Ramp::default();
+    assert_eq!(ramp.rate(0), DEFAULT_RATE);
+    ramp.set_rate(0, 10.).unwrap();
+
+    // 0.9 V to 1.0 V at 10 mV/ms takes 10 ms
+    ramp.write_setpoint(&mut controller, (0, 0), 1.0).unwrap();
+    assert!(ramp.is_running());
+    assert_eq!(ramp.poll(&mut controller, 0), None);
+    // Nothing is written until a whole step has passed
+    assert_eq!(ramp.poll(&mut controller, STEP_US / 2), None);
+    assert!((vout_command(&mut controller, Page::ChannelA) - 0.9).abs() < 0.002);
+
+    assert_eq!(ramp.poll(&mut controller, 5 * STEP_US), None);
+    assert!((vout_command(&mut controller, Page::ChannelA) - 0.95).abs() < 0.002);
+    assert!((ramp.progress(0).unwrap() - 0.5).abs() < 0.01);
+
+    assert_eq!(
+        ramp.poll(&mut controller, 11 * STEP_US),
+        Some(RampEvent::Done(Page::ChannelA))
+    );
+    assert!(!ramp.is_running());
+    assert!((vout_command(&mut controller, Page::ChannelA) - 1.0).abs() < 0.002);
+}
+
+#[test]
+fn a_new_setpoint_carries_on_from_the_running_ramp() {
+    let mut controller = controller(Tps536c7::default());
+    let mut ramp = Ramp::default();
+    ramp.set_rate(1, 20.).unwrap();
+
+    // The memory channel starts at 1.35 V
+    ramp.write_setpoint(&mut controller, (1, 0), 1.45).unwrap();
+    ramp.poll(&mut controller, 0);
+    ramp.poll(&mut controller, 2 * STEP_US);
+    ramp.write_setpoint(&mut controller, (1, 0), 1.3).unwrap();
+
+    assert_eq!(ramp.target(1), Some(1.3));
+    assert_eq!(ramp.progress(1), Some(0.));
+    ramp.poll(&mut controller, 3 * STEP_US);
+    ramp.poll(&mut controller, 4 * STEP_US);
+    assert!((vout_command(&mut controller, Page::ChannelB) - 1.37).abs() < 0.002);
+}
+
+#[test]
+fn current_limits_are_written_straight_away() {
+    let mut controller = controller(Tps536c7::default());
+    let mut ramp = Ramp::default();
+
+    ramp.write_setpoint(&mut controller, (0, 1), 120.).unwrap();
+
+    assert!(!ramp.is_running());
+    let limit = controller
+        .ch_a()
+        .unwrap()
+        .iout_oc_fault_limit()
+        .read()
+        .unwrap();
+    assert_eq!(limit, 120.);
+}
+
+#[test]
+fn a_fault_aborts_the_ramp_where_it_is() {
+    let mut sim = Tps536c7::default();
+    sim.inject_fault(Channel::A, Fault::OverCurrent);
+    let mut controller = controller(sim);
+    let mut ramp = Ramp::default();
+    ramp.set_rate(0, 10.).unwrap();
+    ramp.write_setpoint(&mut controller, (0, 0), 1.2).unwrap();
+    ramp.poll(&mut controller, 0);
+
+    match ramp.poll(&mut controller, 3 * STEP_US) {
+        Some(RampEvent::Aborted(Page::ChannelA, status)) => {
+            assert!(status.contains(StatusWord::IOUT_OC))
+        }
+        other => panic!("Expected the ramp to abort, got {other:?}"),
+    }
+    assert!(!ramp.is_running());
+    assert!((vout_command(&mut controller, Page::ChannelA) - 0.93).abs() < 0.002);
+}
+
+#[test]
+fn an_abort_is_kept_for_the_host_until_fetched() {
+    let mut sim = Tps536c7::default();
+    sim.inject_fault(Channel::B, Fault::OverCurrent);
+    let mut controller = controller(sim);
+    let mut ramp = Ramp::default();
+    assert_eq!(
+        ramp.handle(&Request::GetRampAbort, &mut controller),
+        Some(Response::RampAbort(None))
+    );
+
+    ramp.write_setpoint(&mut controller, (1, 0), 1.3).unwrap();
+    ramp.poll(&mut controller, 0);
+    let event = ramp.poll(&mut controller, STEP_US).unwrap();
+    assert_eq!(event.page(), Page::ChannelB);
+    assert_eq!(event.as_str(), "Overcurrent");
+
+    assert_eq!(
+        ramp.handle(&Request::GetRampAbort, &mut controller),
+        Some(Response::RampAbort(Some(event)))
+    );
+    assert_eq!(
+        ramp.handle(&Request::GetRampAbort, &mut controller),
+        Some(Response::RampAbort(None))
+    );
+}
+
+#[test]
+fn raw_vout_command_writes_are_ramped() {
+    let mut controller = controller(Tps536c7::default());
+    let mut ramp = Ramp::default();
+
+    // 1 V in 2^-9 steps
+    let write = Request::Write {
+        page: 1,
+        data: &[0x21, 0x00, 0x02],
+    };
+    assert_eq!(ramp.handle(&write, &mut controller), Some(Response::Ack));
+
+    assert_eq!(ramp.target(1), Some(1.0));
+    let other = Request::Write {
+        page: 1,
+        data: &[0x46, 0x28, 0xF0],
+    };
+    assert_eq!(ramp.handle(&other, &mut controller), None);
+}
+
+#[test]
+fn rates_outside_the_limits_are_refused() {
+    let mut controller = controller(Tps536c7::default());
+    let mut ramp = Ramp::default();
+
+    for rate in [0., -1., 1000., f32::NAN] {
+        let request = Request::SetRampRate { page: 0, rate };
+        assert_eq!(
+            ramp.handle(&request, &mut controller),
+            Some(Response::Error(RequestError::InvalidRampRate))
+        );
+    }
+    let request = Request::SetRampRate { page: 1, rate: 5. };
+    assert_eq!(ramp.handle(&request, &mut controller), Some(Response::Ack));
+    assert_eq!(ramp.rate(1), 5.);
+}
@@ -0,0 +1,238 @@
+mod common;
+
+use common::{assert_close, controller, NoDelay};
+use firmware_core::control::handle_request;
+use firmware_core::pmbus::{Command, GenericPmbus, Page, PmbusDevice, VrmError};
+use firmware_core::protocol::{Request, RequestError, Response};
+use firmware_core::ramp::Ramp;
+use firmware_core::safety::{Envelope, Rejection, Violation};
+use tps536c7_simulator::{Tps536c7, ADDRESS};
+
+#[test]
+fn setpoints_outside_the_envelope_never_reach_the_controller() {
+    let mut controller = controller(Tps536c7::default());
+    let c = controller.ch_a().unwrap();
+
+    assert_eq!(
+        c.vout_command().write(3.0),
+        Err(VrmError::OutOfEnvelope(Violation::VoltageHigh))
+    );
+    assert_eq!(
+        c.vout_command().write(0.3),
+        Err(VrmError::OutOfEnvelope(Violation::VoltageLow))
+    );
+    assert_eq!(
+        c.iout_oc_fault_limit().write(400.),
+        Err(VrmError::OutOfEnvelope(Violation::CurrentLimit))
+    );
+    assert_eq!(
+        c.ot_fault_limit().write(150.),
+        Err(VrmError::OutOfEnvelope(Violation::TempLimit))
+    );
+
+    assert_close(c.vout_command().read().unwrap(), 0.9);
+    assert_close(c.ot_fault_limit().read().unwrap(), 125.);
+    // Only the newest rejection is kept, and only reported once
+    let rejection = controller.take_rejection().unwrap();
+    assert_eq!(rejection.page, Page::ChannelA);
+    assert_eq!(rejection.violation, Violation::TempLimit);
+    assert_close(rejection.value, 150.);
+    assert_eq!(controller.take_rejection(), None);
+}
+
+#[test]
+fn negative_current_and_temperature_limits_are_refused() {
+    let mut controller = controller(Tps536c7::default());
+    let c = controller.ch_b().unwrap();
+    let iout_limit = c.iout_oc_fault_limit().read().unwrap();
+
+    assert_eq!(
+        c.iout_oc_fault_limit().write(-10.),
+        Err(VrmError::OutOfEnvelope(Violation::NegativeLimit))
+    );
+    assert_eq!(
+        c.ot_fault_limit().write(-5.),
+        Err(VrmError::OutOfEnvelope(Violation::NegativeLimit))
+    );
+    // -10 A in SLINEAR11 as a raw USB write
+    assert_eq!(
+        handle_request(
+            &mut controller,
+            &mut NoDelay,
+            &Request::Write {
+                page: 1,
+                data: &[0x46, 0xF6, 0x07]
+            },
+        ),
+        Response::Error(RequestError::Vrm(VrmError::OutOfEnvelope(
+            Violation::NegativeLimit
+        )))
+    );
+
+    let c = controller.ch_b().unwrap();
+    assert_close(c.iout_oc_fault_limit().read().unwrap(), iout_limit);
+    assert_close(c.ot_fault_limit().read().unwrap(), 125.);
+    // 0 is still a limit the rail can be given
+    c.ot_fault_limit().write(0.).unwrap();
+}
+
+#[test]
+fn raw_usb_writes_are_checked_too() {
+    let mut controller = controller(Tps536c7::default());
+    let mut request = |data| {
+        handle_request(
+            &mut controller,
+            &mut NoDelay,
+            &Request::Write { page: 1, data },
+        )
+    };
+    let refused =
+        |violation| Response::Error(RequestError::Vrm(VrmError::OutOfEnvelope(violation)));
+
+    // 2.0 V in the default 2^-9 linear mode, above the memory's 1.5 V
+    assert_eq!(
+        request(&[0x21, 0x00, 0x04]),
+        refused(Violation::VoltageHigh)
+    );
+    assert_eq!(
+        request(&[0x24, 0x00, 0x04]),
+        refused(Violation::VoltageHigh)
+    );
+    // 100 A in SLINEAR11
+    assert_eq!(
+        request(&[0x46, 0x20, 0xF3]),
+        refused(Violation::CurrentLimit)
+    );
+    // Changing the format would change what the setpoints mean
+    assert_eq!(request(&[0x20, 0x16]), refused(Violation::Protected));
+    // A VOUT_COMMAND that is not a whole word cannot be checked
+    assert_eq!(request(&[0x21, 0x00]), refused(Violation::Protected));
+
+    assert_eq!(request(&[0x21, 0x00, 0x03]), Response::Ack);
+    assert_close(
+        controller.ch_b().unwrap().vout_command().read().unwrap(),
+        1.5,
+    );
+}
+
+#[test]
+fn ramps_are_checked_before_they_start() {
+    let mut controller = controller(Tps536c7::default());
+    let mut ramp = Ramp::default();
+
+    assert_eq!(
+        ramp.write_setpoint(&mut controller, (0, 0), 1.4),
+        Err(VrmError::OutOfEnvelope(Violation::VoltageHigh))
+    );
+    let response = ramp.handle(
+        &Request::Write {
+            page: 1,
+            data: &[0x21, 0x00, 0x01],
+        },
+        &mut controller,
+    );
+
+    assert_eq!(
+        response,
+        Some(Response::Error(RequestError::Vrm(VrmError::OutOfEnvelope(
+            Violation::VoltageLow
+        ))))
+    );
+    assert!(!ramp.is_running());
+}
+
+#[test]
+fn writes_to_both_pages_have_to_suit_both_rails() {
+    let mut controller = controller(Tps536c7::default());
+
+    // Inside the core's range but below the memory's
+    let result = controller.ch_ab().unwrap().vout_command().write(0.9);
+
+    assert_eq!(result, Err(VrmError::OutOfEnvelope(Violation::VoltageLow)));
+    assert!(matches!(
+        controller.take_rejection(),
+        Some(Rejection {
+            page: Page::ChannelB,
+            ..
+        })
+    ));
+    controller
+        .ch_ab()
+        .unwrap()
+        .vout_command()
+        .write(1.1)
+        .unwrap();
+}
+
+#[test]
+fn the_controller_limits_are_programmed_to_the_envelope() {
+    let mut controller = controller(Tps536c7::default());
+    let envelope = Envelope::default();
+
+    controller.apply_envelope().unwrap();
+
+    let core = controller.ch_a().unwrap();
+    assert_close(core.vout_max().read().unwrap(), envelope.core.vout_max);
+    assert_close(core.vout_min().read().unwrap(), envelope.core.vout_min);
+    let mem = controller.ch_b().unwrap();
+    assert_close(mem.vout_max().read().unwrap(), envelope.mem.vout_max);
+    assert_close(mem.vout_min().read().unwrap(), envelope.mem.vout_min);
+}
+
+#[test]
+fn restoring_the_defaults_programs_the_limits_again() {
+    let mut controller = controller(Tps536c7::default());
+    let envelope = Envelope::default();
+    controller.apply_envelope().unwrap();
+
+    // The power on defaults have VOUT_MAX above the envelope
+    controller.restore_default_all(&mut NoDelay).unwrap();
+
+    let core = controller.ch_a().unwrap();
+    assert_close(core.vout_max().read().unwrap(), envelope.core.vout_max);
+    let mem = controller.ch_b().unwrap();
+    assert_close(mem.vout_min().read().unwrap(), envelope.mem.vout_min);
+}
+
+#[test]
+fn the_generic_driver_is_held_to_the_envelope() {
+    let mut generic = GenericPmbus::new(Tps536c7::default(), ADDRESS, true);
+    let vout_command = Command::VOUTCommand.to_address();
+    generic.select_page(Page::ChannelB).unwrap();
+
+    assert_eq!(
+        generic.write_vout_class(vout_command, 1.6),
+        Err(VrmError::OutOfEnvelope(Violation::VoltageHigh))
+    );
+    assert_eq!(
+        generic.take_rejection().map(|r| (r.page, r.violation)),
+        Some((Page::ChannelB, Violation::VoltageHigh))
+    );
+    generic.write_vout_class(vout_command, 1.4).unwrap();
+    assert_close(generic.read_vout_class(vout_command).unwrap(), 1.4);
+
+    // The core's lower limit applies once paged to it
+    generic.select_page(Page::ChannelA).unwrap();
+    assert_eq!(
+        generic.write_vout_class(vout_command, 1.4),
+        Err(VrmError::OutOfEnvelope(Violation::VoltageHigh))
+    );
+}
+
+#[test]
+fn limits_the_format_cannot_hold_can_still_be_written() {
+    let mut controller = controller(Tps536c7::default());
+    let mut envelope = Envelope::default();
+    // Not a multiple of the 2^-9 V steps
+    envelope.core.vout_max = 1.1;
+    envelope.core.iout_max = 123.4;
+    controller.set_envelope(envelope);
+
+    let c = controller.ch_a().unwrap();
+    c.vout_command().write(1.1).unwrap();
+    c.iout_oc_fault_limit().write(123.4).unwrap();
+    assert_eq!(
+        c.vout_command().write(1.105),
+        Err(VrmError::OutOfEnvelope(Violation::VoltageHigh))
+    );
+}
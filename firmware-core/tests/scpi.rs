@@ -0,0 +1,283 @@
+mod common;
+
+use common::controller;
+use firmware_core::navigation::Device;
+use firmware_core::pmbus::Page;
+use firmware_core::protocol::{encode_frame, FrameReader, Message, Request, MAX_FRAME};
+use firmware_core::ramp::{Ramp, STEP_US};
+use firmware_core::scpi::{
+    parse, Command, LineReader, Measurement, Reply, Scpi, ScpiError, HANDSHAKE, MAX_LINE,
+};
+use firmware_core::sequence::{Ready, Sequencer};
+use firmware_core::vrm_controller::TPSC536C7;
+use tps536c7_simulator::{Channel, Tps536c7, ADDRESS};
+
+/// Runs each line in turn, returning everything the interpreter answered
+fn run(
+    scpi: &mut Scpi,
+    controller: &mut TPSC536C7<Tps536c7>,
+    dev: &mut Device,
+    lines: &[&str],
+) -> String {
+    run_ramped(scpi, controller, &mut Ramp::default(), dev, lines)
+}
+
+fn run_ramped(
+    scpi: &mut Scpi,
+    controller: &mut TPSC536C7<Tps536c7>,
+    ramp: &mut Ramp,
+    dev: &mut Device,
+    lines: &[&str],
+) -> String {
+    run_sequenced(
+        scpi,
+        controller,
+        ramp,
+        &mut Sequencer::default(),
+        dev,
+        lines,
+    )
+}
+
+fn run_sequenced(
+    scpi: &mut Scpi,
+    controller: &mut TPSC536C7<Tps536c7>,
+    ramp: &mut Ramp,
+    seq: &mut Sequencer,
+    dev: &mut Device,
+    lines: &[&str],
+) -> String {
+    let mut out = String::new();
+    for line in lines {
+        let mut reply = Reply::default();
+        scpi.execute(line, controller, ramp, seq, dev, &mut reply);
+        out += core::str::from_utf8(reply.as_bytes()).unwrap();
+    }
+    out
+}
+
+#[test]
+fn keywords_take_the_long_or_short_form_in_any_case() {
+    assert_eq!(parse("*idn?"), Ok(Command::Identify));
+    assert_eq!(parse("VOLT:CORE 1.050"), Ok(Command::Set((0, 0), 1.05)));
+    assert_eq!(parse(":voltage:mem?"), Ok(Command::QuerySet((1, 0))));
+    assert_eq!(parse("CURR:LIM:MEM 40"), Ok(Command::Set((1, 1), 40.)));
+    assert_eq!(parse("Current:Limit:Core?"), Ok(Command::QuerySet((0, 1))));
+    assert_eq!(
+        parse("MEAS:TEMP:MEMORY?"),
+        Ok(Command::Measure(Measurement::Temperature, Page::ChannelB))
+    );
+    assert_eq!(parse("OUTP ON"), Ok(Command::Output(true)));
+    assert_eq!(parse("output 0"), Ok(Command::Output(false)));
+    assert_eq!(parse("SYST:ERR?"), Ok(Command::QueryError));
+}
+
+#[test]
+fn bad_commands_are_reported_with_their_scpi_error() {
+    assert_eq!(parse("VOLT:GPU 1"), Err(ScpiError::UndefinedHeader));
+    assert_eq!(parse("MEAS:VOLT:CORE"), Err(ScpiError::UndefinedHeader));
+    assert_eq!(parse("VOLT:CORE:MAX 1"), Err(ScpiError::UndefinedHeader));
+    assert_eq!(parse("VOLT:CORE"), Err(ScpiError::MissingParameter));
+    assert_eq!(parse("VOLT:CORE abc"), Err(ScpiError::DataType));
+    assert_eq!(parse("VOLT:CORE -1"), Err(ScpiError::IllegalParameter));
+    assert_eq!(parse("OUTP MAYBE"), Err(ScpiError::IllegalParameter));
+    assert_eq!(parse("*IDN? 1"), Err(ScpiError::ParameterNotAllowed));
+}
+
+#[test]
+fn setpoints_are_written_and_read_back() {
+    let mut controller = controller(Tps536c7::default());
+    let mut dev = Device::default();
+    let mut scpi = Scpi::default();
+    let mut ramp = Ramp::default();
+
+    let out = run_ramped(
+        &mut scpi,
+        &mut controller,
+        &mut ramp,
+        &mut dev,
+        &[
+            "VOLT:CORE 1.050",
+            "CURR:LIM:MEM 40",
+            "VOLT:CORE?",
+            "CURR:LIM:MEM?",
+        ],
+    );
+
+    // The voltage is still ramping, the query gives its target
+    assert_eq!(out, "1.0500\n40.0000\n");
+    let mut now = 0;
+    while ramp.is_running() {
+        now += STEP_US;
+        ramp.poll(&mut controller, now);
+    }
+    let out = run_ramped(
+        &mut scpi,
+        &mut controller,
+        &mut ramp,
+        &mut dev,
+        &["VOLT:CORE?"],
+    );
+    assert_eq!(out, "1.0508\n");
+    // Same as accepting the values on the front panel
+    assert!((dev.core().get_voltage_setpoint() - 1.05).abs() < 0.001);
+    assert_eq!(dev.mem().get_current_limit(), 40.);
+}
+
+#[test]
+fn measurements_read_the_controller() {
+    let mut sim = Tps536c7::default();
+    sim.set_load(Channel::B, 30.);
+    let mut controller = controller(sim);
+    let mut scpi = Scpi::default();
+
+    let out = run(
+        &mut scpi,
+        &mut controller,
+        &mut Device::default(),
+        &["MEAS:VOLT:CORE?", "MEAS:CURR:MEM?", "*IDN?"],
+    );
+
+    let lines: Vec<_> = out.lines().collect();
+    assert_eq!(lines[0], "0.9004");
+    assert_eq!(lines[1], "30.0000");
+    assert!(lines[2].starts_with("Overclocking Club,gpu-external-power-supply,"));
+}
+
+#[test]
+fn output_switches_the_rails_in_sequence() {
+    let mut controller = controller(Tps536c7::default());
+    let mut scpi = Scpi::default();
+    let mut ramp = Ramp::default();
+    let mut seq = Sequencer::default();
+    let mut dev = Device::default();
+    seq.init(&mut controller).unwrap();
+    let mut run = |controller: &mut TPSC536C7<Tps536c7>, seq: &mut Sequencer, lines: &[&str]| {
+        run_sequenced(&mut scpi, controller, &mut ramp, seq, &mut dev, lines)
+    };
+    let ready = Ready {
+        core: true,
+        mem: true,
+    };
+
+    let out = run(&mut controller, &mut seq, &["OUTP?", "MEAS:VOLT:MEM?"]);
+    assert_eq!(out, "0\n0.0000\n");
+
+    // Memory first, the core only once BVR_READY is up
+    let on = ["OUTP ON", "OUTP?", "MEAS:VOLT:CORE?", "MEAS:VOLT:MEM?"];
+    let out = run(&mut controller, &mut seq, &on);
+    assert_eq!(out, "0\n0.0000\n1.3496\n");
+    // A pass to note the time of each state and one to move on from it
+    for now in 0..4 {
+        seq.poll(&mut controller, ready, now);
+    }
+    let out = run(&mut controller, &mut seq, &["OUTP?", "MEAS:VOLT:CORE?"]);
+    assert_eq!(out, "1\n0.9004\n");
+
+    // And the core first on the way down
+    let out = run(
+        &mut controller,
+        &mut seq,
+        &["OUTP OFF", "OUTP?", "MEAS:VOLT:CORE?", "MEAS:VOLT:MEM?"],
+    );
+    assert_eq!(out, "0\n0.0000\n1.3496\n");
+}
+
+#[test]
+fn errors_queue_until_read() {
+    let mut controller = controller(Tps536c7::default());
+    let mut scpi = Scpi::default();
+    let mut dev = Device::default();
+
+    let out = run(
+        &mut scpi,
+        &mut controller,
+        &mut dev,
+        &["FOO", "VOLT:CORE", "SYST:ERR?", "SYST:ERR?", "SYST:ERR?"],
+    );
+
+    assert_eq!(
+        out,
+        "-113,\"Undefined header\"\n-109,\"Missing parameter\"\n0,\"No error\"\n"
+    );
+}
+
+#[test]
+fn a_full_error_queue_ends_in_an_overflow() {
+    let mut controller = controller(Tps536c7::default());
+    let mut scpi = Scpi::default();
+    let mut dev = Device::default();
+
+    run(&mut scpi, &mut controller, &mut dev, &["FOO"; 10]);
+
+    for _ in 0..7 {
+        assert_eq!(scpi.pop_error(), Some(ScpiError::UndefinedHeader));
+    }
+    assert_eq!(scpi.pop_error(), Some(ScpiError::QueueOverflow));
+    assert_eq!(scpi.pop_error(), None);
+
+    run(&mut scpi, &mut controller, &mut dev, &["FOO", "*CLS"]);
+    assert_eq!(scpi.pop_error(), None);
+}
+
+#[test]
+fn controller_errors_carry_their_cause() {
+    // Nothing answers on the controller's address
+    let mut controller = TPSC536C7::new(Tps536c7::new(0x10), ADDRESS, true);
+    let mut scpi = Scpi::default();
+
+    let out = run(
+        &mut scpi,
+        &mut controller,
+        &mut Device::default(),
+        &["VOLT:MEM 1.2", "SYST:ERR?"],
+    );
+
+    assert_eq!(out, "-300,\"Device-specific error;Nack\"\n");
+}
+
+#[test]
+fn setpoints_outside_the_envelope_are_out_of_range() {
+    let mut controller = controller(Tps536c7::default());
+    let mut scpi = Scpi::default();
+
+    let out = run(
+        &mut scpi,
+        &mut controller,
+        &mut Device::default(),
+        &["VOLT:CORE 2.5", "SYST:ERR?", "VOLT:CORE?"],
+    );
+
+    assert_eq!(out, "-222,\"Data out of range\"\n0.9004\n");
+}
+
+#[test]
+fn lines_end_at_a_newline() {
+    let mut lines = LineReader::default();
+    let mut got = Vec::new();
+    for &byte in b"\r\n*IDN?\r\nOUTP ON\n" {
+        if let Some(line) = lines.push(byte) {
+            got.push(line.unwrap().to_string());
+        }
+    }
+    assert_eq!(got, ["*IDN?", "OUTP ON"]);
+
+    let long = [b'A'; MAX_LINE + 1];
+    assert!(long.iter().all(|&byte| lines.push(byte).is_none()));
+    assert_eq!(
+        lines.push(b'\n').map(|line| line.map(str::to_string)),
+        Some(Err(ScpiError::InputOverrun))
+    );
+}
+
+#[test]
+fn the_handshake_never_starts_a_request_frame() {
+    // Sequence numbers of 251 and up take three bytes, the longest a request gets before its tag
+    for seq in [0, 1, 250, 251, 0x0101, 0xFFFF] {
+        let mut frame = [0u8; MAX_FRAME];
+        encode_frame(seq, &Message::Request(Request::Ping), &mut frame).unwrap();
+        assert_ne!(frame[0], HANDSHAKE, "seq {seq}");
+        assert!(frame[0] <= 5, "seq {seq}");
+    }
+    assert!(FrameReader::default().at_boundary());
+}
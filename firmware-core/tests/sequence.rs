@@ -0,0 +1,166 @@
+mod common;
+
+use common::{controller, NoDelay};
+use firmware_core::control::handle_request;
+use firmware_core::pmbus::{Page, PmbusDevice};
+use firmware_core::protocol::{Request, RequestError, Response};
+use firmware_core::sequence::{
+    Ready, SequenceFault, Sequencer, State, OFF_TIMEOUT_US, READY_TIMEOUT_US,
+};
+use firmware_core::vrm_controller::TPSC536C7;
+use tps536c7_simulator::Tps536c7;
+
+const NONE: Ready = Ready {
+    core: false,
+    mem: false,
+};
+const MEM: Ready = Ready {
+    core: false,
+    mem: true,
+};
+const BOTH: Ready = Ready {
+    core: true,
+    mem: true,
+};
+
+fn operation(controller: &mut TPSC536C7<Tps536c7>, page: Page) -> u8 {
+    controller.select_page(page).unwrap();
+    controller.read_byte(0x01).unwrap()
+}
+
+/// Polls with the same ready levels until the state stops changing, returning the clock
+fn settle(
+    seq: &mut Sequencer,
+    controller: &mut TPSC536C7<Tps536c7>,
+    ready: Ready,
+    mut now: u32,
+) -> u32 {
+    for _ in 0..8 {
+        seq.poll(controller, ready, now);
+        now += 100;
+    }
+    now
+}
+
+#[test]
+fn memory_comes_up_before_the_core_and_goes_down_after_it() {
+    let mut controller = controller(Tps536c7::default());
+    let mut seq = Sequencer::default();
+    seq.init(&mut controller).unwrap();
+    assert_eq!(operation(&mut controller, Page::ChannelA), 0x00);
+    assert_eq!(operation(&mut controller, Page::ChannelB), 0x00);
+    assert!(!seq.mem_enable());
+
+    seq.power_up(&mut controller).unwrap();
+    let now = settle(&mut seq, &mut controller, NONE, 0);
+    assert_eq!(seq.state(), State::MemRamp);
+    assert!(seq.mem_enable());
+    assert_eq!(operation(&mut controller, Page::ChannelA), 0x00);
+    assert_eq!(operation(&mut controller, Page::ChannelB), 0x80);
+
+    let now = settle(&mut seq, &mut controller, MEM, now);
+    assert_eq!(seq.state(), State::CoreRamp);
+    assert_eq!(operation(&mut controller, Page::ChannelA), 0x80);
+    let now = settle(&mut seq, &mut controller, BOTH, now);
+    assert!(seq.is_on());
+
+    seq.power_down(&mut controller).unwrap();
+    let now = settle(&mut seq, &mut controller, BOTH, now);
+    assert_eq!(seq.state(), State::CoreDown);
+    assert_eq!(operation(&mut controller, Page::ChannelA), 0x00);
+    assert_eq!(operation(&mut controller, Page::ChannelB), 0x80);
+
+    let now = settle(&mut seq, &mut controller, MEM, now);
+    assert_eq!(seq.state(), State::MemDown);
+    assert!(!seq.mem_enable());
+    assert_eq!(operation(&mut controller, Page::ChannelB), 0x00);
+    settle(&mut seq, &mut controller, NONE, now);
+    assert_eq!(seq.state(), State::Off);
+}
+
+#[test]
+fn a_rail_that_never_comes_ready_turns_both_off() {
+    let mut controller = controller(Tps536c7::default());
+    let mut seq = Sequencer::default();
+    seq.init(&mut controller).unwrap();
+    seq.power_up(&mut controller).unwrap();
+    let now = settle(&mut seq, &mut controller, MEM, 0);
+    assert_eq!(seq.state(), State::CoreRamp);
+
+    seq.poll(&mut controller, MEM, now + READY_TIMEOUT_US);
+
+    assert_eq!(
+        seq.state(),
+        State::Fault(SequenceFault::ReadyTimeout(Page::ChannelA))
+    );
+    assert!(!seq.mem_enable());
+    assert_eq!(operation(&mut controller, Page::ChannelA), 0x00);
+    assert_eq!(operation(&mut controller, Page::ChannelB), 0x00);
+}
+
+#[test]
+fn losing_ready_or_not_dropping_it_is_a_fault() {
+    let mut controller = controller(Tps536c7::default());
+    let mut seq = Sequencer::default();
+    seq.init(&mut controller).unwrap();
+    seq.power_up(&mut controller).unwrap();
+    let now = settle(&mut seq, &mut controller, BOTH, 0);
+    assert!(seq.is_on());
+
+    let now = settle(&mut seq, &mut controller, MEM, now);
+    assert_eq!(
+        seq.state(),
+        State::Fault(SequenceFault::LostReady(Page::ChannelA))
+    );
+
+    // A fault can be retried, and the shutdown is timed like the start up
+    seq.power_up(&mut controller).unwrap();
+    let now = settle(&mut seq, &mut controller, BOTH, now);
+    assert!(seq.is_on());
+    seq.power_down(&mut controller).unwrap();
+    let now = settle(&mut seq, &mut controller, BOTH, now);
+    seq.poll(&mut controller, BOTH, now + OFF_TIMEOUT_US);
+    assert_eq!(
+        seq.state(),
+        State::Fault(SequenceFault::OffTimeout(Page::ChannelA))
+    );
+}
+
+#[test]
+fn usb_requests_run_the_sequence() {
+    let mut controller = controller(Tps536c7::default());
+    let mut seq = Sequencer::default();
+    seq.init(&mut controller).unwrap();
+
+    assert_eq!(
+        seq.handle(&Request::SetOutput(true), &mut controller),
+        Some(Response::Ack)
+    );
+    settle(&mut seq, &mut controller, BOTH, 0);
+    assert_eq!(
+        seq.handle(&Request::GetOutput, &mut controller),
+        Some(Response::Output(State::On))
+    );
+    assert_eq!(seq.handle(&Request::Ping, &mut controller), None);
+    // Off switches the core off straight away
+    seq.handle(&Request::SetOutput(false), &mut controller);
+    assert_eq!(operation(&mut controller, Page::ChannelA), 0x00);
+    assert_eq!(
+        handle_request(&mut controller, &mut NoDelay, &Request::GetOutput),
+        Response::Error(RequestError::Refused)
+    );
+}
+
+#[test]
+fn raw_usb_writes_cannot_switch_the_rails() {
+    let mut controller = controller(Tps536c7::default());
+
+    for data in [&[0x01, 0x80][..], &[0x02, 0x00]] {
+        let response = handle_request(
+            &mut controller,
+            &mut NoDelay,
+            &Request::Write { page: 0xFF, data },
+        );
+        assert_eq!(response, Response::Error(RequestError::Refused));
+    }
+}
@@ -0,0 +1,288 @@
+mod common;
+
+use common::controller;
+use embedded_storage::nor_flash::{
+    check_erase, check_read, check_write, ErrorType, NorFlash, NorFlashErrorKind, ReadNorFlash,
+};
+use firmware_core::margin::Margin;
+use firmware_core::navigation::{Device, Navigation};
+use firmware_core::profile::{Name, Profile, Profiles, NAME_LEN, PROFILES};
+use firmware_core::ramp::Ramp;
+use firmware_core::settings::{
+    Preferences, RailSettings, Settings, SettingsError, SettingsStore, SETTLE_US, SLOT,
+};
+use tps536c7_simulator::Tps536c7;
+
+const BLOCK: u32 = 1024;
+
+/// NOR flash in RAM: erasing sets every bit, writing can only clear them
+struct RamFlash {
+    data: Vec<u8>,
+    erases: Vec<u32>,
+}
+
+impl RamFlash {
+    fn new(blocks: u32) -> RamFlash {
+        RamFlash {
+            data: vec![0xFF; (blocks * BLOCK) as usize],
+            erases: vec![0; blocks as usize],
+        }
+    }
+}
+
+impl ErrorType for RamFlash {
+    type Error = NorFlashErrorKind;
+}
+
+impl ReadNorFlash for RamFlash {
+    const READ_SIZE: usize = 1;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        check_read(self, offset, bytes.len())?;
+        let offset = offset as usize;
+        bytes.copy_from_slice(&self.data[offset..offset + bytes.len()]);
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        self.data.len()
+    }
+}
+
+impl NorFlash for RamFlash {
+    const WRITE_SIZE: usize = 4;
+    const ERASE_SIZE: usize = BLOCK as usize;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        check_erase(self, from, to)?;
+        self.data[from as usize..to as usize].fill(0xFF);
+        for block in from / BLOCK..to / BLOCK {
+            self.erases[block as usize] += 1;
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        check_write(self, offset, bytes.len())?;
+        for (cell, byte) in self.data[offset as usize..].iter_mut().zip(bytes) {
+            *cell &= byte;
+        }
+        Ok(())
+    }
+}
+
+fn settings(volts: f32) -> Settings {
+    Settings {
+        core: RailSettings {
+            voltage: volts,
+            current_limit: 150.,
+        },
+        mem: RailSettings {
+            voltage: 1.35,
+            current_limit: 40.,
+        },
+        profiles: Profiles::default(),
+        preferences: Preferences::default(),
+    }
+}
+
+/// A store over the first blocks of flash, with nothing loaded yet
+fn store(blocks: u32) -> SettingsStore {
+    SettingsStore::new(0..blocks * BLOCK, BLOCK)
+}
+
+#[test]
+fn blank_flash_has_no_settings() {
+    let mut flash = RamFlash::new(2);
+
+    assert_eq!(store(2).load(&mut flash), Ok(None));
+}
+
+#[test]
+fn saved_settings_are_loaded_after_a_restart() {
+    let mut flash = RamFlash::new(2);
+    let mut first = store(2);
+    first.load(&mut flash).unwrap();
+    first.save(&mut flash, &settings(1.0)).unwrap();
+    first.save(&mut flash, &settings(1.1)).unwrap();
+
+    let mut second = store(2);
+    assert_eq!(second.load(&mut flash), Ok(Some(settings(1.1))));
+    // Carries on after the newest record
+    second.save(&mut flash, &settings(1.2)).unwrap();
+    assert_eq!(store(2).load(&mut flash), Ok(Some(settings(1.2))));
+}
+
+#[test]
+fn unchanged_settings_are_not_written_again() {
+    let mut flash = RamFlash::new(2);
+    let mut store = store(2);
+    store.save(&mut flash, &settings(1.0)).unwrap();
+    let written = flash.data.clone();
+
+    store.save(&mut flash, &settings(1.0)).unwrap();
+
+    assert_eq!(flash.data, written);
+}
+
+#[test]
+fn the_log_wraps_and_wears_the_blocks_evenly() {
+    let mut flash = RamFlash::new(4);
+    let mut store = store(4);
+    let per_block = BLOCK as usize / SLOT;
+
+    for idx in 0..per_block * 4 * 3 {
+        store
+            .save(&mut flash, &settings(idx as f32 / 1000.))
+            .unwrap();
+    }
+
+    let last = (per_block * 4 * 3 - 1) as f32 / 1000.;
+    assert_eq!(self::store(4).load(&mut flash), Ok(Some(settings(last))));
+    // Three passes over the region, the first finding blank flash
+    assert_eq!(flash.erases, [2, 2, 2, 2]);
+}
+
+#[test]
+fn a_single_block_is_erased_when_full() {
+    let mut flash = RamFlash::new(1);
+    let mut store = store(1);
+    let per_block = BLOCK as usize / SLOT;
+
+    for idx in 0..=per_block {
+        store.save(&mut flash, &settings(idx as f32)).unwrap();
+    }
+
+    assert_eq!(flash.erases, [1]);
+    assert_eq!(
+        self::store(1).load(&mut flash),
+        Ok(Some(settings(per_block as f32)))
+    );
+}
+
+#[test]
+fn a_torn_record_falls_back_to_the_one_before() {
+    let mut flash = RamFlash::new(2);
+    let mut first = store(2);
+    first.save(&mut flash, &settings(1.0)).unwrap();
+    first.save(&mut flash, &settings(1.1)).unwrap();
+    // Power lost halfway through the second record
+    flash.data[SLOT + 12] = 0xFF;
+    flash.data[SLOT + 13] &= 0x0F;
+
+    let mut second = store(2);
+    assert_eq!(second.load(&mut flash), Ok(Some(settings(1.0))));
+
+    // The torn slot is skipped rather than written over
+    second.save(&mut flash, &settings(1.2)).unwrap();
+    assert_eq!(store(2).load(&mut flash), Ok(Some(settings(1.2))));
+    assert_eq!(flash.erases, [0, 0]);
+}
+
+#[test]
+fn misaligned_regions_are_refused() {
+    let mut flash = RamFlash::new(2);
+
+    let mut store = SettingsStore::new(0..BLOCK + 512, BLOCK);
+    assert_eq!(store.load(&mut flash), Err(SettingsError::NotAligned));
+    let mut store = SettingsStore::new(0..2 * BLOCK, BLOCK / 2);
+    assert_eq!(
+        store.save(&mut flash, &settings(1.0)),
+        Err(SettingsError::NotAligned)
+    );
+    let mut store = SettingsStore::new(0..4 * BLOCK, BLOCK);
+    assert_eq!(store.load(&mut flash), Err(SettingsError::OutOfBounds));
+}
+
+#[test]
+fn changes_are_only_saved_once_they_settle() {
+    let mut flash = RamFlash::new(2);
+    let mut store = store(2);
+    store.load(&mut flash).unwrap();
+
+    // A setpoint being stepped through restarts the wait every time it changes
+    let mut now = 0;
+    for volts in [1.0, 1.05, 1.1] {
+        assert_eq!(store.poll(&mut flash, &settings(volts), now), Ok(false));
+        now += SETTLE_US / 2;
+    }
+    // 1.1 V was first seen half a SETTLE_US ago
+    assert_eq!(store.poll(&mut flash, &settings(1.1), now), Ok(false));
+    assert_eq!(store.load(&mut flash), Ok(None));
+
+    now += SETTLE_US / 2;
+    assert_eq!(store.poll(&mut flash, &settings(1.1), now), Ok(true));
+    assert_eq!(store.poll(&mut flash, &settings(1.1), 2 * now), Ok(false));
+    assert_eq!(store.load(&mut flash), Ok(Some(settings(1.1))));
+}
+
+#[test]
+fn applied_settings_reach_the_controller_and_panel() {
+    let mut controller = controller(Tps536c7::default());
+    let mut dev = Device::default();
+
+    settings(1.05).apply(&mut controller, &mut dev).unwrap();
+
+    let core = controller.ch_a().unwrap();
+    assert!((core.vout_command().read().unwrap() - 1.05).abs() < 0.005);
+    assert!((core.iout_oc_fault_limit().read().unwrap() - 150.).abs() < 1.);
+    let profiles = Profiles::default();
+    let preferences = Preferences::default();
+    assert_eq!(
+        Settings::capture(&mut dev, &profiles, preferences),
+        Some(settings(1.05))
+    );
+    dev.mem().set_error(true);
+    assert_eq!(Settings::capture(&mut dev, &profiles, preferences), None);
+}
+
+#[test]
+fn preferences_survive_a_restart() {
+    let mut nav = Navigation::default();
+    let mut ramp = Ramp::default();
+    let mut margin = Margin::default();
+    nav.set_position((1, 2));
+    ramp.set_rate(1, 2.5).unwrap();
+    margin.set_percent(0, 10.).unwrap();
+    let mut saved = settings(1.0);
+    saved.preferences = Preferences::capture(&nav, &ramp, &margin);
+    let mut flash = RamFlash::new(2);
+    store(2).save(&mut flash, &saved).unwrap();
+
+    let loaded = store(2).load(&mut flash).unwrap().unwrap();
+    let mut nav = Navigation::default();
+    let mut ramp = Ramp::default();
+    let mut margin = Margin::default();
+    loaded.preferences.apply(&mut nav, &mut ramp, &mut margin);
+
+    assert_eq!(nav.get_position(), (1, 2));
+    assert_eq!(ramp.rate(1), 2.5);
+    assert_eq!(margin.percent(0), 10.);
+    // Values out of range keep the defaults
+    Preferences::default().apply(&mut nav, &mut ramp, &mut margin);
+    assert_eq!(nav.get_position(), (0, 0));
+    assert_eq!(ramp.rate(1), 2.5);
+    assert_eq!(margin.percent(0), 10.);
+}
+
+#[test]
+fn every_profile_slot_fits_in_a_record() {
+    let mut flash = RamFlash::new(2);
+    let mut full = settings(1.0);
+    let name = Name::new(&"W".repeat(NAME_LEN)).unwrap();
+    for slot in 0..PROFILES {
+        full.profiles
+            .set(
+                slot,
+                Profile {
+                    name,
+                    ..Profile::default()
+                },
+            )
+            .unwrap();
+    }
+
+    store(2).save(&mut flash, &full).unwrap();
+
+    assert_eq!(store(2).load(&mut flash), Ok(Some(full)));
+}
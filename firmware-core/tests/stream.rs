@@ -0,0 +1,156 @@
+mod common;
+
+use common::controller;
+use firmware_core::protocol::{Request, RequestError, Response, StreamConfig};
+use firmware_core::stream::Streamer;
+use firmware_core::vrm_controller::TPSC536C7;
+use firmware_core::vrm_status::StatusWord;
+use tps536c7_simulator::{Channel, Fault, Tps536c7, ADDRESS};
+
+fn config(rate_hz: u16, fields: u8) -> StreamConfig {
+    StreamConfig {
+        rate_hz,
+        fields,
+        core: true,
+        mem: true,
+    }
+}
+
+#[test]
+fn samples_come_due_once_per_period() {
+    let mut streamer = Streamer::default();
+    assert_eq!(streamer.poll(0), None);
+
+    // 1 kHz, 1000 us apart
+    streamer
+        .start(config(1000, StreamConfig::ALL), 500)
+        .unwrap();
+
+    assert_eq!(streamer.poll(500), Some(500));
+    assert_eq!(streamer.poll(900), None);
+    assert_eq!(streamer.poll(1600), Some(1500));
+    assert_eq!(streamer.poll(2000), None);
+}
+
+#[test]
+fn missed_periods_count_as_overruns() {
+    let mut streamer = Streamer::default();
+    streamer.start(config(100, StreamConfig::VOUT), 0).unwrap();
+    let mut controller = controller(Tps536c7::default());
+
+    assert_eq!(streamer.poll(0), Some(0));
+    // Three 10 ms periods late, the newest due sample is taken and the two before it are lost
+    assert_eq!(streamer.poll(35_000), Some(30_000));
+    streamer.dropped();
+
+    let (seq, sample) = streamer.sample(&mut controller, 30_000);
+    assert_eq!(seq, 0);
+    assert_eq!(sample.overruns, 3);
+    assert_eq!(streamer.poll(39_999), None);
+    assert_eq!(streamer.poll(40_000), Some(40_000));
+}
+
+#[test]
+fn scheduling_survives_the_clock_wrapping() {
+    let mut streamer = Streamer::default();
+    streamer
+        .start(config(10, StreamConfig::VOUT), u32::MAX - 50_000)
+        .unwrap();
+
+    assert_eq!(streamer.poll(u32::MAX - 50_000), Some(u32::MAX - 50_000));
+    assert_eq!(streamer.poll(u32::MAX), None);
+    assert_eq!(streamer.poll(49_999), Some(49_999));
+}
+
+#[test]
+fn rates_outside_the_range_are_refused() {
+    let mut streamer = Streamer::default();
+
+    for rate_hz in [0, 9, 1001] {
+        assert_eq!(
+            streamer.handle(&Request::StartStream(config(rate_hz, StreamConfig::ALL)), 0),
+            Some(Response::Error(RequestError::InvalidRate))
+        );
+    }
+    assert!(!streamer.is_running());
+    assert_eq!(
+        streamer.handle(&Request::StartStream(config(10, StreamConfig::ALL)), 0),
+        Some(Response::Ack)
+    );
+    assert!(streamer.is_running());
+    assert_eq!(
+        streamer.handle(&Request::StopStream, 0),
+        Some(Response::Ack)
+    );
+    assert!(!streamer.is_running());
+    // Everything else is left to control::handle_request
+    assert_eq!(streamer.handle(&Request::Ping, 0), None);
+}
+
+#[test]
+fn samples_hold_only_the_selected_fields() {
+    let mut sim = Tps536c7::default();
+    sim.set_load(Channel::A, 80.);
+    let mut controller = controller(sim);
+    let mut streamer = Streamer::default();
+    streamer
+        .start(
+            StreamConfig {
+                rate_hz: 1000,
+                fields: StreamConfig::VOUT | StreamConfig::IOUT,
+                core: true,
+                mem: false,
+            },
+            0,
+        )
+        .unwrap();
+
+    let (_, sample) = streamer.sample(&mut controller, 1234);
+
+    assert_eq!(sample.tick_us, 1234);
+    assert!(sample.mem.is_none());
+    let core = sample.core.unwrap();
+    assert!((core.vout.unwrap() - 0.9).abs() < 0.01);
+    assert!((core.iout.unwrap() - 80.).abs() < 0.01);
+    assert_eq!(core.temperature, None);
+    assert_eq!(core.vout_setpoint, None);
+    assert_eq!(core.status, None);
+    assert!(!core.error);
+}
+
+#[test]
+fn setpoints_and_status_are_sampled() {
+    let mut sim = Tps536c7::default();
+    sim.inject_fault(Channel::B, Fault::OverTemperature);
+    let mut controller = controller(sim);
+    let mut streamer = Streamer::default();
+    streamer
+        .start(
+            config(100, StreamConfig::SETPOINTS | StreamConfig::STATUS),
+            0,
+        )
+        .unwrap();
+
+    let (_, sample) = streamer.sample(&mut controller, 0);
+
+    let mem = sample.mem.unwrap();
+    assert!((mem.vout_setpoint.unwrap() - 1.35).abs() < 0.01);
+    assert!((mem.current_limit.unwrap() - 60.).abs() < 0.1);
+    assert!(mem.status.unwrap().contains(StatusWord::TEMPERATURE));
+    assert!(!sample.core.unwrap().status.unwrap().is_faulted());
+}
+
+#[test]
+fn failed_reads_flag_the_channel() {
+    // Nothing answers on the controller's address
+    let mut controller = TPSC536C7::new(Tps536c7::new(0x10), ADDRESS, true);
+    let mut streamer = Streamer::default();
+    streamer.start(config(100, StreamConfig::VOUT), 0).unwrap();
+
+    let (_, sample) = streamer.sample(&mut controller, 0);
+
+    let core = sample.core.unwrap();
+    assert!(core.error);
+    assert_eq!(core.vout, None);
+    assert!(sample.mem.unwrap().error);
+}
@@ -0,0 +1,229 @@
+use embedded_hal::i2c::{ErrorKind, ErrorType, I2c, NoAcknowledgeSource, Operation};
+use firmware_core::pmbus::{Command, GenericPmbus, PmbusDevice};
+use firmware_core::vrm_controller::TPSC536C7;
+use firmware_core::vrm_status::{
+    ChannelStatus, StatusByte, StatusCml, StatusInput, StatusIout, StatusTemperature, StatusVout,
+    StatusWord,
+};
+
+const ADDRESS: u8 = 0x60;
+
+/// A bus with a single register on it, anything else is NACKed
+struct Register {
+    cmd: u8,
+    bytes: Vec<u8>,
+}
+
+impl Register {
+    fn new(cmd: Command, bytes: &[u8]) -> Register {
+        Register {
+            cmd: cmd.to_address(),
+            bytes: bytes.to_vec(),
+        }
+    }
+}
+
+impl ErrorType for Register {
+    type Error = ErrorKind;
+}
+
+impl I2c for Register {
+    fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        let nack = ErrorKind::NoAcknowledge(NoAcknowledgeSource::Data);
+        match operations {
+            [Operation::Write(&[cmd]), Operation::Read(buf)]
+                if address == ADDRESS && cmd == self.cmd =>
+            {
+                buf.fill(0);
+                let len = buf.len().min(self.bytes.len());
+                buf[..len].copy_from_slice(&self.bytes[..len]);
+                Ok(())
+            }
+            _ => Err(nack),
+        }
+    }
+}
+
+/// Reads a byte wide status register through the generic driver with only bit set
+fn read_bit<T>(cmd: Command, bit: u8, read: fn(&mut GenericPmbus<Register>) -> T) -> T {
+    let mut dev = GenericPmbus::new(Register::new(cmd, &[1 << bit]), ADDRESS, false);
+    read(&mut dev)
+}
+
+/// Checks that every flag decodes from its own bit, and only from it
+macro_rules! assert_bits {
+    ($type:ident, $cmd:expr, $read:ident, { $($flag:ident = $bit:expr),* $(,)? }) => {
+        let flags = [$(($type::$flag, $bit)),*];
+        for (flag, bit) in flags {
+            assert_eq!(flag, 1 << bit, "{} bit {bit}", stringify!($type));
+            let status = read_bit($cmd, bit, |dev| dev.$read().unwrap());
+            assert_eq!(status.bits(), flag);
+            for (other, _) in flags {
+                assert_eq!(status.contains(other), other == flag);
+            }
+        }
+    };
+}
+
+#[test]
+fn status_byte_bits() {
+    assert_bits!(StatusByte, Command::StatusByte, status_byte, {
+        NONE_OF_THE_ABOVE = 0,
+        CML = 1,
+        TEMPERATURE = 2,
+        VIN_UV = 3,
+        IOUT_OC = 4,
+        VOUT_OV = 5,
+        OFF = 6,
+        BUSY = 7,
+    });
+}
+
+#[test]
+fn status_word_bits() {
+    let flags = [
+        (StatusWord::NONE_OF_THE_ABOVE, 0),
+        (StatusWord::CML, 1),
+        (StatusWord::TEMPERATURE, 2),
+        (StatusWord::VIN_UV, 3),
+        (StatusWord::IOUT_OC, 4),
+        (StatusWord::VOUT_OV, 5),
+        (StatusWord::OFF, 6),
+        (StatusWord::BUSY, 7),
+        (StatusWord::UNKNOWN, 8),
+        (StatusWord::OTHER, 9),
+        (StatusWord::FANS, 10),
+        (StatusWord::POWER_GOOD_N, 11),
+        (StatusWord::MFR_SPECIFIC, 12),
+        (StatusWord::INPUT, 13),
+        (StatusWord::IOUT_POUT, 14),
+        (StatusWord::VOUT, 15),
+    ];
+    for (flag, bit) in flags {
+        assert_eq!(flag, 1 << bit);
+        // Little endian on the wire
+        let bytes = (1u16 << bit).to_le_bytes();
+        let mut dev = GenericPmbus::new(Register::new(Command::StatusWord, &bytes), ADDRESS, false);
+        let status = dev.status_word().unwrap();
+        assert_eq!(status.bits(), flag);
+        for (other, _) in flags {
+            assert_eq!(status.contains(other), other == flag);
+        }
+        assert_eq!(status.status_byte().bits(), (1u16 << bit) as u8);
+        // Only the output being off, busy, not power good or nothing in particular is no fault
+        let benign = matches!(bit, 0 | 6 | 7 | 11);
+        assert_eq!(status.is_faulted(), !benign, "bit {bit}");
+        assert_eq!(status.is_power_good(), bit != 11);
+    }
+}
+
+#[test]
+fn status_vout_bits() {
+    assert_bits!(StatusVout, Command::StatusVout, status_vout, {
+        VOUT_TRACKING_ERROR = 0,
+        TOFF_MAX_WARN = 1,
+        TON_MAX_FAULT = 2,
+        VOUT_MAX_MIN_WARN = 3,
+        VOUT_UV_FAULT = 4,
+        VOUT_UV_WARN = 5,
+        VOUT_OV_WARN = 6,
+        VOUT_OV_FAULT = 7,
+    });
+}
+
+#[test]
+fn status_iout_bits() {
+    assert_bits!(StatusIout, Command::StatusIout, status_iout, {
+        POUT_OP_WARN = 0,
+        POUT_OP_FAULT = 1,
+        POWER_LIMIT = 2,
+        CURRENT_SHARE_FAULT = 3,
+        IOUT_UC_FAULT = 4,
+        IOUT_OC_WARN = 5,
+        IOUT_OC_LV_FAULT = 6,
+        IOUT_OC_FAULT = 7,
+    });
+}
+
+#[test]
+fn status_input_bits() {
+    assert_bits!(StatusInput, Command::StatusInput, status_input, {
+        PIN_OP_WARN = 0,
+        IIN_OC_WARN = 1,
+        IIN_OC_FAULT = 2,
+        UNIT_OFF_LOW_VIN = 3,
+        VIN_UV_FAULT = 4,
+        VIN_UV_WARN = 5,
+        VIN_OV_WARN = 6,
+        VIN_OV_FAULT = 7,
+    });
+}
+
+#[test]
+fn status_temperature_bits() {
+    assert_bits!(StatusTemperature, Command::StatusTemperature, status_temperature, {
+        UT_FAULT = 4,
+        UT_WARN = 5,
+        OT_WARN = 6,
+        OT_FAULT = 7,
+    });
+    // The reserved bits decode to no flag
+    for bit in 0..4 {
+        let status = read_bit(Command::StatusTemperature, bit, |dev| {
+            dev.status_temperature().unwrap()
+        });
+        let flags = StatusTemperature::UT_FAULT
+            | StatusTemperature::UT_WARN
+            | StatusTemperature::OT_WARN
+            | StatusTemperature::OT_FAULT;
+        assert!(!status.contains(flags));
+    }
+}
+
+#[test]
+fn status_cml_bits() {
+    assert_bits!(StatusCml, Command::StatusCml, status_cml, {
+        OTHER_MEMORY_FAULT = 0,
+        OTHER_COMM_FAULT = 1,
+        PROCESSOR_FAULT = 3,
+        MEMORY_FAULT = 4,
+        PEC_FAILED = 5,
+        INVALID_DATA = 6,
+        INVALID_COMMAND = 7,
+    });
+}
+
+#[test]
+fn status_all_splits_into_the_registers_of_each_channel() {
+    // Byte count, then per channel STATUS_WORD (little endian), VOUT, IOUT, INPUT, TEMPERATURE,
+    // CML and MFR_SPECIFIC, each with a different bit set
+    let mut block = vec![2 * ChannelStatus::LENGTH as u8];
+    block.extend([0x01, 0x80, 0x02, 0x04, 0x08, 0x10, 0x20, 0x40]);
+    block.extend([0x40, 0x08, 0x80, 0x01, 0x02, 0x80, 0x01, 0x02]);
+    let mut controller = TPSC536C7::new(Register::new(Command::StatusAll, &block), ADDRESS, false);
+
+    let [a, b] = controller.read_status_all().unwrap();
+
+    assert_eq!(
+        a.word.bits(),
+        StatusWord::NONE_OF_THE_ABOVE | StatusWord::VOUT
+    );
+    assert!(a.vout.contains(StatusVout::TOFF_MAX_WARN));
+    assert!(a.iout.contains(StatusIout::POWER_LIMIT));
+    assert!(a.input.contains(StatusInput::UNIT_OFF_LOW_VIN));
+    assert!(a.temperature.contains(StatusTemperature::UT_FAULT));
+    assert!(a.cml.contains(StatusCml::PEC_FAILED));
+    assert_eq!(a.mfr_specific, 0x40);
+
+    assert_eq!(b.word.bits(), StatusWord::OFF | StatusWord::POWER_GOOD_N);
+    assert!(b.vout.contains(StatusVout::VOUT_OV_FAULT));
+    assert!(b.iout.contains(StatusIout::POUT_OP_WARN));
+    assert!(b.input.contains(StatusInput::IIN_OC_WARN));
+    assert!(b.temperature.contains(StatusTemperature::OT_FAULT));
+    assert!(b.cml.contains(StatusCml::OTHER_MEMORY_FAULT));
+    assert_eq!(b.mfr_specific, 0x02);
+}
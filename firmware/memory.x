@@ -1,6 +1,17 @@
-/* Linker script for the STM32C031C6Tx */
+/* Linker script for the STM32F401 (128K flash) */
+/*
+ * Flash budget:
+ *   sector 0      0x08000000  16K  vector table only
+ *   sectors 1, 2  0x08004000  32K  settings log (firmware-core/src/settings.rs), two blocks so
+ *                                  the block erased next never holds the newest record
+ *   sectors 3, 4  0x0800C000  80K  program, from _stext on
+ */
 MEMORY
 {
   FLASH : ORIGIN = 0x08000000, LENGTH = 128K
+  SETTINGS : ORIGIN = 0x08004000, LENGTH = 32K
   RAM : ORIGIN = 0x20000000, LENGTH = 24K
 }
+
+/* Keeps the program out of the settings sectors */
+_stext = ORIGIN(SETTINGS) + LENGTH(SETTINGS);
@@ -5,6 +5,7 @@ use cortex_m_rt::entry;
 
 use stm32f4xx_hal::{self as hal, gpio::PinState, i2c::I2c, pac, prelude::*};
 
+use stm32f4xx_hal::flash::FlashExt;
 use stm32f4xx_hal::otg_fs::{UsbBus, USB};
 use usb_device::prelude::*;
 
@@ -24,14 +25,27 @@ use defmt_rtt as _;
 
 use panic_semihosting as _; // Sends Backtraces through Probe-rs
 
-use navigation::Navigation;
+use firmware_core::control::{handle_request, update_phase_read, update_vrm_read};
+use firmware_core::margin::Margin;
+use firmware_core::navigation::{self, Action, Button, Navigation};
+use firmware_core::pmbus::{self, ControllerKind, PmbusDevice};
+use firmware_core::profile::Profiles;
+use firmware_core::protocol::{self, FrameError, FrameReader, RequestError, Response};
+use firmware_core::ramp::{Ramp, RampEvent};
+use firmware_core::safety::{Rejection, Violation};
+use firmware_core::scpi::{self, LineReader, Scpi};
+use firmware_core::sequence::{Ready, Sequencer, State};
+use firmware_core::settings::{Preferences, Settings, SettingsStore};
+use firmware_core::stream::Streamer;
+use firmware_core::vrm_controller;
 use usbd_serial::embedded_io::{ReadReady, WriteReady};
-use vrm_controller::TPSC536C7;
-mod navigation;
-mod vrm_controller;
 
 static mut EP_MEMORY: [u32; 1024] = [0; 1024];
 
+// Flash sectors 1 and 2 as offsets from the start of flash, left out of the program in memory.x
+const SETTINGS_REGION: core::ops::Range<u32> = 0x4000..0xC000;
+const SETTINGS_SECTOR: u32 = 0x4000;
+
 #[entry]
 fn main() -> ! {
     defmt::info!("System Starting");
@@ -39,6 +53,7 @@ fn main() -> ! {
     //** Microcontroller Configuration **//
     let dp = pac::Peripherals::take().expect("Cannot Take Peripherals");
     let rcc = dp.RCC.constrain();
+    let mut flash = dp.FLASH;
     let clocks = rcc
         .cfgr
         .use_hse(8.MHz())
@@ -54,9 +69,10 @@ fn main() -> ! {
     let mut led = gpioa.pa5.into_push_pull_output();
 
     //** Enable VRM Controller Pins **//
-    let _avr_ready = gpioc.pc1.into_floating_input();
-    let _bvr_ready = gpioc.pc2.into_floating_input();
-    let _b_enable = gpioc.pc10.into_push_pull_output_in_state(PinState::High);
+    // VR_READY of each rail and the memory rail's enable, all owned by the power sequencer
+    let avr_ready = gpioc.pc1.into_floating_input();
+    let bvr_ready = gpioc.pc2.into_floating_input();
+    let mut b_enable = gpioc.pc10.into_push_pull_output_in_state(PinState::Low);
 
     //** Enable Onboard Control Pins **//
     let up = gpioc.pc6.into_pull_up_input();
@@ -91,9 +107,21 @@ fn main() -> ! {
 
     //** VRM Controller Initialization **//
     let i2c_addr = 0x5F;
+    // Packet error checking, guards setpoint writes against bus noise from the VRM
+    let i2c_pec = true;
 
     // Create Controller
-    let mut controller = vrm_controller::TPSC536C7::new(i2c1, i2c_addr);
+    let mut controller = vrm_controller::TPSC536C7::new(i2c1, i2c_addr, i2c_pec);
+    // Only the TPS536C7 is supported, any other controller is reported but still driven as one
+    match ControllerKind::read(&mut controller) {
+        Ok(ControllerKind::Tps536c7) => (),
+        Ok(kind) => defmt::warn!("Unexpected VRM Controller: {}", kind),
+        Err(err) => defmt::error!("VRM Controller Identify Failed: {}", err),
+    }
+    // Read the output voltage format of each channel
+    if let Err(err) = controller.init() {
+        defmt::error!("VRM Controller Init Failed: {}", err);
+    }
     defmt::info!("Past VRM Controller Init");
 
     //** Display Configuration **//
@@ -123,18 +151,7 @@ fn main() -> ! {
     let hollow = PrimitiveStyle::with_stroke(BinaryColor::On, 1);
 
     // GUI Text
-    Text::with_baseline("   Vcore Vmem", Point::zero(), text_style, Baseline::Top)
-        .draw(&mut display)
-        .unwrap();
-    Text::with_baseline("V:", Point::new(0, 16), text_style, Baseline::Top)
-        .draw(&mut display)
-        .unwrap();
-    Text::with_baseline("A:", Point::new(0, 32), text_style, Baseline::Top)
-        .draw(&mut display)
-        .unwrap();
-    Text::with_baseline("T:", Point::new(0, 48), text_style, Baseline::Top)
-        .draw(&mut display)
-        .unwrap();
+    display_labels(&mut display, text_style, "OFF");
 
     // Flush Display
     display.flush().unwrap();
@@ -150,7 +167,12 @@ fn main() -> ! {
     #[allow(static_mut_refs)] // Not My implementation
     let usb_bus = UsbBus::new(usb, unsafe { &mut EP_MEMORY });
 
-    let mut serial = usbd_serial::SerialPort::new(&usb_bus);
+    // Room for a whole frame each way, so a frame is never split by a full buffer
+    let mut serial = usbd_serial::SerialPort::new_with_store(
+        &usb_bus,
+        [0u8; protocol::MAX_FRAME],
+        [0u8; protocol::MAX_FRAME],
+    );
 
     let mut usb_dev = UsbDeviceBuilder::new(&usb_bus, UsbVidPid(0x16c0, 0x27dd))
         .device_class(usbd_serial::USB_CLASS_CDC)
@@ -164,159 +186,247 @@ fn main() -> ! {
     defmt::info!("USB Initialized");
 
     // Timer Configuration
+    let mut delay = dp.TIM5.delay_us(&clocks);
     let mut led_time = dp.TIM1.counter_ms(&clocks);
     let mut ui_time = dp.TIM3.counter_ms(&clocks);
+    // Free running microsecond clock for stream timestamps, TIM2 is 32 bit so it wraps after
+    // about 71 minutes
+    let mut clock = dp.TIM2.counter_us(&clocks);
     led_time.start(1000.millis()).unwrap();
     ui_time.start(100.millis()).unwrap();
+    clock.start(u32::MAX.micros()).unwrap();
 
     // Current State of Devices
     let mut nav = Navigation::default();
     let mut dev = navigation::Device::default();
-    let mut updated_val: f32 = 10.;
-
+    let mut frames = FrameReader::default();
+    let mut telemetry_seq = 0u16;
+    let mut streamer = Streamer::default();
+    // SCPI text interface, selected by the host with scpi::HANDSHAKE
+    let mut text_mode = false;
+    let mut lines = LineReader::default();
+    let mut scpi = Scpi::default();
+    let mut profiles = Profiles::default();
+    // Voltage setpoint changes from every source are ramped to
+    let mut ramp = Ramp::default();
+    let mut settings = SettingsStore::new(SETTINGS_REGION, SETTINGS_SECTOR);
+    // Margins the rails while they are up, from the panel and USB
+    let mut margin = Margin::default();
+    // Last write the safety envelope refused, shown until a button is pressed
+    let mut rejected: Option<Rejection> = None;
+    // Last ramp that aborted or failed, shown the same way
+    let mut ramp_aborted: Option<RampEvent> = None;
+
+    // Both rails off before anything is written to them, the setpoints below are only applied
+    // once the sequencer brings the rails up
+    let mut sequencer = Sequencer::default();
+    if let Err(err) = sequencer.init(&mut controller) {
+        defmt::error!("Failed to Turn Off Outputs: {}", err);
+    }
+    // Hardware limits from the safety envelope, next so a restored setpoint is held to them
+    if let Err(err) = controller.apply_envelope() {
+        defmt::error!("Failed to Program VOUT_MAX / VOUT_MIN: {}", err);
+    }
+    nav.set_envelope(*controller.envelope());
+    // Restore the last setpoints before the outputs come up, and the cursor, ramp rates and
+    // margins with them
+    match settings.load(&mut flash.unlocked()) {
+        Ok(Some(saved)) => {
+            profiles = saved.profiles;
+            saved.preferences.apply(&mut nav, &mut ramp, &mut margin);
+            match saved.apply(&mut controller, &mut dev) {
+                Ok(()) => defmt::info!("Settings Restored: {}", saved),
+                Err(err) => defmt::error!("Settings Restore Failed: {}", err),
+            }
+        }
+        Ok(None) => defmt::info!("No Saved Settings"),
+        Err(err) => defmt::error!("Settings Load Failed: {}", err),
+    }
     // Get Initial Values
     update_vrm_read(&mut dev, &mut controller);
-    // Enable the device
-    controller.ch_ab().on_off_config(0x00);
-    // No Minimum Output Voltage
-    controller.vout_min().write(0.);
-
+    // Bring the rails up in order, memory before core
+    if let Err(err) = sequencer.power_up(&mut controller) {
+        defmt::error!("Failed to Enable Device: {}", err);
+    }
     loop {
-        let mut update_display = true;
+        // Polled every pass so a stream can keep the USB endpoint busy between UI ticks
+        usb_dev.poll(&mut [&mut serial]);
 
         if led_time.wait().is_ok() {
             led.toggle();
+
+            // Per phase values are only needed for spotting an unbalanced power stage, so they
+            // are read far less often than the main telemetry
+            update_phase_read(&mut dev, &mut controller);
         }
 
         // Code that Runs Periodically
         if ui_time.wait().is_ok() {
             // Button Input
-            match nav.get_mode() {
-                navigation::Mode::Navigation => {
-                    if up.is_low() {
-                        defmt::info!("Up");
-                        nav.move_up();
-                    } else if down.is_low() {
-                        defmt::info!("Down");
-                        nav.move_down();
-                    } else if right.is_low() {
-                        defmt::info!("Right");
-                        nav.move_right();
-                    } else if left.is_low() {
-                        defmt::info!("Left");
-                        nav.move_left();
-                    } else if enter.is_low() {
-                        defmt::info!("Enter");
-                        if nav.get_position().1 == 2 {
-                            continue;
+            let button = if up.is_low() {
+                Some(Button::Up)
+            } else if down.is_low() {
+                Some(Button::Down)
+            } else if right.is_low() {
+                Some(Button::Right)
+            } else if left.is_low() {
+                Some(Button::Left)
+            } else if enter.is_low() {
+                Some(Button::Enter)
+            } else {
+                None
+            };
+            if let Some(button) = button {
+                defmt::info!("Button: {}", button);
+                // Any button only dismisses a shown rejection or ramp abort
+                let action = match (rejected.take(), ramp_aborted.take()) {
+                    (None, None) => nav.press(button, &mut dev),
+                    _ => Action::None,
+                };
+                match action {
+                    Action::Write(position, val) => {
+                        if let Err(err) = ramp.write_setpoint(&mut controller, position, val) {
+                            defmt::error!("Failed to Write Setpoint: {}", err);
                         }
-                        nav.change_mode();
-
-                        // temporary value that is being updated
-                        updated_val = match nav.get_position() {
-                            (0, 0) => dev.core().get_voltage_setpoint(),
-                            (0, 1) => dev.core().get_current_limit(),
-                            (0, 2) => dev.core().get_temperature(),
-                            (1, 0) => dev.mem().get_voltage_setpoint(),
-                            (1, 1) => dev.mem().get_current_limit(),
-                            (1, 2) => dev.mem().get_temperature(),
-                            (_, _) => 0., // Default condition that sound never match
-                        };
-                    } else {
-                        update_display = false;
                     }
-                }
-                navigation::Mode::Update => {
-                    let (step_small, step_large) = match nav.get_position() {
-                        (_, 0) => (0.005, 0.1),
-                        (_, 1) => (1., 10.),
-                        (_, _) => (0., 0.),
-                    };
-                    if up.is_low() {
-                        updated_val += step_small;
-                    } else if down.is_low() {
-                        updated_val -= step_small;
-                    } else if right.is_low() {
-                        updated_val += step_large;
-                    } else if left.is_low() {
-                        updated_val -= step_large;
-                    } else if enter.is_low() {
-                        nav.change_mode();
-
-                        // Write Data to I2C and update dev voltages here
-                        dev.store_value(nav.get_position(), updated_val);
-
-                        match nav.get_position() {
-                            (0, _) => {
-                                controller.ch_a();
-                            }
-                            (1, _) => {
-                                controller.ch_b();
-                            }
-                            (_, _) => (),
-                        };
-                        match nav.get_position() {
-                            (_, 0) => controller.vout_command().write(updated_val),
-                            (_, 1) => controller.iout_oc_fault_limit().write(updated_val),
-                            (_, _) => (), // Default condition that sound never match
-                        };
-                    } else {
-                        update_display = false;
+                    Action::StoreNvm => match controller.store_default_all(&mut delay) {
+                        Ok(()) => defmt::info!("Settings Stored to Controller NVM"),
+                        Err(err) => defmt::error!("NVM Store Failed: {}", err),
+                    },
+                    Action::LoadProfile(slot) => {
+                        let result = profiles.load(slot, &mut controller, &mut ramp, &mut dev);
+                        if let Err(err) = result {
+                            defmt::error!("Failed to Load Profile {}: {}", slot, err);
+                        }
+                    }
+                    Action::SaveProfile(slot) => {
+                        if let Err(err) = profiles.save(slot, None, &mut controller, &ramp) {
+                            defmt::error!("Failed to Save Profile {}: {}", slot, err);
+                        }
+                    }
+                    Action::ToggleOutput => {
+                        if let Err(err) = sequencer.toggle(&mut controller) {
+                            defmt::error!("Failed to Switch Output: {}", err);
+                        }
+                    }
+                    Action::Margin(channel, level) => {
+                        let result = margin.set_level(&mut controller, &sequencer, channel, level);
+                        if let Err(err) = result {
+                            defmt::error!("Failed to Margin {}: {}", channel, err);
+                        }
                     }
+                    Action::None => (),
                 }
             }
 
+            // Refused by the panel above or by SCPI and USB requests since the last tick
+            if let Some(rejection) = controller.take_rejection() {
+                rejected = Some(rejection);
+            }
+
             // Runs only if there is a value to update on the display to save on unnecessary write
             // cycles and full display clears
-            // if update_display {
             // Updates the displays for all stored values
-            // Vcore
-            display_data(
-                &mut display,
-                text_style,
-                fill,
-                navigation::translate_point((0, 0)),
-                dev.core().get_voltage(),
-            );
-            display_data(
-                &mut display,
-                text_style,
-                fill,
-                navigation::translate_point((0, 1)),
-                dev.core().get_current(),
-            );
-            display_data(
-                &mut display,
-                text_style,
-                fill,
-                navigation::translate_point((0, 2)),
-                dev.core().get_temperature(),
-            );
-            // Vmem
-            display_data(
-                &mut display,
-                text_style,
-                fill,
-                navigation::translate_point((1, 0)),
-                dev.mem().get_voltage(),
-            );
-            display_data(
-                &mut display,
-                text_style,
-                fill,
-                navigation::translate_point((1, 1)),
-                dev.mem().get_current(),
-            );
-            display_data(
-                &mut display,
-                text_style,
-                fill,
-                navigation::translate_point((1, 2)),
-                dev.mem().get_temperature(),
-            );
+            if let Some(rejection) = &rejected {
+                clear_display(&mut display, fill);
+                display_rejection(&mut display, text_style, fill, rejection);
+            } else if let Some(event) = &ramp_aborted {
+                clear_display(&mut display, fill);
+                display_ramp_abort(&mut display, text_style, event);
+            } else if let navigation::Mode::Confirm = nav.get_mode() {
+                clear_display(&mut display, fill);
+                display_prompt(
+                    &mut display,
+                    text_style,
+                    &["Save to NVM?", "Up: Save", "Other: Back"],
+                );
+            } else if let navigation::Mode::Output = nav.get_mode() {
+                clear_display(&mut display, fill);
+                let enter = match sequencer.state() {
+                    State::MemRamp | State::CoreRamp | State::On => "Enter: Off",
+                    _ => "Enter: On",
+                };
+                display_prompt(
+                    &mut display,
+                    text_style,
+                    &["Output:", sequencer.state().as_str(), enter, "Other: Back"],
+                );
+            } else if let navigation::Mode::Margin = nav.get_mode() {
+                clear_display(&mut display, fill);
+                let channel = nav.get_position().0 as usize;
+                let rail = match channel {
+                    0 => "Vcore",
+                    _ => "Vmem",
+                };
+                display_prompt(
+                    &mut display,
+                    text_style,
+                    &[rail, "Now:", "Set:", "Enter: Apply"],
+                );
+                for (row, level) in [(1, margin.level(channel)), (2, nav.get_margin())] {
+                    Text::with_baseline(
+                        level.as_str(),
+                        Point::new(45, 16 * row),
+                        text_style,
+                        Baseline::Top,
+                    )
+                    .draw(&mut display)
+                    .unwrap();
+                }
+                // Percentage next to the rail
+                display_data(
+                    &mut display,
+                    text_style,
+                    fill,
+                    Point::new(72, 0),
+                    margin.percent(channel),
+                );
+                Text::with_baseline("%", Point::new(117, 0), text_style, Baseline::Top)
+                    .draw(&mut display)
+                    .unwrap();
+            } else if let navigation::Mode::Profile = nav.get_mode() {
+                clear_display(&mut display, fill);
+                display_profiles(
+                    &mut display,
+                    (text_style, text_style_inv),
+                    (fill, fill_inv),
+                    &profiles,
+                    nav.get_profile(),
+                );
+            } else {
+                // Buffered, so clearing every redraw only drops what a prompt left behind
+                clear_display(&mut display, fill);
+                let output = match sequencer.state() {
+                    State::On => "ON",
+                    State::Fault(_) => "FLT",
+                    _ => "OFF",
+                };
+                display_labels(&mut display, text_style, output);
+                // Vcore
+                display_channel(
+                    &mut display,
+                    (text_style, text_style_inv),
+                    (fill, fill_inv),
+                    0,
+                    "Vcore",
+                    dev.core(),
+                    ramp.progress(0),
+                );
+                // Vmem
+                display_channel(
+                    &mut display,
+                    (text_style, text_style_inv),
+                    (fill, fill_inv),
+                    1,
+                    "Vmem",
+                    dev.mem(),
+                    ramp.progress(1),
+                );
+            }
 
             // Update Currently Hovered
             match nav.get_mode() {
+                _ if rejected.is_some() || ramp_aborted.is_some() => (),
                 navigation::Mode::Navigation => {
                     Rectangle::new(nav.get_point(), Size::new(9 * 5, 16))
                         .into_styled(hollow)
@@ -329,9 +439,13 @@ fn main() -> ! {
                         text_style_inv,
                         fill_inv,
                         nav.get_point(),
-                        updated_val,
+                        nav.get_value(),
                     );
                 }
+                navigation::Mode::Confirm
+                | navigation::Mode::Profile
+                | navigation::Mode::Output
+                | navigation::Mode::Margin => (),
             }
 
             display.flush().unwrap();
@@ -339,24 +453,60 @@ fn main() -> ! {
             // Read new I2C Values (at end so that it has the whole UI time for the values to
             // collect)
             update_vrm_read(&mut dev, &mut controller);
+            // Catches setpoints changed from the panel, SCPI or USB requests alike, once any
+            // ramp has settled and the new values have stayed put for a while
+            if !ramp.is_running() {
+                save_settings(
+                    &mut settings,
+                    &mut flash,
+                    &mut dev,
+                    &profiles,
+                    Preferences::capture(&nav, &ramp, &margin),
+                    clock.now().ticks(),
+                );
+            }
 
-            // USB Handling
-            if !usb_dev.poll(&mut [&mut serial]) {
-                continue; // This means that the port cannot read or write currently
+            // USB to send values to computer, frames would garble the replies of the text mode
+            if !text_mode && serial.write_ready().unwrap() {
+                send_frame(
+                    &mut serial,
+                    telemetry_seq,
+                    &protocol::Message::Telemetry(dev.clone()),
+                );
+                telemetry_seq = telemetry_seq.wrapping_add(1);
             }
+        }
 
-            // USB to send values to computer
-            if serial.write_ready().unwrap() {
-                let mut slice = [0u8; 128];
-                let length =
-                    bincode::encode_into_slice(&dev, &mut slice, bincode::config::standard())
-                        .unwrap();
+        // Host configured telemetry stream, samples that come due while the UI redraws are
+        // counted as overruns
+        if let Some(tick) = streamer.poll(clock.now().ticks()) {
+            let (seq, sample) = streamer.sample(&mut controller, tick);
+            if !send_frame(&mut serial, seq, &protocol::Message::Sample(sample)) {
+                streamer.dropped();
+            }
+        }
 
-                let slice = &slice[..length];
+        // Steps on the microsecond clock, a ramp that aborts or fails is shown on the panel and
+        // kept for the host (Request::GetRampAbort)
+        match ramp.poll(&mut controller, clock.now().ticks()) {
+            Some(RampEvent::Done(_)) | None => (),
+            Some(event) => ramp_aborted = Some(event),
+        }
 
-                let _ = serial.write(slice);
-            }
+        let ready = Ready {
+            core: avr_ready.is_high(),
+            mem: bvr_ready.is_high(),
+        };
+        if let Some(state) = sequencer.poll(&mut controller, ready, clock.now().ticks()) {
+            defmt::info!("Sequence: {}", state);
         }
+        // Margin sweep dwells, the sweep logs each step itself
+        margin.poll(&mut controller, &sequencer, clock.now().ticks());
+        b_enable.set_state(if sequencer.mem_enable() {
+            PinState::High
+        } else {
+            PinState::Low
+        });
 
         // If no data to read, don't try read
         if !serial.read_ready().unwrap() {
@@ -365,54 +515,352 @@ fn main() -> ! {
 
         let mut buf = [0u8; 128];
         let count = match serial.read(&mut buf) {
-            Ok(count) if count > 0 => {
+            Ok(count) => {
                 defmt::debug!("USB: Read {} Bytes", count);
-                Some(count)
+                count
             }
             Err(UsbError::WouldBlock) => {
                 defmt::error!("USB: Read Buffer Full");
-                None
+                0
             }
-            Err(err) => {
+            Err(_) => {
                 defmt::error!("USB: Other Error");
-                None
+                0
             }
-            _ => None,
         };
 
-        if count.is_some() {
-            defmt::println!("USB_Data: {}", buf[0..count.unwrap()]);
-        }
-
-        // Valid Read
-        if count.is_some() {
-            // Set Channel
-            match buf[0] & 1u8 {
-                0 => {
-                    // Channel A
-                    controller.ch_a();
+        // Frames can span reads, the reader keeps the partial frame between them
+        for &byte in &buf[..count] {
+            if text_mode {
+                // Framed hosts send a delimiter ahead of every request, so it ends the text mode
+                if byte == 0 {
+                    defmt::info!("USB: Framed Mode");
+                    text_mode = false;
+                    lines.clear();
+                    continue;
                 }
-                1 => {
-                    // Channel B
-                    controller.ch_b();
+                let mut reply = scpi::Reply::default();
+                match lines.push(byte) {
+                    None => continue,
+                    Some(Ok(line)) => {
+                        defmt::info!("SCPI: {=str}", line);
+                        scpi.execute(
+                            line,
+                            &mut controller,
+                            &mut ramp,
+                            &mut sequencer,
+                            &mut dev,
+                            &mut reply,
+                        );
+                    }
+                    Some(Err(err)) => scpi.push_error(err),
                 }
-                _ => {}
+                write_all(&mut serial, reply.as_bytes());
+                continue;
             }
-            // Send Command to I2C Device
-            match buf[0] & 0x02 {
-                0x0 => {
-                    // Write
-                    controller.command(&buf[1..count.unwrap()]);
+            if byte == scpi::HANDSHAKE && frames.at_boundary() {
+                defmt::info!("USB: SCPI Mode");
+                text_mode = true;
+                streamer.stop();
+                continue;
+            }
+
+            let (seq, response) = match frames.push(byte) {
+                None => continue,
+                Some(Ok(protocol::Frame {
+                    seq,
+                    message: protocol::Message::Request(request),
+                })) => {
+                    defmt::info!("USB: Request {}: {}", seq, request);
+                    let response = streamer
+                        .handle(&request, clock.now().ticks())
+                        .or_else(|| sequencer.handle(&request, &mut controller))
+                        .or_else(|| margin.handle(&request, &mut controller, &sequencer))
+                        .or_else(|| ramp.handle(&request, &mut controller))
+                        .or_else(|| profiles.handle(&request, &mut controller, &mut ramp, &mut dev))
+                        .unwrap_or_else(|| handle_request(&mut controller, &mut delay, &request));
+                    (seq, response)
                 }
-                0x2 => {
-                    // Read
+                Some(Ok(protocol::Frame { seq, .. })) => {
+                    (seq, Response::Error(RequestError::Malformed))
                 }
-                _ => {}
+                Some(Err(FrameError::Version { version, seq })) => (
+                    seq,
+                    Response::Error(RequestError::UnsupportedVersion(version)),
+                ),
+                // Without an intact frame there is no sequence number to answer, the host
+                // times out and resends
+                Some(Err(err)) => {
+                    defmt::error!("USB: Bad Frame: {}", err);
+                    continue;
+                }
+            };
+            if let Response::Error(err) = response {
+                defmt::error!("USB: Request {} Failed: {}", seq, err);
             }
+            send_frame(&mut serial, seq, &protocol::Message::Response(response));
         }
     }
 }
 
+// Saves the setpoints, profiles and preferences once they changed and settled, skipped while a
+// channel can't be read
+fn save_settings(
+    settings: &mut SettingsStore,
+    flash: &mut pac::FLASH,
+    dev: &mut navigation::Device,
+    profiles: &Profiles,
+    preferences: Preferences,
+    now: u32,
+) {
+    let Some(current) = Settings::capture(dev, profiles, preferences) else {
+        return;
+    };
+    match settings.poll(&mut flash.unlocked(), &current, now) {
+        Ok(true) => defmt::info!("Settings Saved"),
+        Ok(false) => (),
+        Err(err) => defmt::error!("Settings Save Failed: {}", err),
+    }
+}
+
+// Frames and queues a message for the host, returning false if it was not (fully) sent. The
+// host resynchronises on the next delimiter after a partly sent frame
+fn send_frame<B: usb_device::bus::UsbBus, RS, WS>(
+    serial: &mut usbd_serial::SerialPort<B, RS, WS>,
+    seq: u16,
+    message: &protocol::Message,
+) -> bool
+where
+    RS: core::borrow::BorrowMut<[u8]>,
+    WS: core::borrow::BorrowMut<[u8]>,
+{
+    let mut frame = [0u8; protocol::MAX_FRAME];
+    let length = match protocol::encode_frame(seq, message, &mut frame) {
+        Ok(length) => length,
+        Err(err) => {
+            defmt::error!("USB: Frame Encode Failed: {}", err);
+            return false;
+        }
+    };
+    write_all(serial, &frame[..length])
+}
+
+// Queues bytes for the host, returning false if the buffer filled up before all were queued
+fn write_all<B: usb_device::bus::UsbBus, RS, WS>(
+    serial: &mut usbd_serial::SerialPort<B, RS, WS>,
+    mut data: &[u8],
+) -> bool
+where
+    RS: core::borrow::BorrowMut<[u8]>,
+    WS: core::borrow::BorrowMut<[u8]>,
+{
+    while !data.is_empty() {
+        match serial.write(data) {
+            Ok(count) => data = &data[count..],
+            Err(_) => {
+                defmt::error!("USB: Write Dropped, {} Bytes Unsent", data.len());
+                return false;
+            }
+        }
+    }
+    true
+}
+
+// Draws the static row and column labels around the 2x3 grid, with the output state in the
+// corner above the row labels
+fn display_labels<I: embedded_hal::i2c::I2c, D: ssd1306::size::DisplaySize>(
+    display: &mut Ssd1306<I2CInterface<I>, D, ssd1306::mode::BufferedGraphicsMode<D>>,
+    text_style: MonoTextStyle<BinaryColor>,
+    output: &str,
+) {
+    Text::with_baseline(output, Point::zero(), text_style, Baseline::Top)
+        .draw(display)
+        .unwrap();
+    Text::with_baseline("   Vcore Vmem", Point::zero(), text_style, Baseline::Top)
+        .draw(display)
+        .unwrap();
+    Text::with_baseline("V:", Point::new(0, 16), text_style, Baseline::Top)
+        .draw(display)
+        .unwrap();
+    Text::with_baseline("A:", Point::new(0, 32), text_style, Baseline::Top)
+        .draw(display)
+        .unwrap();
+    Text::with_baseline("T:", Point::new(0, 48), text_style, Baseline::Top)
+        .draw(display)
+        .unwrap();
+}
+
+// Draws one line of text per display row, used for full screen prompts
+fn display_prompt<I: embedded_hal::i2c::I2c, D: ssd1306::size::DisplaySize>(
+    display: &mut Ssd1306<I2CInterface<I>, D, ssd1306::mode::BufferedGraphicsMode<D>>,
+    text_style: MonoTextStyle<BinaryColor>,
+    lines: &[&str],
+) {
+    for (row, line) in lines.iter().enumerate() {
+        Text::with_baseline(
+            line,
+            Point::new(0, 16 * row as i32),
+            text_style,
+            Baseline::Top,
+        )
+        .draw(display)
+        .unwrap();
+    }
+}
+
+// Shows which rail stopped ramping and the fault that stopped it
+fn display_ramp_abort<I: embedded_hal::i2c::I2c, D: ssd1306::size::DisplaySize>(
+    display: &mut Ssd1306<I2CInterface<I>, D, ssd1306::mode::BufferedGraphicsMode<D>>,
+    text_style: MonoTextStyle<BinaryColor>,
+    event: &RampEvent,
+) {
+    let rail = match event.page() {
+        pmbus::Page::ChannelA => "Vcore ramp",
+        _ => "Vmem ramp",
+    };
+    display_prompt(
+        display,
+        text_style,
+        &[rail, "stopped:", event.as_str(), "Any: Back"],
+    );
+}
+
+// Shows which rail refused a write and why, with the refused value unless the register itself
+// is protected
+fn display_rejection<I: embedded_hal::i2c::I2c, D: ssd1306::size::DisplaySize>(
+    display: &mut Ssd1306<I2CInterface<I>, D, ssd1306::mode::BufferedGraphicsMode<D>>,
+    text_style: MonoTextStyle<BinaryColor>,
+    fill: PrimitiveStyle<BinaryColor>,
+    rejection: &Rejection,
+) {
+    let rail = match rejection.page {
+        pmbus::Page::ChannelA => "Vcore refused",
+        _ => "Vmem refused",
+    };
+    display_prompt(
+        display,
+        text_style,
+        &[rail, rejection.violation.as_str(), "", "Any: Back"],
+    );
+    if rejection.violation != Violation::Protected {
+        display_data(
+            display,
+            text_style,
+            fill,
+            Point::new(0, 32),
+            rejection.value,
+        );
+    }
+}
+
+// Lists the profile slots one per display row, the selected one inverted
+fn display_profiles<I: embedded_hal::i2c::I2c, D: ssd1306::size::DisplaySize>(
+    display: &mut Ssd1306<I2CInterface<I>, D, ssd1306::mode::BufferedGraphicsMode<D>>,
+    (text_style, text_style_inv): (MonoTextStyle<BinaryColor>, MonoTextStyle<BinaryColor>),
+    (fill, fill_inv): (PrimitiveStyle<BinaryColor>, PrimitiveStyle<BinaryColor>),
+    profiles: &Profiles,
+    selected: usize,
+) {
+    for (slot, name) in profiles.names().iter().enumerate() {
+        let point = Point::new(0, 16 * slot as i32);
+        let (text_style, fill) = if slot == selected {
+            (text_style_inv, fill_inv)
+        } else {
+            (text_style, fill)
+        };
+        Rectangle::new(point, Size::new(128, 16))
+            .into_styled(fill)
+            .draw(display)
+            .unwrap();
+
+        let number = [b'1' + slot as u8];
+        Text::with_baseline(
+            core::str::from_utf8(&number).unwrap(),
+            point,
+            text_style,
+            Baseline::Top,
+        )
+        .draw(display)
+        .unwrap();
+        let name = name.as_ref().map_or("-", |name| name.as_str());
+        Text::with_baseline(name, point + Point::new(18, 0), text_style, Baseline::Top)
+            .draw(display)
+            .unwrap();
+    }
+}
+
+// Displays one column of the 2x3 grid, or "ERR" in place of each value if the channel failed
+//
+// The column header is drawn inverted while the controller reports a fault on the channel, and
+// shows how far along a voltage ramp is while there is one
+fn display_channel<I: embedded_hal::i2c::I2c, D: ssd1306::size::DisplaySize>(
+    display: &mut Ssd1306<I2CInterface<I>, D, ssd1306::mode::BufferedGraphicsMode<D>>,
+    (text_style, text_style_inv): (MonoTextStyle<BinaryColor>, MonoTextStyle<BinaryColor>),
+    (fill, fill_inv): (PrimitiveStyle<BinaryColor>, PrimitiveStyle<BinaryColor>),
+    x: i32,
+    name: &str,
+    chan: &navigation::Channel,
+    ramp: Option<f32>,
+) {
+    let header = Point::new(navigation::translate_point((x, 0)).x, 0);
+    let mut percent = [b' '; 4];
+    let name = match ramp {
+        Some(progress) => format_percent(progress, &mut percent),
+        None => name,
+    };
+    if chan.get_status().is_faulted() {
+        display_text(display, text_style_inv, fill_inv, header, name);
+    } else {
+        display_text(display, text_style, fill, header, name);
+    }
+
+    let values = [
+        chan.get_voltage(),
+        chan.get_current(),
+        chan.get_temperature(),
+    ];
+    for (y, val) in values.into_iter().enumerate() {
+        let point = navigation::translate_point((x, y as i32));
+        if chan.get_error() {
+            display_text(display, text_style, fill, point, "ERR");
+        } else {
+            display_data(display, text_style, fill, point, val);
+        }
+    }
+}
+
+// Writes a fraction from 0 to 1 as a right aligned percentage, eg " 45%"
+fn format_percent(fraction: f32, buf: &mut [u8; 4]) -> &str {
+    let mut val = (fraction.clamp(0., 1.) * 100.) as u32;
+    *buf = [b' ', b' ', b'0', b'%'];
+    for slot in buf[..3].iter_mut().rev() {
+        if val == 0 {
+            break;
+        }
+        *slot = b'0' + (val % 10) as u8;
+        val /= 10;
+    }
+    core::str::from_utf8(buf).unwrap()
+}
+
+// Displays a string in one cell of the 2x3 grid of values on the main display
+fn display_text<I: embedded_hal::i2c::I2c, D: ssd1306::size::DisplaySize>(
+    display: &mut Ssd1306<I2CInterface<I>, D, ssd1306::mode::BufferedGraphicsMode<D>>,
+    text_style: MonoTextStyle<BinaryColor>,
+    fill: PrimitiveStyle<BinaryColor>,
+    point: Point,
+    text: &str,
+) {
+    Rectangle::new(point, Size::new(9 * 5, 16))
+        .into_styled(fill)
+        .draw(display)
+        .unwrap();
+
+    Text::with_baseline(text, point, text_style, Baseline::Top)
+        .draw(display)
+        .unwrap();
+}
+
 // Displays a floating point value in the 2x3 grid of values on the main display
 fn display_data<I: embedded_hal::i2c::I2c, D: ssd1306::size::DisplaySize>(
     display: &mut Ssd1306<I2CInterface<I>, D, ssd1306::mode::BufferedGraphicsMode<D>>,
@@ -455,31 +903,3 @@ fn clear_display<I: embedded_hal::i2c::I2c, D: ssd1306::size::DisplaySize>(
         .draw(display)
         .unwrap();
 }
-
-fn update_vrm_read<I: embedded_hal::i2c::I2c>(
-    dev: &mut navigation::Device,
-    controller: &mut TPSC536C7<I>,
-) {
-    // Get Values for Display
-    // Voltage
-    dev.core().set_voltage(controller.ch_a().read_vout());
-    dev.mem().set_voltage(controller.ch_b().read_vout());
-    // Temperature
-    dev.core()
-        .set_temperature(controller.ch_a().read_temperature_1());
-    dev.mem()
-        .set_temperature(controller.ch_b().read_temperature_1());
-    // Current
-    dev.core().set_current(controller.ch_a().read_iout());
-    dev.mem().set_current(controller.ch_b().read_iout());
-    // Voltage Setpoint
-    dev.core()
-        .set_voltage_setpoint(controller.ch_a().vout_command().read());
-    dev.mem()
-        .set_voltage_setpoint(controller.ch_b().vout_command().read());
-    // Current Limit
-    dev.core()
-        .set_current_limit(controller.ch_a().iout_oc_fault_limit().read());
-    dev.mem()
-        .set_current_limit(controller.ch_b().iout_oc_fault_limit().read());
-}
@@ -0,0 +1,47 @@
+use serialport::{SerialPortInfo, SerialPortType};
+
+use crate::Error;
+
+/// USB vendor and product id the firmware enumerates with
+pub const VID: u16 = 0x16c0;
+pub const PID: u16 = 0x27dd;
+/// Product string the firmware sets, the VID/PID pair is a shared one so this tells boards apart
+pub const PRODUCT: &str = "gpu-external-power-supply";
+
+/// True if the port belongs to a power supply
+pub fn is_power_supply(info: &SerialPortInfo) -> bool {
+    match &info.port_type {
+        SerialPortType::UsbPort(usb) => {
+            usb.vid == VID && usb.pid == PID && usb.product.as_deref() == Some(PRODUCT)
+        }
+        _ => false,
+    }
+}
+
+/// Every connected power supply, as (port path, serial number)
+pub fn power_supplies() -> Result<Vec<(String, Option<String>)>, Error> {
+    let ports = serialport::available_ports()?;
+    Ok(ports
+        .into_iter()
+        .filter(is_power_supply)
+        .map(|info| {
+            let serial = match info.port_type {
+                SerialPortType::UsbPort(usb) => usb.serial_number,
+                _ => None,
+            };
+            (info.port_name, serial)
+        })
+        .collect())
+}
+
+/// Path of the only connected power supply, an error if there is none or more than one
+pub fn find_port() -> Result<String, Error> {
+    let mut found = power_supplies()?;
+    match found.len() {
+        0 => Err(Error::NotFound),
+        1 => Ok(found.remove(0).0),
+        _ => Err(Error::Ambiguous(
+            found.into_iter().map(|(port, _)| port).collect(),
+        )),
+    }
+}
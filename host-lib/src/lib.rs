@@ -0,0 +1,18 @@
+// Host side of the USB protocol: finding the power supply, a link that sends requests and
+// collects replies and telemetry, and a typed handle on top of it. The message and telemetry
+// types come from firmware-core, so the host and the firmware can never disagree on the layout.
+
+pub mod discover;
+pub mod link;
+pub mod mock;
+pub mod power_supply;
+
+pub use firmware_core::margin::{Level, MarginStatus, SweepConfig, SweepStep};
+pub use firmware_core::navigation::Device;
+pub use firmware_core::profile::{Name, Profile, RailProfile, PROFILES};
+pub use firmware_core::protocol::{ChannelSample, Format, Register, Sample, StreamConfig};
+pub use firmware_core::ramp::RampEvent;
+pub use firmware_core::sequence::State;
+pub use firmware_core::vrm_status::StatusWord;
+pub use link::{Error, Link};
+pub use power_supply::{Channel, PowerSupply, SampleStream, TelemetryStream};
@@ -0,0 +1,215 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::time::{Duration, Instant};
+
+use firmware_core::navigation::Device;
+use firmware_core::protocol::{
+    encode_frame, Frame, FrameReader, Message, Request, RequestError, Response, Sample, MAX_FRAME,
+};
+
+/// How long a request waits for its reply unless changed with Link::set_timeout
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Errors talking to the power supply
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    /// No power supply is connected
+    NotFound,
+    /// More than one power supply is connected, the ports found
+    Ambiguous(Vec<String>),
+    /// No reply (or telemetry) arrived in time
+    Timeout,
+    /// The firmware refused or failed the request
+    Device(RequestError),
+    /// The firmware answered with a reply that does not fit the request
+    UnexpectedResponse(Response),
+    /// The request does not fit in a frame
+    TooLarge,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "{err}"),
+            Error::NotFound => write!(f, "no power supply found"),
+            Error::Ambiguous(ports) => write!(
+                f,
+                "more than one power supply found, pick one with --port: {}",
+                ports.join(", ")
+            ),
+            Error::Timeout => write!(f, "the power supply did not answer"),
+            Error::Device(err) => write!(f, "the power supply reported {err:?}"),
+            Error::UnexpectedResponse(resp) => write!(f, "unexpected reply {resp:?}"),
+            Error::TooLarge => write!(f, "request too large for a frame"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::Io(err)
+    }
+}
+
+impl From<serialport::Error> for Error {
+    fn from(err: serialport::Error) -> Error {
+        Error::Io(err.into())
+    }
+}
+
+/// A message received from the firmware, owned so it outlives the frame buffer
+enum Received {
+    Response(u16, Response),
+    Telemetry(Device),
+    Sample(u16, Sample),
+}
+
+/// Request / reply link to the firmware over any byte stream (serial port, pty, socket)
+///
+/// The stream's reads should time out (or return WouldBlock) now and then, the link uses those
+/// gaps to notice that its own deadline passed
+pub struct Link<T> {
+    port: T,
+    reader: FrameReader,
+    received: VecDeque<Received>,
+    seq: u16,
+    timeout: Duration,
+    synced: bool,
+}
+
+impl<T: Read + Write> Link<T> {
+    pub fn new(port: T) -> Link<T> {
+        Link {
+            port,
+            reader: FrameReader::default(),
+            received: VecDeque::new(),
+            seq: 0,
+            timeout: DEFAULT_TIMEOUT,
+            synced: false,
+        }
+    }
+
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// The underlying transport
+    pub fn transport(&mut self) -> &mut T {
+        &mut self.port
+    }
+
+    pub fn release(self) -> T {
+        self.port
+    }
+
+    /// Sends a request and waits for its reply, replies reporting an error become Error::Device
+    pub fn request(&mut self, request: &Request) -> Result<Response, Error> {
+        self.seq = self.seq.wrapping_add(1);
+        let seq = self.seq;
+        self.send(seq, &Message::Request(*request))?;
+
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            match self.receive(deadline)? {
+                // Replies to earlier requests that timed out are stale
+                Received::Response(reply, _) if reply != seq => continue,
+                Received::Response(_, Response::Error(err)) => return Err(Error::Device(err)),
+                Received::Response(_, response) => return Ok(response),
+                // Dropped while waiting, a stream stopped with a request would otherwise leave
+                // its last samples queued
+                Received::Telemetry(_) | Received::Sample(..) => continue,
+            }
+        }
+    }
+
+    /// Waits for the next telemetry frame the firmware sends
+    pub fn telemetry(&mut self) -> Result<Device, Error> {
+        if !self.synced {
+            // Telemetry only needs the port open, but the delimiter also clears the firmware's
+            // reader of anything a previous session left half written
+            self.port.write_all(&[0])?;
+            self.synced = true;
+        }
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            if let Received::Telemetry(dev) = self.receive(deadline)? {
+                return Ok(dev);
+            }
+        }
+    }
+
+    /// Waits for the next sample of a stream started with Request::StartStream, returned with
+    /// its sequence number (which counts samples, so gaps show lost frames)
+    pub fn sample(&mut self) -> Result<(u16, Sample), Error> {
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            if let Received::Sample(seq, sample) = self.receive(deadline)? {
+                return Ok((seq, sample));
+            }
+        }
+    }
+
+    fn send(&mut self, seq: u16, message: &Message) -> Result<(), Error> {
+        let mut frame = [0u8; MAX_FRAME + 1];
+        // A leading delimiter ends whatever partial frame the firmware might be holding
+        let start = if self.synced { 1 } else { 0 };
+        let length = encode_frame(seq, message, &mut frame[1..]).map_err(|_| Error::TooLarge)?;
+        self.port.write_all(&frame[start..length + 1])?;
+        self.port.flush()?;
+        self.synced = true;
+        Ok(())
+    }
+
+    /// Next message from the firmware, reading more from the port as needed
+    fn receive(&mut self, deadline: Instant) -> Result<Received, Error> {
+        loop {
+            if let Some(received) = self.received.pop_front() {
+                return Ok(received);
+            }
+            if Instant::now() >= deadline {
+                return Err(Error::Timeout);
+            }
+
+            let mut buf = [0u8; 256];
+            let count = match self.port.read(&mut buf) {
+                Ok(0) => return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+                Ok(count) => count,
+                Err(err)
+                    if matches!(
+                        err.kind(),
+                        io::ErrorKind::TimedOut
+                            | io::ErrorKind::WouldBlock
+                            | io::ErrorKind::Interrupted
+                    ) =>
+                {
+                    continue
+                }
+                Err(err) => return Err(err.into()),
+            };
+
+            for &byte in &buf[..count] {
+                // Broken frames are dropped, a lost reply shows up as a timeout
+                let received = match self.reader.push(byte) {
+                    Some(Ok(Frame {
+                        seq,
+                        message: Message::Response(response),
+                    })) => Received::Response(seq, response),
+                    Some(Ok(Frame {
+                        message: Message::Telemetry(dev),
+                        ..
+                    })) => Received::Telemetry(dev),
+                    Some(Ok(Frame {
+                        seq,
+                        message: Message::Sample(sample),
+                    })) => Received::Sample(seq, sample),
+                    _ => continue,
+                };
+                self.received.push_back(received);
+            }
+        }
+    }
+}
@@ -0,0 +1,102 @@
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+
+use firmware_core::navigation::Device;
+use firmware_core::protocol::{
+    encode_frame, Frame, FrameError, FrameReader, Message, Request, RequestError, Response,
+    MAX_FRAME,
+};
+
+/// In memory stand-in for the firmware's end of the serial port
+///
+/// Requests the host writes are answered by handler, and while telemetry is set every read that
+/// finds nothing else queued gets a telemetry frame, like the firmware streaming it. With
+/// nothing to send, reads time out the way a serial port does
+pub struct MockTransport<H> {
+    handler: H,
+    reader: FrameReader,
+    outgoing: VecDeque<u8>,
+    telemetry: Option<Device>,
+    telemetry_seq: u16,
+}
+
+impl<H: FnMut(&Request) -> Response> MockTransport<H> {
+    pub fn new(handler: H) -> MockTransport<H> {
+        MockTransport {
+            handler,
+            reader: FrameReader::default(),
+            outgoing: VecDeque::new(),
+            telemetry: None,
+            telemetry_seq: 0,
+        }
+    }
+
+    /// Device state to stream, None stops the stream
+    pub fn set_telemetry(&mut self, dev: Option<Device>) {
+        self.telemetry = dev;
+    }
+
+    /// Queues raw bytes for the host, eg a corrupted or partial frame
+    pub fn inject(&mut self, bytes: &[u8]) {
+        self.outgoing.extend(bytes);
+    }
+
+    /// Queues a framed message for the host, eg stream samples
+    pub fn send(&mut self, seq: u16, message: &Message) {
+        queue(&mut self.outgoing, seq, message);
+    }
+}
+
+impl<H: FnMut(&Request) -> Response> Write for MockTransport<H> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for &byte in buf {
+            // Answered the way the firmware answers them
+            let (seq, response) = match self.reader.push(byte) {
+                None => continue,
+                Some(Ok(Frame {
+                    seq,
+                    message: Message::Request(request),
+                })) => (seq, (self.handler)(&request)),
+                Some(Ok(Frame { seq, .. })) => (seq, Response::Error(RequestError::Malformed)),
+                Some(Err(FrameError::Version { version, seq })) => (
+                    seq,
+                    Response::Error(RequestError::UnsupportedVersion(version)),
+                ),
+                Some(Err(_)) => continue,
+            };
+            queue(&mut self.outgoing, seq, &Message::Response(response));
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<H> Read for MockTransport<H> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.outgoing.is_empty() {
+            let Some(dev) = &self.telemetry else {
+                return Err(io::ErrorKind::TimedOut.into());
+            };
+            queue(
+                &mut self.outgoing,
+                self.telemetry_seq,
+                &Message::Telemetry(dev.clone()),
+            );
+            self.telemetry_seq = self.telemetry_seq.wrapping_add(1);
+        }
+        let count = buf.len().min(self.outgoing.len());
+        for (slot, byte) in buf.iter_mut().zip(self.outgoing.drain(..count)) {
+            *slot = byte;
+        }
+        Ok(count)
+    }
+}
+
+fn queue(outgoing: &mut VecDeque<u8>, seq: u16, message: &Message) {
+    let mut frame = [0u8; MAX_FRAME];
+    let length = encode_frame(seq, message, &mut frame).expect("message fits in a frame");
+    outgoing.extend(&frame[..length]);
+}
@@ -0,0 +1,332 @@
+use std::io::{Read, Write};
+use std::time::Duration;
+
+use pmbus_types_rs::slinear11;
+use serialport::SerialPort;
+
+use firmware_core::margin::{Level, MarginStatus, SweepConfig, SweepStep};
+use firmware_core::navigation::Device;
+use firmware_core::pmbus::{Command, VoutMode};
+use firmware_core::profile::{Name, Profile};
+use firmware_core::protocol::{
+    Format, Register, Request, RequestError, Response, Sample, StreamConfig,
+};
+use firmware_core::ramp::RampEvent;
+use firmware_core::sequence::State;
+use firmware_core::vrm_status::StatusWord;
+
+use crate::{discover, Error, Link};
+
+/// Read timeout of an opened serial port, only bounds how often the link checks its own deadline
+const PORT_POLL: Duration = Duration::from_millis(50);
+
+/// The two outputs of the supply
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Channel {
+    /// GPU core, PAGE 0 (channel A) of the controller
+    Core,
+    /// GPU memory, PAGE 1 (channel B) of the controller
+    Mem,
+}
+
+impl Channel {
+    pub fn page(self) -> u8 {
+        match self {
+            Channel::Core => 0,
+            Channel::Mem => 1,
+        }
+    }
+}
+
+/// Typed handle on a power supply, over a serial port or any other Read + Write transport
+pub struct PowerSupply<T> {
+    link: Link<T>,
+}
+
+impl PowerSupply<Box<dyn SerialPort>> {
+    /// Opens the power supply on the given serial port
+    pub fn open(path: &str) -> Result<Self, Error> {
+        // CDC ignores the baud rate
+        let port = serialport::new(path, 115_200).timeout(PORT_POLL).open()?;
+        // Telemetry queued while nothing was listening is stale
+        port.clear(serialport::ClearBuffer::Input)?;
+        Ok(PowerSupply::new(port))
+    }
+
+    /// Opens the only connected power supply
+    pub fn discover() -> Result<Self, Error> {
+        PowerSupply::open(&discover::find_port()?)
+    }
+}
+
+impl<T: Read + Write> PowerSupply<T> {
+    pub fn new(transport: T) -> PowerSupply<T> {
+        PowerSupply {
+            link: Link::new(transport),
+        }
+    }
+
+    /// How long each request waits for its reply
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.link.set_timeout(timeout);
+    }
+
+    /// The underlying link, for requests this handle has no method for
+    pub fn link(&mut self) -> &mut Link<T> {
+        &mut self.link
+    }
+
+    /// The underlying transport
+    pub fn transport(&mut self) -> &mut T {
+        self.link.transport()
+    }
+
+    pub fn release(self) -> T {
+        self.link.release()
+    }
+
+    /// Checks the firmware answers
+    pub fn ping(&mut self) -> Result<(), Error> {
+        match self.link.request(&Request::Ping)? {
+            Response::Pong => Ok(()),
+            other => Err(Error::UnexpectedResponse(other)),
+        }
+    }
+
+    /// Waits for the next telemetry frame
+    pub fn telemetry(&mut self) -> Result<Device, Error> {
+        self.link.telemetry()
+    }
+
+    /// Every telemetry frame as it arrives, ends after the first error
+    pub fn telemetry_stream(&mut self) -> TelemetryStream<'_, T> {
+        TelemetryStream {
+            link: &mut self.link,
+            done: false,
+        }
+    }
+
+    /// Starts streaming samples, replacing any stream already running
+    pub fn start_stream(&mut self, config: StreamConfig) -> Result<(), Error> {
+        self.expect_ack(&Request::StartStream(config))
+    }
+
+    pub fn stop_stream(&mut self) -> Result<(), Error> {
+        self.expect_ack(&Request::StopStream)
+    }
+
+    /// Samples of the running stream as they arrive, with their sequence numbers. Ends after the
+    /// first error
+    pub fn samples(&mut self) -> SampleStream<'_, T> {
+        SampleStream {
+            link: &mut self.link,
+            done: false,
+        }
+    }
+
+    /// Sets VOUT_COMMAND, encoded in the channel's VOUT_MODE
+    pub fn set_voltage(&mut self, channel: Channel, volts: f32) -> Result<(), Error> {
+        let mode = self.read_register(channel, Command::VoutMode.to_address(), 1, Format::Raw)?;
+        let raw = VoutMode::from_bits(mode.data()[0])
+            .and_then(|mode| mode.from_volts(volts))
+            .map_err(|err| Error::Device(RequestError::Vrm(err)))?;
+        self.write_word(channel, Command::VOUTCommand, raw)
+    }
+
+    /// Sets IOUT_OC_FAULT_LIMIT in amps
+    pub fn set_current_limit(&mut self, channel: Channel, amps: f32) -> Result<(), Error> {
+        self.write_word(channel, Command::IoutOCFaultLimit, slinear11::from(amps))
+    }
+
+    /// Sets the rate voltage setpoint changes of the channel are ramped at, in mV/ms
+    pub fn set_ramp_rate(&mut self, channel: Channel, mv_per_ms: f32) -> Result<(), Error> {
+        self.expect_ack(&Request::SetRampRate {
+            page: channel.page(),
+            rate: mv_per_ms,
+        })
+    }
+
+    /// Brings both outputs up (memory first) or down (core first)
+    pub fn set_output(&mut self, on: bool) -> Result<(), Error> {
+        self.expect_ack(&Request::SetOutput(on))
+    }
+
+    /// Where the power sequence is, State::On once both outputs are up
+    pub fn output(&mut self) -> Result<State, Error> {
+        match self.link.request(&Request::GetOutput)? {
+            Response::Output(state) => Ok(state),
+            other => Err(Error::UnexpectedResponse(other)),
+        }
+    }
+
+    /// Moves the output to its high or low margin or back to nominal, the outputs have to be on
+    pub fn set_margin(&mut self, channel: Channel, level: Level) -> Result<(), Error> {
+        self.expect_ack(&Request::SetMargin {
+            page: channel.page(),
+            level,
+        })
+    }
+
+    /// Sets how far the output is margined, in percent of its setpoint
+    pub fn set_margin_percent(&mut self, channel: Channel, percent: f32) -> Result<(), Error> {
+        self.expect_ack(&Request::SetMarginPercent {
+            page: channel.page(),
+            percent,
+        })
+    }
+
+    /// Starts stepping the outputs in config through their margins, logging telemetry at the end
+    /// of each dwell
+    pub fn start_margin_sweep(&mut self, config: SweepConfig) -> Result<(), Error> {
+        self.expect_ack(&Request::StartMarginSweep(config))
+    }
+
+    /// Ends a running sweep, the swept outputs go back to nominal
+    pub fn stop_margin_sweep(&mut self) -> Result<(), Error> {
+        self.expect_ack(&Request::StopMarginSweep)
+    }
+
+    /// The last ramp that aborted or failed, cleared once read
+    pub fn ramp_abort(&mut self) -> Result<Option<RampEvent>, Error> {
+        match self.link.request(&Request::GetRampAbort)? {
+            Response::RampAbort(event) => Ok(event),
+            other => Err(Error::UnexpectedResponse(other)),
+        }
+    }
+
+    /// Margin settings of both outputs and how far a sweep is
+    pub fn margin(&mut self) -> Result<MarginStatus, Error> {
+        match self.link.request(&Request::GetMargin)? {
+            Response::Margin(status) => Ok(status),
+            other => Err(Error::UnexpectedResponse(other)),
+        }
+    }
+
+    /// Telemetry logged for a step of the last sweep
+    pub fn sweep_step(&mut self, step: u8) -> Result<SweepStep, Error> {
+        match self.link.request(&Request::GetSweepStep(step))? {
+            Response::SweepStep(logged) => Ok(logged),
+            other => Err(Error::UnexpectedResponse(other)),
+        }
+    }
+
+    /// Names of the stored profiles with their slots, empty slots left out
+    pub fn profiles(&mut self) -> Result<Vec<(u8, Name)>, Error> {
+        match self.link.request(&Request::ListProfiles)? {
+            Response::Profiles(names) => Ok((0..)
+                .zip(names)
+                .filter_map(|(slot, name)| Some((slot, name?)))
+                .collect()),
+            other => Err(Error::UnexpectedResponse(other)),
+        }
+    }
+
+    /// The profile stored in slot
+    pub fn profile(&mut self, slot: u8) -> Result<Profile, Error> {
+        match self.link.request(&Request::GetProfile(slot))? {
+            Response::Profile(profile) => Ok(profile),
+            other => Err(Error::UnexpectedResponse(other)),
+        }
+    }
+
+    /// Writes the profile stored in slot to the controller
+    pub fn load_profile(&mut self, slot: u8) -> Result<(), Error> {
+        self.expect_ack(&Request::LoadProfile(slot))
+    }
+
+    /// Stores the current setpoints and limits in slot under name, replacing its profile
+    pub fn save_profile(&mut self, slot: u8, name: Name) -> Result<(), Error> {
+        self.expect_ack(&Request::SaveProfile { slot, name })
+    }
+
+    pub fn delete_profile(&mut self, slot: u8) -> Result<(), Error> {
+        self.expect_ack(&Request::DeleteProfile(slot))
+    }
+
+    /// Reads STATUS_WORD straight from the controller
+    pub fn read_status(&mut self, channel: Channel) -> Result<StatusWord, Error> {
+        let reg = self.read_register(channel, Command::StatusWord.to_address(), 2, Format::Raw)?;
+        let [lo, hi] = [reg.data()[0], reg.data()[1]];
+        Ok(StatusWord::from_bits(u16::from_le_bytes([lo, hi])))
+    }
+
+    /// Reads len bytes of a register, decoded in format
+    pub fn read_register(
+        &mut self,
+        channel: Channel,
+        cmd: u8,
+        len: u8,
+        format: Format,
+    ) -> Result<Register, Error> {
+        let request = Request::Read {
+            page: channel.page(),
+            cmd,
+            len,
+            format,
+        };
+        match self.link.request(&request)? {
+            Response::Register(reg) if reg.data().len() == len as usize => Ok(reg),
+            other => Err(Error::UnexpectedResponse(other)),
+        }
+    }
+
+    /// Writes raw data bytes to a register
+    pub fn write_register(&mut self, channel: Channel, cmd: u8, data: &[u8]) -> Result<(), Error> {
+        let mut msg = Vec::with_capacity(data.len() + 1);
+        msg.push(cmd);
+        msg.extend_from_slice(data);
+        self.expect_ack(&Request::Write {
+            page: channel.page(),
+            data: &msg,
+        })
+    }
+
+    fn expect_ack(&mut self, request: &Request) -> Result<(), Error> {
+        match self.link.request(request)? {
+            Response::Ack => Ok(()),
+            other => Err(Error::UnexpectedResponse(other)),
+        }
+    }
+
+    fn write_word(&mut self, channel: Channel, cmd: Command, val: u16) -> Result<(), Error> {
+        self.write_register(channel, cmd.to_address(), &val.to_le_bytes())
+    }
+}
+
+/// Iterator over the telemetry a power supply streams, see PowerSupply::telemetry_stream
+pub struct TelemetryStream<'a, T> {
+    link: &'a mut Link<T>,
+    done: bool,
+}
+
+impl<T: Read + Write> Iterator for TelemetryStream<'_, T> {
+    type Item = Result<Device, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let telemetry = self.link.telemetry();
+        self.done = telemetry.is_err();
+        Some(telemetry)
+    }
+}
+
+/// Iterator over the samples of a stream, see PowerSupply::samples
+pub struct SampleStream<'a, T> {
+    link: &'a mut Link<T>,
+    done: bool,
+}
+
+impl<T: Read + Write> Iterator for SampleStream<'_, T> {
+    type Item = Result<(u16, Sample), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let sample = self.link.sample();
+        self.done = sample.is_err();
+        Some(sample)
+    }
+}
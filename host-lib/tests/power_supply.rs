@@ -0,0 +1,307 @@
+#[path = "../../firmware-core/tests/common/mod.rs"]
+mod common;
+
+use std::time::Duration;
+
+use common::{assert_close, controller, is_on, NoDelay};
+use firmware_core::control::handle_request;
+use firmware_core::margin::Margin;
+use firmware_core::pmbus::{Page, VrmError};
+use firmware_core::profile::Profiles;
+use firmware_core::protocol::{Message, Request, RequestError, Response};
+use firmware_core::ramp::{Ramp, STEP_US};
+use firmware_core::safety::Violation;
+use firmware_core::sequence::{Ready, Sequencer};
+use firmware_core::stream::Streamer;
+use gpu_psu_host::mock::MockTransport;
+use gpu_psu_host::{
+    Channel, Device, Error, Format, Level, Name, PowerSupply, Sample, State, StatusWord,
+    StreamConfig, SweepConfig,
+};
+use tps536c7_simulator::{Fault, Tps536c7};
+
+/// A power supply whose requests are answered by the firmware's handlers driving the simulator
+fn simulated(sim: Tps536c7) -> PowerSupply<MockTransport<impl FnMut(&Request) -> Response>> {
+    let mut controller = controller(sim);
+    let mut profiles = Profiles::default();
+    let mut ramp = Ramp::default();
+    let mut dev = Device::default();
+    let mut sequencer = Sequencer::default();
+    let mut margin = Margin::default();
+    let mut now = 0;
+    PowerSupply::new(MockTransport::new(move |request: &Request| {
+        let response = sequencer
+            .handle(request, &mut controller)
+            .or_else(|| margin.handle(request, &mut controller, &sequencer))
+            .or_else(|| ramp.handle(request, &mut controller))
+            .or_else(|| profiles.handle(request, &mut controller, &mut ramp, &mut dev))
+            .unwrap_or_else(|| handle_request(&mut controller, &mut NoDelay, request));
+        // Ramps run to the end before the next request
+        while ramp.is_running() {
+            now += STEP_US;
+            ramp.poll(&mut controller, now);
+        }
+        // As does the power sequence, the simulator has no VR_READY so a rail is ready once on
+        for _ in 0..8 {
+            let ready = Ready {
+                core: is_on(&mut controller, Page::ChannelA),
+                mem: is_on(&mut controller, Page::ChannelB),
+            };
+            now += STEP_US;
+            sequencer.poll(&mut controller, ready, now);
+        }
+        // And margin sweeps
+        while margin.is_sweeping() {
+            now += STEP_US;
+            margin.poll(&mut controller, &sequencer, now);
+        }
+        response
+    }))
+}
+
+#[test]
+fn sets_the_voltage_in_the_channel_format() {
+    let mut psu = simulated(Tps536c7::default());
+
+    psu.set_voltage(Channel::Core, 1.05).unwrap();
+    psu.set_voltage(Channel::Mem, 1.25).unwrap();
+
+    let core = psu
+        .read_register(Channel::Core, 0x21, 2, Format::Vout)
+        .unwrap();
+    assert_close(core.value.unwrap(), 1.05);
+    let mem = psu
+        .read_register(Channel::Mem, 0x21, 2, Format::Vout)
+        .unwrap();
+    assert_close(mem.value.unwrap(), 1.25);
+}
+
+#[test]
+fn sets_the_current_limit() {
+    let mut psu = simulated(Tps536c7::default());
+
+    psu.set_current_limit(Channel::Mem, 40.).unwrap();
+
+    let limit = psu
+        .read_register(Channel::Mem, 0x46, 2, Format::Linear11)
+        .unwrap();
+    assert_close(limit.value.unwrap(), 40.);
+}
+
+#[test]
+fn reads_the_status_word() {
+    let mut sim = Tps536c7::default();
+    sim.inject_fault(tps536c7_simulator::Channel::B, Fault::OverCurrent);
+    let mut psu = simulated(sim);
+
+    assert!(!psu.read_status(Channel::Core).unwrap().is_faulted());
+    let mem = psu.read_status(Channel::Mem).unwrap();
+    assert!(mem.contains(StatusWord::IOUT_OC));
+}
+
+#[test]
+fn device_errors_are_reported() {
+    let mut psu = simulated(Tps536c7::default());
+
+    assert!(matches!(
+        psu.write_register(Channel::Core, 0x11, &[]),
+        Err(Error::Device(RequestError::Refused))
+    ));
+    // The link is still usable afterwards
+    psu.ping().unwrap();
+}
+
+#[test]
+fn setpoints_outside_the_envelope_are_refused() {
+    let mut psu = simulated(Tps536c7::default());
+
+    assert!(matches!(
+        psu.set_voltage(Channel::Core, 2.0),
+        Err(Error::Device(RequestError::Vrm(VrmError::OutOfEnvelope(
+            Violation::VoltageHigh
+        ))))
+    ));
+    // Raw writes included, VOUT_MAX = 3 V
+    assert!(matches!(
+        psu.write_register(Channel::Mem, 0x24, &[0x00, 0x06]),
+        Err(Error::Device(RequestError::Vrm(VrmError::OutOfEnvelope(_))))
+    ));
+}
+
+#[test]
+fn ramp_rates_out_of_range_are_refused() {
+    let mut psu = simulated(Tps536c7::default());
+
+    psu.set_ramp_rate(Channel::Core, 5.).unwrap();
+    assert!(matches!(
+        psu.set_ramp_rate(Channel::Mem, 0.),
+        Err(Error::Device(RequestError::InvalidRampRate))
+    ));
+}
+
+#[test]
+fn ramp_aborts_are_fetched_once() {
+    let mut sim = Tps536c7::default();
+    sim.inject_fault(tps536c7_simulator::Channel::B, Fault::OverCurrent);
+    let mut psu = simulated(sim);
+    assert_eq!(psu.ramp_abort().unwrap(), None);
+
+    psu.set_voltage(Channel::Mem, 1.3).unwrap();
+
+    let event = psu.ramp_abort().unwrap().expect("the ramp aborted");
+    assert_eq!(event.page(), Page::ChannelB);
+    assert_eq!(event.as_str(), "Overcurrent");
+    assert_eq!(psu.ramp_abort().unwrap(), None);
+}
+
+#[test]
+fn the_output_switches_in_sequence() {
+    let mut psu = simulated(Tps536c7::default());
+
+    assert_eq!(psu.output().unwrap(), State::Off);
+    psu.set_output(true).unwrap();
+    assert_eq!(psu.output().unwrap(), State::On);
+
+    psu.set_output(false).unwrap();
+    assert_eq!(psu.output().unwrap(), State::Off);
+    let vout = psu
+        .read_register(Channel::Mem, 0x8B, 2, Format::Vout)
+        .unwrap();
+    assert_close(vout.value.unwrap(), 0.);
+}
+
+#[test]
+fn margins_and_sweeps_need_the_output_on() {
+    let mut psu = simulated(Tps536c7::default());
+    let config = SweepConfig {
+        core: true,
+        mem: false,
+        dwell_ms: 10,
+    };
+
+    assert!(matches!(
+        psu.set_margin(Channel::Core, Level::High),
+        Err(Error::Device(RequestError::OutputOff))
+    ));
+    psu.set_output(true).unwrap();
+    psu.set_margin_percent(Channel::Core, 10.).unwrap();
+    psu.set_margin(Channel::Core, Level::High).unwrap();
+    let vout = psu
+        .read_register(Channel::Core, 0x8B, 2, Format::Vout)
+        .unwrap();
+    assert_close(vout.value.unwrap(), 0.99);
+
+    psu.start_margin_sweep(config).unwrap();
+    let status = psu.margin().unwrap();
+    assert_eq!((status.sweep, status.logged), (None, 3));
+    assert_eq!(status.level, [Level::Nominal; 2]);
+    let low = psu.sweep_step(0).unwrap();
+    assert_eq!(low.level, Level::Low);
+    assert_close(low.sample.vout.unwrap(), 0.81);
+    assert!(matches!(
+        psu.sweep_step(3),
+        Err(Error::Device(RequestError::InvalidMargin))
+    ));
+}
+
+#[test]
+fn profiles_are_saved_listed_and_loaded() {
+    let mut psu = simulated(Tps536c7::default());
+    psu.set_voltage(Channel::Core, 1.05).unwrap();
+    psu.save_profile(1, Name::new("daily OC").unwrap()).unwrap();
+    psu.set_voltage(Channel::Core, 0.9).unwrap();
+
+    let names = psu.profiles().unwrap();
+    assert_eq!(names.len(), 1);
+    assert_eq!((names[0].0, names[0].1.as_str()), (1, "daily OC"));
+    assert_close(psu.profile(1).unwrap().core.voltage, 1.05);
+
+    psu.load_profile(1).unwrap();
+    let core = psu
+        .read_register(Channel::Core, 0x21, 2, Format::Vout)
+        .unwrap();
+    assert_close(core.value.unwrap(), 1.05);
+
+    psu.delete_profile(1).unwrap();
+    assert!(psu.profiles().unwrap().is_empty());
+    assert!(matches!(
+        psu.load_profile(1),
+        Err(Error::Device(RequestError::NoProfile))
+    ));
+}
+
+#[test]
+fn telemetry_streams_while_sent() {
+    let mut psu = PowerSupply::new(MockTransport::new(|_: &Request| Response::Ack));
+    psu.set_timeout(Duration::from_millis(20));
+    let mut dev = Device::default();
+    dev.core().set_voltage(0.9);
+    dev.mem().set_current(12.5);
+    psu.transport().set_telemetry(Some(dev));
+
+    let mut stream = psu.telemetry_stream();
+    for _ in 0..3 {
+        let mut dev = stream.next().unwrap().unwrap();
+        assert_eq!(dev.core().get_voltage(), 0.9);
+        assert_eq!(dev.mem().get_current(), 12.5);
+    }
+}
+
+#[test]
+fn missing_telemetry_times_out_and_ends_the_stream() {
+    let mut psu = PowerSupply::new(MockTransport::new(|_: &Request| Response::Ack));
+    psu.set_timeout(Duration::from_millis(20));
+
+    let mut stream = psu.telemetry_stream();
+
+    assert!(matches!(stream.next(), Some(Err(Error::Timeout))));
+    assert!(stream.next().is_none());
+}
+
+#[test]
+fn corrupted_frames_are_skipped() {
+    let mut psu = simulated(Tps536c7::default());
+    // Half a frame and line noise ahead of the reply
+    psu.transport()
+        .inject(&[0x05, 0x01, 0x02, 0x00, 0xFF, 0x13, 0x00]);
+
+    psu.ping().unwrap();
+}
+
+#[test]
+fn stream_samples_arrive_in_order() {
+    let mut streamer = Streamer::default();
+    let mut psu = PowerSupply::new(MockTransport::new(move |request: &Request| {
+        streamer.handle(request, 0).unwrap_or(Response::Ack)
+    }));
+    psu.set_timeout(Duration::from_millis(20));
+    assert!(matches!(
+        psu.start_stream(StreamConfig::default()),
+        Err(Error::Device(RequestError::InvalidRate))
+    ));
+    psu.start_stream(StreamConfig {
+        rate_hz: 1000,
+        fields: StreamConfig::VOUT,
+        core: true,
+        mem: false,
+    })
+    .unwrap();
+    for seq in 0..3u16 {
+        let sample = Sample {
+            tick_us: seq as u32 * 1000,
+            ..Sample::default()
+        };
+        psu.transport().send(seq, &Message::Sample(sample));
+    }
+
+    let samples: Vec<_> = psu.samples().collect();
+
+    assert_eq!(samples.len(), 4);
+    for (seq, sample) in samples[..3].iter().enumerate() {
+        let (got, sample) = sample.as_ref().unwrap();
+        assert_eq!(*got as usize, seq);
+        assert_eq!(sample.tick_us as usize, seq * 1000);
+    }
+    assert!(matches!(samples[3], Err(Error::Timeout)));
+    psu.stop_stream().unwrap();
+}
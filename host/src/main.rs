@@ -0,0 +1,598 @@
+use std::process::ExitCode;
+use std::thread;
+use std::time::Duration;
+
+use clap::{Parser, Subcommand, ValueEnum};
+
+use firmware_core::navigation::Channel;
+use firmware_core::pmbus::{Command, Page};
+use firmware_core::profile::NAME_LEN;
+use firmware_core::protocol;
+use gpu_psu_host::{
+    self as host, ChannelSample, Device, Error, Level, Name, PowerSupply, Profile, RailProfile,
+    Register, Sample, State, StreamConfig, SweepConfig, SweepStep, PROFILES,
+};
+
+/// Control and monitor the GPU external power supply over USB
+#[derive(Parser)]
+#[command(version)]
+struct Cli {
+    /// Serial port of the power supply, found by its USB ids if not given
+    #[arg(short, long, global = true)]
+    port: Option<String>,
+    /// Seconds to wait for a reply
+    #[arg(long, global = true, default_value_t = 1.0)]
+    timeout: f64,
+    #[command(subcommand)]
+    command: Cmd,
+}
+
+#[derive(Subcommand)]
+enum Cmd {
+    /// Print the latest telemetry of both channels and the last ramp that aborted
+    Status,
+    /// Change a setpoint, volts for vcore / vmem, amps for the current limits and mV/ms for the
+    /// rate voltage changes are ramped at
+    Set { target: Target, value: f32 },
+    /// Read a PMBus register
+    ReadReg {
+        channel: Rail,
+        /// Command code, decimal or 0x prefixed hex
+        #[arg(value_parser = parse_u8)]
+        cmd: u8,
+        /// Number of bytes to read
+        #[arg(default_value_t = 2)]
+        len: u8,
+        /// How to decode the value
+        #[arg(short, long, value_enum, default_value_t = Format::Raw)]
+        format: Format,
+    },
+    /// Write raw bytes to a PMBus register
+    WriteReg {
+        channel: Rail,
+        #[arg(value_parser = parse_u8)]
+        cmd: u8,
+        #[arg(value_parser = parse_u8)]
+        data: Vec<u8>,
+    },
+    /// Print telemetry as it arrives, one line per channel
+    Monitor {
+        /// Stop after this many updates
+        #[arg(short = 'n', long)]
+        count: Option<u32>,
+    },
+    /// Stream samples at a fixed rate, one line per sample
+    Stream {
+        /// Samples per second, 10 to 1000
+        #[arg(short, long, default_value_t = 100)]
+        rate: u16,
+        /// Values to sample, all of them if not given
+        #[arg(short, long, value_enum, value_delimiter = ',')]
+        fields: Vec<Field>,
+        /// Channels to sample, both if not given
+        #[arg(short, long, value_enum, value_delimiter = ',')]
+        channels: Vec<Rail>,
+        /// Stop after this many samples
+        #[arg(short = 'n', long)]
+        count: Option<u32>,
+    },
+    /// Read the common configuration and telemetry registers of both channels
+    Dump,
+    /// Manage the named profiles stored on the power supply
+    Profile {
+        #[command(subcommand)]
+        action: ProfileCmd,
+    },
+    /// Switch both outputs on (memory first) or off (core first), or print where the power
+    /// sequence is if neither is given
+    Output { switch: Option<Switch> },
+    /// Move the outputs to a margin around their setpoints for stability testing, the outputs
+    /// have to be on
+    Margin {
+        #[command(subcommand)]
+        action: MarginCmd,
+    },
+}
+
+#[derive(Subcommand)]
+enum MarginCmd {
+    /// Print the margin of both channels and the steps of the last sweep
+    Show,
+    /// Move a channel to its high or low margin or back to nominal
+    Set { channel: Rail, level: MarginLevel },
+    /// Set how far a channel is margined, in percent of its setpoint
+    Percent { channel: Rail, percent: f32 },
+    /// Step channels through their low and high margins and back, printing telemetry at the end
+    /// of each step
+    Sweep {
+        /// Channels to sweep, both if not given
+        #[arg(short, long, value_enum, value_delimiter = ',')]
+        channels: Vec<Rail>,
+        /// Milliseconds to hold each step, 10 to 60000
+        #[arg(short, long, default_value_t = 1000)]
+        dwell: u32,
+    },
+    /// End a running sweep, the swept channels go back to nominal
+    Stop,
+}
+
+#[derive(Subcommand)]
+enum ProfileCmd {
+    /// List the stored profiles
+    List,
+    /// Print the setpoints and limits a profile holds
+    Show {
+        #[arg(value_parser = parse_slot)]
+        slot: u8,
+    },
+    /// Write a profile to the controller
+    Load {
+        #[arg(value_parser = parse_slot)]
+        slot: u8,
+    },
+    /// Store the current setpoints and limits as a profile, replacing the one in the slot
+    Save {
+        #[arg(value_parser = parse_slot)]
+        slot: u8,
+        /// Up to 12 printable ASCII characters
+        #[arg(value_parser = parse_name)]
+        name: Name,
+    },
+    Delete {
+        #[arg(value_parser = parse_slot)]
+        slot: u8,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Target {
+    Vcore,
+    Vmem,
+    IcoreLimit,
+    ImemLimit,
+    VcoreRamp,
+    VmemRamp,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Switch {
+    On,
+    Off,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum MarginLevel {
+    Nominal,
+    High,
+    Low,
+}
+
+impl From<MarginLevel> for Level {
+    fn from(level: MarginLevel) -> Level {
+        match level {
+            MarginLevel::Nominal => Level::Nominal,
+            MarginLevel::High => Level::High,
+            MarginLevel::Low => Level::Low,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum Rail {
+    Core,
+    Mem,
+}
+
+impl From<Rail> for host::Channel {
+    fn from(rail: Rail) -> host::Channel {
+        match rail {
+            Rail::Core => host::Channel::Core,
+            Rail::Mem => host::Channel::Mem,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Format {
+    Raw,
+    Vout,
+    Linear11,
+}
+
+impl From<Format> for protocol::Format {
+    fn from(format: Format) -> protocol::Format {
+        match format {
+            Format::Raw => protocol::Format::Raw,
+            Format::Vout => protocol::Format::Vout,
+            Format::Linear11 => protocol::Format::Linear11,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Field {
+    Vout,
+    Iout,
+    Temp,
+    Setpoints,
+    Status,
+}
+
+impl Field {
+    fn bit(self) -> u8 {
+        match self {
+            Field::Vout => StreamConfig::VOUT,
+            Field::Iout => StreamConfig::IOUT,
+            Field::Temp => StreamConfig::TEMPERATURE,
+            Field::Setpoints => StreamConfig::SETPOINTS,
+            Field::Status => StreamConfig::STATUS,
+        }
+    }
+}
+
+/// Registers printed by dump: name, command, length, format
+const DUMP: &[(&str, Command, u8, protocol::Format)] = &[
+    ("OPERATION", Command::Operation, 1, protocol::Format::Raw),
+    (
+        "ON_OFF_CONFIG",
+        Command::OnOffConfig,
+        1,
+        protocol::Format::Raw,
+    ),
+    ("VOUT_MODE", Command::VoutMode, 1, protocol::Format::Raw),
+    (
+        "VOUT_COMMAND",
+        Command::VOUTCommand,
+        2,
+        protocol::Format::Vout,
+    ),
+    ("VOUT_MAX", Command::VOUTMax, 2, protocol::Format::Vout),
+    ("VOUT_MIN", Command::VOUTMin, 2, protocol::Format::Vout),
+    (
+        "IOUT_OC_FAULT_LIMIT",
+        Command::IoutOCFaultLimit,
+        2,
+        protocol::Format::Linear11,
+    ),
+    (
+        "IOUT_OC_WARN_LIMIT",
+        Command::IoutOCWarnLimit,
+        2,
+        protocol::Format::Linear11,
+    ),
+    (
+        "OT_FAULT_LIMIT",
+        Command::OTFaultLimit,
+        2,
+        protocol::Format::Linear11,
+    ),
+    (
+        "OT_WARN_LIMIT",
+        Command::OTWarnLimit,
+        2,
+        protocol::Format::Linear11,
+    ),
+    ("STATUS_WORD", Command::StatusWord, 2, protocol::Format::Raw),
+    ("READ_VIN", Command::ReadVin, 2, protocol::Format::Linear11),
+    ("READ_VOUT", Command::ReadVout, 2, protocol::Format::Vout),
+    (
+        "READ_IOUT",
+        Command::ReadIout,
+        2,
+        protocol::Format::Linear11,
+    ),
+    (
+        "READ_TEMPERATURE_1",
+        Command::ReadTemperature1,
+        2,
+        protocol::Format::Linear11,
+    ),
+    (
+        "READ_POUT",
+        Command::ReadPout,
+        2,
+        protocol::Format::Linear11,
+    ),
+    ("READ_PIN", Command::ReadPin, 2, protocol::Format::Linear11),
+];
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    match run(&cli) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("error: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(cli: &Cli) -> Result<(), Error> {
+    let mut psu = match &cli.port {
+        Some(path) => PowerSupply::open(path)?,
+        None => PowerSupply::discover()?,
+    };
+    psu.set_timeout(Duration::from_secs_f64(cli.timeout));
+
+    match cli.command {
+        Cmd::Status => {
+            let mut dev = psu.telemetry()?;
+            print_status(&mut dev);
+            if let Some(event) = psu.ramp_abort()? {
+                let name = match event.page() {
+                    Page::ChannelA => "core",
+                    _ => "mem",
+                };
+                println!("ramp on {name} stopped: {}", event.as_str());
+            }
+        }
+        Cmd::Set { target, value } => match target {
+            Target::Vcore => psu.set_voltage(host::Channel::Core, value)?,
+            Target::Vmem => psu.set_voltage(host::Channel::Mem, value)?,
+            Target::IcoreLimit => psu.set_current_limit(host::Channel::Core, value)?,
+            Target::ImemLimit => psu.set_current_limit(host::Channel::Mem, value)?,
+            Target::VcoreRamp => psu.set_ramp_rate(host::Channel::Core, value)?,
+            Target::VmemRamp => psu.set_ramp_rate(host::Channel::Mem, value)?,
+        },
+        Cmd::ReadReg {
+            channel,
+            cmd,
+            len,
+            format,
+        } => {
+            let reg = psu.read_register(channel.into(), cmd, len, format.into())?;
+            println!("{}", format_register(&reg));
+        }
+        Cmd::WriteReg {
+            channel,
+            cmd,
+            ref data,
+        } => psu.write_register(channel.into(), cmd, data)?,
+        Cmd::Monitor { count } => {
+            let updates = psu
+                .telemetry_stream()
+                .take(count.map_or(usize::MAX, |n| n as usize));
+            for dev in updates {
+                let mut dev = dev?;
+                print_channel("core", dev.core());
+                print_channel("mem", dev.mem());
+            }
+        }
+        Cmd::Stream {
+            rate,
+            ref fields,
+            ref channels,
+            count,
+        } => {
+            let fields = if fields.is_empty() {
+                StreamConfig::ALL
+            } else {
+                fields.iter().fold(0, |bits, field| bits | field.bit())
+            };
+            let both = channels.is_empty();
+            psu.start_stream(StreamConfig {
+                rate_hz: rate,
+                fields,
+                core: both || channels.contains(&Rail::Core),
+                mem: both || channels.contains(&Rail::Mem),
+            })?;
+            let samples = psu.samples().take(count.map_or(usize::MAX, |n| n as usize));
+            for sample in samples {
+                let (seq, sample) = sample?;
+                print_sample(seq, &sample);
+            }
+            psu.stop_stream()?;
+        }
+        Cmd::Dump => {
+            for (name, channel) in [("core", host::Channel::Core), ("mem", host::Channel::Mem)] {
+                println!("[{name}]");
+                for &(reg, cmd, len, format) in DUMP {
+                    match psu.read_register(channel, cmd.to_address(), len, format) {
+                        Ok(val) => println!("{reg:<20} {}", format_register(&val)),
+                        Err(err) => println!("{reg:<20} {err}"),
+                    }
+                }
+            }
+        }
+        Cmd::Profile { ref action } => match *action {
+            ProfileCmd::List => {
+                for (slot, name) in psu.profiles()? {
+                    println!("{}  {}", slot + 1, name.as_str());
+                }
+            }
+            ProfileCmd::Show { slot } => print_profile(&psu.profile(slot)?),
+            ProfileCmd::Load { slot } => psu.load_profile(slot)?,
+            ProfileCmd::Save { slot, name } => psu.save_profile(slot, name)?,
+            ProfileCmd::Delete { slot } => psu.delete_profile(slot)?,
+        },
+        Cmd::Output { switch } => match switch {
+            Some(Switch::On) => psu.set_output(true)?,
+            Some(Switch::Off) => psu.set_output(false)?,
+            None => match psu.output()? {
+                State::Fault(fault) => println!("Fault: {fault:?}"),
+                state => println!("{}", state.as_str()),
+            },
+        },
+        Cmd::Margin { ref action } => match *action {
+            MarginCmd::Show => {
+                let status = psu.margin()?;
+                for (idx, name) in ["core", "mem"].iter().enumerate() {
+                    println!(
+                        "{name:<4} {:<7} {:.1} %",
+                        status.level[idx].as_str(),
+                        status.percent[idx]
+                    );
+                }
+                if let Some(step) = status.sweep {
+                    println!("sweep running, step {}", step + 1);
+                }
+                for step in 0..status.logged {
+                    print_sweep_step(step, &psu.sweep_step(step)?);
+                }
+            }
+            MarginCmd::Set { channel, level } => psu.set_margin(channel.into(), level.into())?,
+            MarginCmd::Percent { channel, percent } => {
+                psu.set_margin_percent(channel.into(), percent)?
+            }
+            MarginCmd::Sweep {
+                ref channels,
+                dwell,
+            } => {
+                let both = channels.is_empty();
+                psu.start_margin_sweep(SweepConfig {
+                    core: both || channels.contains(&Rail::Core),
+                    mem: both || channels.contains(&Rail::Mem),
+                    dwell_ms: dwell,
+                })?;
+                // Steps are printed as the supply logs them, checking a few times per dwell
+                let mut printed = 0;
+                loop {
+                    let status = psu.margin()?;
+                    for step in printed..status.logged {
+                        print_sweep_step(step, &psu.sweep_step(step)?);
+                    }
+                    printed = status.logged;
+                    if status.sweep.is_none() {
+                        break;
+                    }
+                    thread::sleep(Duration::from_millis((dwell / 4).into()));
+                }
+            }
+            MarginCmd::Stop => psu.stop_margin_sweep()?,
+        },
+    }
+    Ok(())
+}
+
+fn format_register(reg: &Register) -> String {
+    let raw: Vec<String> = reg.data().iter().map(|b| format!("{b:02X}")).collect();
+    match reg.value {
+        Some(val) => format!("{:<12} {val:.4}", raw.join(" ")),
+        None => raw.join(" "),
+    }
+}
+
+fn print_status(dev: &mut Device) {
+    print_channel_status("core", dev.core());
+    print_channel_status("mem", dev.mem());
+}
+
+fn print_channel_status(name: &str, chan: &Channel) {
+    println!("[{name}]");
+    if chan.get_error() {
+        println!("  read failed, values are stale");
+    }
+    println!(
+        "  voltage      {:.4} V (setpoint {:.4} V)",
+        chan.get_voltage(),
+        chan.get_voltage_setpoint()
+    );
+    println!(
+        "  current      {:.2} A (limit {:.2} A)",
+        chan.get_current(),
+        chan.get_current_limit()
+    );
+    println!("  temperature  {:.1} C", chan.get_temperature());
+    println!("  status       {:#06X}", chan.get_status().bits());
+    for (idx, phase) in chan.get_phases().iter().enumerate() {
+        println!(
+            "  phase {idx}      {:.2} A {:.1} C",
+            phase.current, phase.temperature
+        );
+    }
+}
+
+fn print_channel(name: &str, chan: &Channel) {
+    println!(
+        "{name:<4} {:.4} V {:.2} A {:.1} C status {:#06X}{}",
+        chan.get_voltage(),
+        chan.get_current(),
+        chan.get_temperature(),
+        chan.get_status().bits(),
+        if chan.get_error() { " (stale)" } else { "" }
+    );
+}
+
+fn print_profile(profile: &Profile) {
+    println!("{}", profile.name.as_str());
+    for (name, rail) in [("core", &profile.core), ("mem", &profile.mem)] {
+        print_rail_profile(name, rail);
+    }
+}
+
+fn print_rail_profile(name: &str, rail: &RailProfile) {
+    println!(
+        "{name:<4} {:.4} V limit {:.2} A ramp {:.3} mV/ms OT {:.1} C",
+        rail.voltage, rail.current_limit, rail.ramp_rate, rail.ot_limit
+    );
+}
+
+fn print_sample(seq: u16, sample: &Sample) {
+    let mut line = format!(
+        "{seq:>5} {:>10} us overruns {}",
+        sample.tick_us, sample.overruns
+    );
+    for (name, chan) in [("core", &sample.core), ("mem", &sample.mem)] {
+        let Some(chan) = chan else { continue };
+        line += &format!(" | {name}{}", format_channel_sample(chan));
+    }
+    println!("{line}");
+}
+
+fn print_sweep_step(step: u8, logged: &SweepStep) {
+    let name = match logged.page {
+        Page::ChannelA => "core",
+        _ => "mem",
+    };
+    println!(
+        "{:>2} {name:<4} {:<7}{}",
+        step + 1,
+        logged.level.as_str(),
+        format_channel_sample(&logged.sample)
+    );
+}
+
+/// The sampled values of a channel, each with a leading space
+fn format_channel_sample(chan: &ChannelSample) -> String {
+    let mut line = String::new();
+    if let Some(vout) = chan.vout {
+        line += &format!(" {vout:.4} V");
+    }
+    if let Some(iout) = chan.iout {
+        line += &format!(" {iout:.2} A");
+    }
+    if let Some(temp) = chan.temperature {
+        line += &format!(" {temp:.1} C");
+    }
+    if let (Some(vset), Some(ilim)) = (chan.vout_setpoint, chan.current_limit) {
+        line += &format!(" set {vset:.4} V lim {ilim:.2} A");
+    }
+    if let Some(status) = chan.status {
+        line += &format!(" status {:#06X}", status.bits());
+    }
+    if chan.error {
+        line += " (read failed)";
+    }
+    line
+}
+
+/// Parses a profile slot numbered from 1, as on the front panel, into its index
+fn parse_slot(arg: &str) -> Result<u8, String> {
+    match arg.parse::<u8>() {
+        Ok(slot @ 1..) if slot as usize <= PROFILES => Ok(slot - 1),
+        _ => Err(format!("{arg}: slots are numbered 1 to {PROFILES}")),
+    }
+}
+
+fn parse_name(arg: &str) -> Result<Name, String> {
+    Name::new(arg).ok_or_else(|| format!("{arg:?}: up to {NAME_LEN} printable ASCII characters"))
+}
+
+/// Parses a byte given in decimal or with a 0x prefix in hex
+fn parse_u8(arg: &str) -> Result<u8, String> {
+    let parsed = match arg.strip_prefix("0x").or_else(|| arg.strip_prefix("0X")) {
+        Some(hex) => u8::from_str_radix(hex, 16),
+        None => arg.parse(),
+    };
+    parsed.map_err(|err| format!("{arg}: {err}"))
+}
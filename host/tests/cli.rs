@@ -0,0 +1,309 @@
+#[path = "../../firmware-core/tests/common/mod.rs"]
+mod common;
+
+use std::io::{Read, Write};
+use std::process::{Command, Output};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use common::{controller, is_on, NoDelay};
+use firmware_core::control::{handle_request, update_vrm_read};
+use firmware_core::margin::Margin;
+use firmware_core::navigation::Device;
+use firmware_core::pmbus::Page;
+use firmware_core::profile::Profiles;
+use firmware_core::protocol::{encode_frame, Frame, FrameReader, Message, MAX_FRAME};
+use firmware_core::ramp::{Ramp, STEP_US};
+use firmware_core::sequence::{Ready, Sequencer};
+use serialport::{SerialPort, TTYPort};
+use tps536c7_simulator::{Channel, Fault, Tps536c7};
+
+/// How long the stand-in keeps sending telemetry after it last heard from the host, bounded so
+/// nothing piles up in the pty while no CLI is running
+const TELEMETRY_WINDOW: Duration = Duration::from_millis(500);
+const TELEMETRY_PERIOD: Duration = Duration::from_millis(50);
+
+/// The firmware's USB loop on one end of a pseudo-terminal, driving the simulator
+struct StandIn {
+    path: String,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+    // Held open so the master side never sees a hang up between CLI runs
+    _slave: TTYPort,
+}
+
+impl StandIn {
+    fn new(sim: Tps536c7) -> StandIn {
+        let (mut master, slave) = TTYPort::pair().expect("pseudo-terminal");
+        master.set_timeout(Duration::from_millis(10)).unwrap();
+        let path = slave.name().expect("pty path");
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let running = stop.clone();
+        let thread = thread::spawn(move || {
+            let mut controller = controller(sim);
+            let mut reader = FrameReader::default();
+            let mut dev = Device::default();
+            let mut profiles = Profiles::default();
+            let mut ramp = Ramp::default();
+            let mut sequencer = Sequencer::default();
+            let mut margin = Margin::default();
+            let mut now = 0;
+            let mut heard: Option<Instant> = None;
+            let mut sent = Instant::now();
+            let mut telemetry_seq = 0u16;
+
+            while !running.load(Ordering::Relaxed) {
+                let mut buf = [0u8; 64];
+                let count = master.read(&mut buf).unwrap_or(0);
+                if count > 0 {
+                    heard = Some(Instant::now());
+                }
+                for &byte in &buf[..count] {
+                    let Some(Ok(Frame {
+                        seq,
+                        message: Message::Request(request),
+                    })) = reader.push(byte)
+                    else {
+                        continue;
+                    };
+                    let response = sequencer
+                        .handle(&request, &mut controller)
+                        .or_else(|| margin.handle(&request, &mut controller, &sequencer))
+                        .or_else(|| ramp.handle(&request, &mut controller))
+                        .or_else(|| profiles.handle(&request, &mut controller, &mut ramp, &mut dev))
+                        .unwrap_or_else(|| handle_request(&mut controller, &mut NoDelay, &request));
+                    // Ramps run to the end before the reply, so the CLI reads back the target
+                    while ramp.is_running() {
+                        now += STEP_US;
+                        ramp.poll(&mut controller, now);
+                    }
+                    // As does the power sequence, with each rail ready as soon as it is on
+                    for _ in 0..8 {
+                        let ready = Ready {
+                            core: is_on(&mut controller, Page::ChannelA),
+                            mem: is_on(&mut controller, Page::ChannelB),
+                        };
+                        now += STEP_US;
+                        sequencer.poll(&mut controller, ready, now);
+                    }
+                    // And margin sweeps
+                    while margin.is_sweeping() {
+                        now += STEP_US;
+                        margin.poll(&mut controller, &sequencer, now);
+                    }
+                    send(&mut master, seq, &Message::Response(response));
+                }
+
+                let listening = heard.is_some_and(|at| at.elapsed() < TELEMETRY_WINDOW);
+                if listening && sent.elapsed() >= TELEMETRY_PERIOD {
+                    update_vrm_read(&mut dev, &mut controller);
+                    send(&mut master, telemetry_seq, &Message::Telemetry(dev.clone()));
+                    telemetry_seq = telemetry_seq.wrapping_add(1);
+                    sent = Instant::now();
+                }
+            }
+        });
+
+        StandIn {
+            path,
+            stop,
+            thread: Some(thread),
+            _slave: slave,
+        }
+    }
+
+    /// Runs the CLI against the stand-in
+    fn run(&self, args: &[&str]) -> Output {
+        Command::new(env!("CARGO_BIN_EXE_gpu-psu-ctl"))
+            .args(["--port", &self.path])
+            .args(args)
+            .output()
+            .unwrap()
+    }
+
+    /// Runs the CLI, expecting it to succeed, and returns what it printed
+    fn stdout(&self, args: &[&str]) -> String {
+        let output = self.run(args);
+        assert!(
+            output.status.success(),
+            "{args:?} failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8(output.stdout).unwrap()
+    }
+}
+
+impl Drop for StandIn {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn send(port: &mut TTYPort, seq: u16, message: &Message) {
+    let mut frame = [0u8; MAX_FRAME];
+    let length = encode_frame(seq, message, &mut frame).unwrap();
+    port.write_all(&frame[..length]).unwrap();
+}
+
+#[test]
+fn status_prints_both_channels() {
+    let mut sim = Tps536c7::default();
+    sim.set_load(Channel::A, 100.);
+    let stand_in = StandIn::new(sim);
+
+    let out = stand_in.stdout(&["status"]);
+
+    assert!(out.contains("[core]"), "{out}");
+    assert!(out.contains("[mem]"), "{out}");
+    // 0.9 V in the channel's ULINEAR16 format
+    assert!(out.contains("0.9004 V"), "{out}");
+    assert!(out.contains("100.00 A"), "{out}");
+}
+
+#[test]
+fn status_reports_an_aborted_ramp_once() {
+    let mut sim = Tps536c7::default();
+    sim.inject_fault(Channel::B, Fault::OverCurrent);
+    let stand_in = StandIn::new(sim);
+
+    stand_in.stdout(&["set", "vmem", "1.3"]);
+
+    let out = stand_in.stdout(&["status"]);
+    assert!(out.contains("ramp on mem stopped: Overcurrent"), "{out}");
+    let out = stand_in.stdout(&["status"]);
+    assert!(!out.contains("ramp on"), "{out}");
+}
+
+#[test]
+fn set_writes_in_the_channel_format() {
+    let stand_in = StandIn::new(Tps536c7::default());
+
+    stand_in.stdout(&["set", "vcore-ramp", "20"]);
+    stand_in.stdout(&["set", "vcore", "1.05"]);
+    stand_in.stdout(&["set", "imem-limit", "40"]);
+
+    let vout = stand_in.stdout(&["read-reg", "core", "0x21", "--format", "vout"]);
+    assert!(vout.trim_end().ends_with("1.0508"), "{vout}");
+    let limit = stand_in.stdout(&["read-reg", "mem", "0x46", "-f", "linear11"]);
+    assert!(limit.trim_end().ends_with("40.0000"), "{limit}");
+}
+
+#[test]
+fn write_reg_sends_raw_bytes() {
+    let stand_in = StandIn::new(Tps536c7::default());
+
+    // VOUT_COMMAND = 0x0200, 1 V in 2^-9 steps
+    stand_in.stdout(&["write-reg", "mem", "0x21", "0x00", "0x02"]);
+
+    let raw = stand_in.stdout(&["read-reg", "mem", "0x21"]);
+    assert_eq!(raw.trim(), "00 02");
+}
+
+#[test]
+fn refused_requests_fail_the_command() {
+    let stand_in = StandIn::new(Tps536c7::default());
+
+    // STORE_DEFAULT_ALL is only allowed through the confirmed NVM request
+    let output = stand_in.run(&["write-reg", "core", "0x11"]);
+
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Refused"));
+
+    let output = stand_in.run(&["set", "vmem-ramp", "1000"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("InvalidRampRate"));
+}
+
+#[test]
+fn monitor_stops_after_the_count() {
+    let stand_in = StandIn::new(Tps536c7::default());
+
+    let out = stand_in.stdout(&["monitor", "-n", "3"]);
+
+    assert_eq!(
+        out.lines().filter(|line| line.starts_with("core")).count(),
+        3
+    );
+    assert_eq!(
+        out.lines().filter(|line| line.starts_with("mem")).count(),
+        3
+    );
+}
+
+#[test]
+fn dump_lists_both_channels() {
+    let stand_in = StandIn::new(Tps536c7::default());
+
+    let out = stand_in.stdout(&["dump"]);
+
+    assert_eq!(
+        out.lines()
+            .filter(|line| line.starts_with("VOUT_COMMAND"))
+            .count(),
+        2
+    );
+    assert!(out.contains("1.3496"), "{out}");
+}
+
+#[test]
+fn profiles_are_saved_and_loaded_by_slot() {
+    let stand_in = StandIn::new(Tps536c7::default());
+
+    stand_in.stdout(&["set", "vcore", "1.05"]);
+    stand_in.stdout(&["profile", "save", "2", "daily OC"]);
+    stand_in.stdout(&["set", "vcore", "0.95"]);
+    stand_in.stdout(&["profile", "load", "2"]);
+
+    assert_eq!(stand_in.stdout(&["profile", "list"]), "2  daily OC\n");
+    let show = stand_in.stdout(&["profile", "show", "2"]);
+    assert!(show.contains("core 1.0508 V"), "{show}");
+    let vout = stand_in.stdout(&["read-reg", "core", "0x21", "--format", "vout"]);
+    assert!(vout.trim_end().ends_with("1.0508"), "{vout}");
+
+    let output = stand_in.run(&["profile", "load", "5"]);
+    assert!(!output.status.success());
+    stand_in.stdout(&["profile", "delete", "2"]);
+    let output = stand_in.run(&["profile", "load", "2"]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("NoProfile"));
+}
+
+#[test]
+fn output_switches_both_rails() {
+    let stand_in = StandIn::new(Tps536c7::default());
+
+    assert_eq!(stand_in.stdout(&["output"]), "Off\n");
+    stand_in.stdout(&["output", "on"]);
+    assert_eq!(stand_in.stdout(&["output"]), "On\n");
+
+    stand_in.stdout(&["output", "off"]);
+    let vout = stand_in.stdout(&["read-reg", "core", "0x8B", "--format", "vout"]);
+    assert!(vout.ends_with(" 0.0000\n"), "{vout}");
+}
+
+#[test]
+fn margin_moves_a_rail_and_sweeps_print_each_step() {
+    let stand_in = StandIn::new(Tps536c7::default());
+
+    let output = stand_in.run(&["margin", "set", "core", "high"]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("OutputOff"));
+    stand_in.stdout(&["output", "on"]);
+    stand_in.stdout(&["margin", "percent", "core", "10"]);
+    stand_in.stdout(&["margin", "set", "core", "high"]);
+    let vout = stand_in.stdout(&["read-reg", "core", "0x8B", "--format", "vout"]);
+    assert!(vout.ends_with(" 0.9902\n"), "{vout}");
+    assert!(stand_in
+        .stdout(&["margin", "show"])
+        .starts_with("core High    10.0 %\nmem  Nominal 5.0 %\n"));
+
+    let sweep = stand_in.stdout(&["margin", "sweep", "--channels", "mem", "--dwell", "10"]);
+    let lines: Vec<&str> = sweep.lines().collect();
+    assert_eq!(lines.len(), 3, "{sweep}");
+    assert!(lines[0].starts_with(" 1 mem  Low     1.28"), "{sweep}");
+    assert!(lines[2].starts_with(" 3 mem  Nominal 1.3"), "{sweep}");
+}
@@ -0,0 +1,411 @@
+// Host side model of the TPS536C7 as an embedded_hal::i2c::I2c bus, so the driver, UI and USB
+// code can be exercised without the board.
+
+use embedded_hal::i2c::{ErrorKind, ErrorType, I2c, NoAcknowledgeSource, Operation};
+
+pub mod linear;
+pub mod rail;
+
+use linear::{pec, slinear11_from, vout_from};
+use rail::{
+    Config, Rail, INVALID_COMMAND, INVALID_DATA, IOUT_OC_FAULT, OT_FAULT, PEC_FAILED, VOUT_OV_FAULT,
+};
+
+/// Address the controller answers on, as strapped on the board
+pub const ADDRESS: u8 = 0x5F;
+
+/// Transactions an NVM store or restore keeps the controller busy for
+const BUSY_TRANSACTIONS: u32 = 3;
+
+/// The two outputs, PAGE 0 and PAGE 1
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Channel {
+    A,
+    B,
+}
+
+impl Channel {
+    fn index(self) -> usize {
+        match self {
+            Channel::A => 0,
+            Channel::B => 1,
+        }
+    }
+}
+
+/// Faults that can be forced on a channel regardless of the load model
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Fault {
+    OverCurrent,
+    OverTemperature,
+    OverVoltage,
+}
+
+/// Simulated dual channel TPS536C7
+pub struct Tps536c7 {
+    address: u8,
+    /// PAGE, 0x00, 0x01 or 0xFF for both
+    page: u8,
+    rails: [Rail; 2],
+    /// Configuration saved by STORE_DEFAULT_ALL and STORE_USER_ALL
+    default_store: [Config; 2],
+    user_store: [Config; 2],
+    vin: f32,
+    ambient: f32,
+    /// Reject writes that do not carry a PEC byte
+    pec_required: bool,
+    /// Transactions left that NACK their address
+    nacks: u32,
+    /// Transactions left before an NVM operation completes
+    busy: u32,
+    mfr_id: Vec<u8>,
+    mfr_model: Vec<u8>,
+    mfr_revision: Vec<u8>,
+}
+
+impl Tps536c7 {
+    /// A Vcore (6 phase, 0.9 V) and Vmem (2 phase, 1.35 V) configuration running from 12 V
+    pub fn new(address: u8) -> Tps536c7 {
+        let rails = [Rail::new(0.9, 200., 6), Rail::new(1.35, 60., 2)];
+        let store = [rails[0].config.clone(), rails[1].config.clone()];
+        Tps536c7 {
+            address,
+            page: 0,
+            rails,
+            default_store: store.clone(),
+            user_store: store,
+            vin: 12.,
+            ambient: 25.,
+            pec_required: false,
+            nacks: 0,
+            busy: 0,
+            mfr_id: b"TI".to_vec(),
+            mfr_model: b"TPS536C7".to_vec(),
+            mfr_revision: b"1.0".to_vec(),
+        }
+    }
+
+    // MODEL INPUTS
+    /// Current the load on a channel draws while its output is on
+    pub fn set_load(&mut self, channel: Channel, amps: f32) {
+        self.rails[channel.index()].load = amps;
+    }
+
+    pub fn set_vin(&mut self, volts: f32) {
+        self.vin = volts;
+    }
+
+    pub fn set_ambient(&mut self, degrees: f32) {
+        self.ambient = degrees;
+    }
+
+    /// Number of power stages fitted to a channel
+    pub fn set_phases(&mut self, channel: Channel, phases: u8) {
+        self.rails[channel.index()].phases = phases;
+    }
+
+    pub fn set_vout_mode(&mut self, channel: Channel, mode: u8) {
+        self.rails[channel.index()].config.vout_mode = mode;
+    }
+
+    pub fn set_mfr_id(&mut self, id: &[u8]) {
+        self.mfr_id = id.to_vec();
+    }
+
+    pub fn set_mfr_model(&mut self, model: &[u8]) {
+        self.mfr_model = model.to_vec();
+    }
+
+    /// Rejects writes without a PEC byte, reads always offer one
+    pub fn require_pec(&mut self, required: bool) {
+        self.pec_required = required;
+    }
+
+    // FAULT INJECTION
+    /// NACKs the address of the next count transactions
+    pub fn inject_nack(&mut self, count: u32) {
+        self.nacks = count;
+    }
+
+    /// Latches a fault on a channel and shuts its output down, cleared by CLEAR_FAULTS
+    pub fn inject_fault(&mut self, channel: Channel, fault: Fault) {
+        let rail = &mut self.rails[channel.index()];
+        match fault {
+            Fault::OverCurrent => rail.status_iout |= IOUT_OC_FAULT,
+            Fault::OverTemperature => rail.status_temperature |= OT_FAULT,
+            Fault::OverVoltage => rail.status_vout |= VOUT_OV_FAULT,
+        }
+        rail.tripped = true;
+    }
+
+    // OBSERVATION
+    pub fn rail(&self, channel: Channel) -> &Rail {
+        &self.rails[channel.index()]
+    }
+
+    pub fn page(&self) -> u8 {
+        self.page
+    }
+
+    /// Voltage the output is actually at
+    pub fn vout(&self, channel: Channel) -> f32 {
+        self.rails[channel.index()].vout(self.vin)
+    }
+
+    pub fn is_on(&self, channel: Channel) -> bool {
+        self.rails[channel.index()].is_on(self.vin)
+    }
+
+    /// Pages the current PAGE addresses
+    fn paged(&self) -> &'static [usize] {
+        match self.page {
+            0x00 => &[0],
+            0x01 => &[1],
+            _ => &[0, 1],
+        }
+    }
+
+    /// Rail that reads come from, channel A when both are paged
+    fn rail_read(&self) -> &Rail {
+        &self.rails[self.paged()[0]]
+    }
+
+    /// Records a communication fault in STATUS_CML and NACKs the data
+    fn cml(&mut self, flag: u8) -> ErrorKind {
+        for &idx in self.paged() {
+            self.rails[idx].status_cml |= flag;
+        }
+        ErrorKind::NoAcknowledge(NoAcknowledgeSource::Data)
+    }
+
+    /// Data bytes a write of cmd carries, None if the command cannot be written
+    fn write_length(&self, cmd: u8) -> Option<usize> {
+        match cmd {
+            0x03 | 0x11 | 0x12 | 0x15 | 0x16 => Some(0),
+            0x00 | 0x01 | 0x02 | 0x04 | 0x20 => Some(1),
+            _ if self.rail_read().config.words.contains_key(&cmd) => Some(2),
+            _ => None,
+        }
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<(), ErrorKind> {
+        let Some((&cmd, payload)) = data.split_first() else {
+            // Quick command
+            return Ok(());
+        };
+        let Some(length) = self.write_length(cmd) else {
+            return Err(self.cml(INVALID_COMMAND));
+        };
+        let payload = if payload.len() == length + 1 {
+            let mut msg = vec![self.address << 1];
+            msg.extend_from_slice(&data[..data.len() - 1]);
+            if pec(&msg) != payload[length] {
+                return Err(self.cml(PEC_FAILED));
+            }
+            &payload[..length]
+        } else if payload.len() == length && !self.pec_required {
+            payload
+        } else {
+            return Err(self.cml(INVALID_DATA));
+        };
+
+        match (cmd, payload) {
+            (0x00, &[page]) => match page {
+                0x00 | 0x01 | 0xFF => self.page = page,
+                _ => return Err(self.cml(INVALID_DATA)),
+            },
+            (0x03, _) => {
+                for &idx in self.paged() {
+                    self.rails[idx].clear_faults();
+                }
+            }
+            (0x04, &[phase]) => {
+                for &idx in self.paged() {
+                    if phase != 0xFF && phase >= self.rails[idx].phases {
+                        return Err(self.cml(INVALID_DATA));
+                    }
+                }
+                for &idx in self.paged() {
+                    self.rails[idx].phase = phase;
+                }
+            }
+            (0x11, _) => {
+                self.default_store = self.rails.clone().map(|rail| rail.config);
+                self.busy = BUSY_TRANSACTIONS;
+            }
+            (0x12, _) => {
+                for (rail, config) in self.rails.iter_mut().zip(&self.default_store) {
+                    rail.config = config.clone();
+                }
+                self.busy = BUSY_TRANSACTIONS;
+            }
+            (0x15, _) => {
+                self.user_store = self.rails.clone().map(|rail| rail.config);
+                self.busy = BUSY_TRANSACTIONS;
+            }
+            (0x16, _) => {
+                for (rail, config) in self.rails.iter_mut().zip(&self.user_store) {
+                    rail.config = config.clone();
+                }
+                self.busy = BUSY_TRANSACTIONS;
+            }
+            (_, &[val]) => {
+                for &idx in self.paged() {
+                    let rail = &mut self.rails[idx];
+                    match cmd {
+                        0x01 => {
+                            // Turning the output off re-arms a latched off fault
+                            if val & 0x80 == 0 {
+                                rail.tripped = false;
+                            }
+                            rail.config.operation = val;
+                        }
+                        0x02 => rail.config.on_off_config = val,
+                        _ => rail.config.vout_mode = val,
+                    }
+                }
+            }
+            (_, &[low, high]) => {
+                for &idx in self.paged() {
+                    self.rails[idx]
+                        .config
+                        .words
+                        .insert(cmd, u16::from_le_bytes([low, high]));
+                }
+            }
+            _ => return Err(self.cml(INVALID_DATA)),
+        }
+        Ok(())
+    }
+
+    /// Builds the response to a read of cmd, without the PEC byte
+    fn read(&mut self, cmd: u8) -> Result<Vec<u8>, ErrorKind> {
+        let (vin, ambient, busy) = (self.vin, self.ambient, self.busy > 0);
+        let rail = self.rail_read();
+        let word = |val: u16| val.to_le_bytes().to_vec();
+        let linear = |val: f32| word(slinear11_from(val));
+        let block = |data: &[u8]| {
+            let mut resp = vec![data.len() as u8];
+            resp.extend_from_slice(data);
+            resp
+        };
+        let all_phases = rail.phase == 0xFF;
+        let resp = match cmd {
+            0x00 => vec![self.page],
+            0x01 => vec![rail.config.operation],
+            0x02 => vec![rail.config.on_off_config],
+            0x04 => vec![rail.phase],
+            0x20 => vec![rail.config.vout_mode],
+            0x78 => vec![rail.status_word(vin, busy) as u8],
+            0x79 => word(rail.status_word(vin, busy)),
+            0x7A => vec![rail.status_vout],
+            0x7B => vec![rail.status_iout],
+            0x7C => vec![rail.status_input],
+            0x7D => vec![rail.status_temperature],
+            0x7E => vec![rail.status_cml],
+            0x80 => vec![rail.status_mfr_specific],
+            0x88 => linear(vin),
+            0x89 => linear(rail.pin(vin) / vin.max(0.1)),
+            0x8B => word(vout_from(rail.config.vout_mode, rail.vout(vin)).unwrap_or(0)),
+            0x8C if all_phases => linear(rail.iout(vin)),
+            0x8C => linear(rail.phase_current(vin)),
+            0x8D if all_phases => linear(rail.temperature(vin, ambient)),
+            0x8D => linear(rail.phase_temperature(vin, ambient, rail.phase)),
+            // The controller die sits away from the power stages and only sees the ambient
+            0x8E => linear(ambient),
+            0x96 => linear(rail.pout(vin)),
+            0x97 => linear(rail.pin(vin)),
+            0x99 => block(&self.mfr_id),
+            0x9A => block(&self.mfr_model),
+            0x9B => block(&self.mfr_revision),
+            0xDB => {
+                let mut data = Vec::new();
+                for rail in &self.rails {
+                    data.extend_from_slice(&rail.status_word(vin, busy).to_le_bytes());
+                    data.extend_from_slice(&[
+                        rail.status_vout,
+                        rail.status_iout,
+                        rail.status_input,
+                        rail.status_temperature,
+                        rail.status_cml,
+                        rail.status_mfr_specific,
+                    ]);
+                }
+                block(&data)
+            }
+            // Vendor specific extended status is not modelled
+            0xDD => vec![0; 7],
+            _ if rail.config.words.contains_key(&cmd) => word(rail.word(cmd)),
+            _ => return Err(self.cml(INVALID_COMMAND)),
+        };
+        Ok(resp)
+    }
+
+    /// Runs the load model once per transaction and counts down an NVM operation
+    fn tick(&mut self) {
+        self.busy = self.busy.saturating_sub(1);
+        for rail in &mut self.rails {
+            rail.evaluate(self.vin, self.ambient);
+        }
+    }
+}
+
+impl Default for Tps536c7 {
+    fn default() -> Tps536c7 {
+        Tps536c7::new(ADDRESS)
+    }
+}
+
+impl ErrorType for Tps536c7 {
+    type Error = ErrorKind;
+}
+
+impl I2c for Tps536c7 {
+    fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), ErrorKind> {
+        if address != self.address {
+            return Err(ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address));
+        }
+        if self.nacks > 0 {
+            self.nacks -= 1;
+            return Err(ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address));
+        }
+        let busy = self.busy > 0;
+        self.tick();
+        match operations {
+            [Operation::Write(data)] => {
+                if busy {
+                    return Err(ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address));
+                }
+                self.write(data)
+            }
+            [Operation::Write(data), Operation::Read(buf)] => {
+                // Only STATUS reads are answered while the NVM is being written
+                let Some(&cmd) = data.first() else {
+                    return Err(ErrorKind::Other);
+                };
+                if busy && !(0x78..=0x80).contains(&cmd) {
+                    return Err(ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address));
+                }
+                if data.len() > 1 {
+                    // Block process calls are not modelled, they are refused like any other
+                    // unsupported command: INVALID_COMMAND in STATUS_CML and the data NACKed
+                    return Err(self.cml(INVALID_COMMAND));
+                }
+                let mut resp = self.read(cmd)?;
+                let mut msg = vec![self.address << 1, cmd, self.address << 1 | 1];
+                msg.extend_from_slice(&resp);
+                resp.push(pec(&msg));
+                // Reads past the end see the bus idling high
+                for (idx, byte) in buf.iter_mut().enumerate() {
+                    *byte = resp.get(idx).copied().unwrap_or(0xFF);
+                }
+                Ok(())
+            }
+            _ => Err(ErrorKind::Other),
+        }
+    }
+}
@@ -0,0 +1,97 @@
+// PMBus LINEAR11 / ULINEAR16 encodings, kept independent of the firmware's so a bug in one
+// shows up as a mismatch instead of cancelling out
+
+/// Decodes SLINEAR11: 5 bit signed exponent, 11 bit signed mantissa
+pub fn slinear11_to(raw: u16) -> f32 {
+    let exp = (raw as i16) >> 11;
+    let mantissa = ((raw << 5) as i16) >> 5;
+    mantissa as f32 * 2f32.powi(exp as i32)
+}
+
+/// Encodes SLINEAR11 with the smallest exponent the mantissa fits in (best resolution)
+pub fn slinear11_from(val: f32) -> u16 {
+    for exp in -16i32..=15 {
+        let mantissa = (val / 2f32.powi(exp)).round();
+        if (-1024.0..=1023.0).contains(&mantissa) {
+            return ((exp as u16 & 0x1F) << 11) | (mantissa as i16 as u16 & 0x7FF);
+        }
+    }
+    // Out of range, saturate at the largest exponent
+    let mantissa = if val < 0. { -1024i16 } else { 1023 };
+    (0x0F << 11) | (mantissa as u16 & 0x7FF)
+}
+
+/// Decodes a ULINEAR16 mantissa with the exponent from VOUT_MODE
+pub fn ulinear16_to(raw: u16, exp: i8) -> f32 {
+    raw as f32 * 2f32.powi(exp as i32)
+}
+
+/// Encodes a ULINEAR16 mantissa with the exponent from VOUT_MODE, saturating at the limits
+pub fn ulinear16_from(val: f32, exp: i8) -> u16 {
+    (val / 2f32.powi(exp as i32))
+        .round()
+        .clamp(0., u16::MAX as f32) as u16
+}
+
+/// Converts a VOUT-class register to volts in the format VOUT_MODE selects
+///
+/// Supports linear mode and the VR12 / VR13 / VR14 / IMVP9 VID tables
+pub fn vout_to(mode: u8, raw: u16) -> Option<f32> {
+    match mode >> 5 {
+        0b000 => Some(ulinear16_to(raw, vout_exponent(mode))),
+        0b001 => {
+            let (base, step) = vid_base_step(mode & 0x1F)?;
+            match raw & 0xFF {
+                0 => Some(0.),
+                code => Some(base + step * (code - 1) as f32),
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Converts volts to a VOUT-class register in the format VOUT_MODE selects
+pub fn vout_from(mode: u8, volts: f32) -> Option<u16> {
+    match mode >> 5 {
+        0b000 => Some(ulinear16_from(volts, vout_exponent(mode))),
+        0b001 => {
+            let (base, step) = vid_base_step(mode & 0x1F)?;
+            if volts < base {
+                return Some(0);
+            }
+            Some(((volts - base) / step + 1.).round().min(255.) as u16)
+        }
+        _ => None,
+    }
+}
+
+/// Sign extends the 5 bit exponent of a linear VOUT_MODE
+fn vout_exponent(mode: u8) -> i8 {
+    ((mode << 3) as i8) >> 3
+}
+
+/// Voltage of VID code 1 and the step between codes for a VOUT_MODE VID table code
+fn vid_base_step(table: u8) -> Option<(f32, f32)> {
+    match table {
+        0x01 | 0x04 => Some((0.25, 0.005)),
+        0x02 => Some((0.5, 0.01)),
+        0x07 => Some((0.2, 0.005)),
+        _ => None,
+    }
+}
+
+/// SMBus packet error code: CRC-8 with polynomial x^8 + x^2 + x + 1
+pub fn pec(data: &[u8]) -> u8 {
+    let mut crc = 0u8;
+    for byte in data {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
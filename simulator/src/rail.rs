@@ -0,0 +1,246 @@
+use std::collections::BTreeMap;
+
+use crate::linear::{slinear11_from, slinear11_to, vout_from, vout_to};
+
+// STATUS_VOUT
+pub const VOUT_OV_FAULT: u8 = 1 << 7;
+pub const VOUT_UV_FAULT: u8 = 1 << 4;
+pub const VOUT_MAX_MIN_WARN: u8 = 1 << 3;
+// STATUS_IOUT
+pub const IOUT_OC_FAULT: u8 = 1 << 7;
+pub const IOUT_OC_WARN: u8 = 1 << 5;
+// STATUS_INPUT
+pub const VIN_UV_FAULT: u8 = 1 << 4;
+pub const UNIT_OFF_LOW_VIN: u8 = 1 << 3;
+// STATUS_TEMPERATURE
+pub const OT_FAULT: u8 = 1 << 7;
+pub const OT_WARN: u8 = 1 << 6;
+// STATUS_CML
+pub const INVALID_COMMAND: u8 = 1 << 7;
+pub const INVALID_DATA: u8 = 1 << 6;
+pub const PEC_FAILED: u8 = 1 << 5;
+
+/// Temperature rise of the power stages per amp of output current
+const DEGREES_PER_AMP: f32 = 0.25;
+/// Extra temperature each phase further from the controller sees
+const DEGREES_PER_PHASE: f32 = 0.5;
+/// Conversion efficiency used to work out the input power
+const EFFICIENCY: f32 = 0.9;
+
+/// Configuration that STORE / RESTORE move to and from the simulated NVM
+#[derive(Clone, Debug, PartialEq)]
+pub struct Config {
+    pub operation: u8,
+    pub on_off_config: u8,
+    pub vout_mode: u8,
+    /// Every word register, keyed by command code. Only these accept word writes
+    pub words: BTreeMap<u8, u16>,
+}
+
+impl Config {
+    fn new(vout_mode: u8, vout: f32, oc_fault: f32) -> Config {
+        let mut words = BTreeMap::new();
+        let vout_reg = |volts| vout_from(vout_mode, volts).unwrap();
+        words.insert(0x21, vout_reg(vout)); // VOUT_COMMAND
+        words.insert(0x24, vout_reg(vout * 1.5)); // VOUT_MAX
+        words.insert(0x25, vout_reg(vout * 1.05)); // VOUT_MARGIN_HIGH
+        words.insert(0x26, vout_reg(vout * 0.95)); // VOUT_MARGIN_LOW
+        words.insert(0x2B, vout_reg(vout * 0.5)); // VOUT_MIN
+        words.insert(0x27, slinear11_from(1.0)); // VOUT_TRANSITION_RATE mV/us
+        words.insert(0x28, slinear11_from(0.0)); // VOUT_DROOP mV/A
+        words.insert(0x33, slinear11_from(600.0)); // FREQUENCY_SWITCH kHz
+        words.insert(0x35, slinear11_from(10.0)); // VIN_ON
+        words.insert(0x36, slinear11_from(9.0)); // VIN_OFF
+        words.insert(0x46, slinear11_from(oc_fault)); // IOUT_OC_FAULT_LIMIT
+        words.insert(0x4A, slinear11_from(oc_fault * 0.9)); // IOUT_OC_WARN_LIMIT
+        words.insert(0x4F, slinear11_from(125.0)); // OT_FAULT_LIMIT
+        words.insert(0x51, slinear11_from(105.0)); // OT_WARN_LIMIT
+        words.insert(0x55, slinear11_from(14.0)); // VIN_OV_FAULT_LIMIT
+        words.insert(0x59, slinear11_from(8.0)); // VIN_UV_FAULT_LIMIT
+        words.insert(0x60, slinear11_from(0.0)); // TON_DELAY
+        words.insert(0x61, slinear11_from(1.0)); // TON_RISE
+        words.insert(0x64, slinear11_from(0.0)); // TOFF_DELAY
+        words.insert(0x65, slinear11_from(1.0)); // TOFF_FALL
+        for offset in 0..=0x2D {
+            words.insert(0xD0 + offset, 0);
+        }
+        Config {
+            operation: 0x80,
+            on_off_config: 0x1A,
+            vout_mode,
+            words,
+        }
+    }
+}
+
+/// A single simulated output (page) with its own registers, load and faults
+#[derive(Clone, Debug)]
+pub struct Rail {
+    pub config: Config,
+    /// Power stages fitted, READ_* with PHASE set past this are rejected
+    pub phases: u8,
+    /// Phase selected by PHASE, 0xFF is all phases
+    pub phase: u8,
+    /// Current the load draws while the output is on
+    pub load: f32,
+    pub status_vout: u8,
+    pub status_iout: u8,
+    pub status_input: u8,
+    pub status_temperature: u8,
+    pub status_cml: u8,
+    pub status_mfr_specific: u8,
+    /// A latched fault has shut the output down until CLEAR_FAULTS
+    pub tripped: bool,
+}
+
+impl Rail {
+    /// Linear mode with a 2^-9 V resolution, the TPS536C7 default
+    pub const DEFAULT_VOUT_MODE: u8 = 0x17;
+
+    pub fn new(vout: f32, oc_fault: f32, phases: u8) -> Rail {
+        Rail {
+            config: Config::new(Self::DEFAULT_VOUT_MODE, vout, oc_fault),
+            phases,
+            phase: 0xFF,
+            load: 0.,
+            status_vout: 0,
+            status_iout: 0,
+            status_input: 0,
+            status_temperature: 0,
+            status_cml: 0,
+            status_mfr_specific: 0,
+            tripped: false,
+        }
+    }
+
+    pub fn word(&self, cmd: u8) -> u16 {
+        self.config.words.get(&cmd).copied().unwrap_or(0)
+    }
+
+    fn linear11(&self, cmd: u8) -> f32 {
+        slinear11_to(self.word(cmd))
+    }
+
+    fn vout_class(&self, cmd: u8) -> f32 {
+        vout_to(self.config.vout_mode, self.word(cmd)).unwrap_or(0.)
+    }
+
+    /// OPERATION has the output on and no fault has shut it down
+    pub fn is_on(&self, vin: f32) -> bool {
+        self.config.operation & 0x80 != 0 && !self.tripped && vin >= self.linear11(0x36)
+    }
+
+    /// Regulated output voltage, the setpoint minus the droop across the load line
+    pub fn vout(&self, vin: f32) -> f32 {
+        if !self.is_on(vin) {
+            return 0.;
+        }
+        // OPERATION can select VOUT_MARGIN_HIGH or VOUT_MARGIN_LOW in place of VOUT_COMMAND
+        let target = match self.config.operation & 0x30 {
+            0x20 => 0x25,
+            0x10 => 0x26,
+            _ => 0x21,
+        };
+        // Setpoints outside VOUT_MIN / VOUT_MAX are clamped like the real controller does
+        let setpoint = self
+            .vout_class(target)
+            .max(self.vout_class(0x2B))
+            .min(self.vout_class(0x24));
+        let droop = self.linear11(0x28) / 1000.;
+        (setpoint - droop * self.load).max(0.)
+    }
+
+    pub fn iout(&self, vin: f32) -> f32 {
+        if self.is_on(vin) {
+            self.load
+        } else {
+            0.
+        }
+    }
+
+    pub fn temperature(&self, vin: f32, ambient: f32) -> f32 {
+        ambient + DEGREES_PER_AMP * self.iout(vin)
+    }
+
+    pub fn pout(&self, vin: f32) -> f32 {
+        self.vout(vin) * self.iout(vin)
+    }
+
+    pub fn pin(&self, vin: f32) -> f32 {
+        self.pout(vin) / EFFICIENCY
+    }
+
+    /// Current of a single phase, the load shares evenly between phases
+    pub fn phase_current(&self, vin: f32) -> f32 {
+        self.iout(vin) / self.phases.max(1) as f32
+    }
+
+    pub fn phase_temperature(&self, vin: f32, ambient: f32, phase: u8) -> f32 {
+        self.temperature(vin, ambient) + DEGREES_PER_PHASE * phase as f32
+    }
+
+    /// Compares the load model against the limits, latching faults and warnings
+    pub fn evaluate(&mut self, vin: f32, ambient: f32) {
+        if vin < self.linear11(0x59) {
+            self.status_input |= VIN_UV_FAULT;
+        }
+        if vin < self.linear11(0x36) {
+            self.status_input |= UNIT_OFF_LOW_VIN;
+        }
+        if !self.is_on(vin) {
+            return;
+        }
+        let iout = self.iout(vin);
+        if iout > self.linear11(0x46) {
+            self.status_iout |= IOUT_OC_FAULT;
+            self.tripped = true;
+        } else if iout > self.linear11(0x4A) {
+            self.status_iout |= IOUT_OC_WARN;
+        }
+        let temperature = self.temperature(vin, ambient);
+        if temperature > self.linear11(0x4F) {
+            self.status_temperature |= OT_FAULT;
+            self.tripped = true;
+        } else if temperature > self.linear11(0x51) {
+            self.status_temperature |= OT_WARN;
+        }
+        let setpoint = self.vout_class(0x21);
+        if setpoint > self.vout_class(0x24) || setpoint < self.vout_class(0x2B) {
+            self.status_vout |= VOUT_MAX_MIN_WARN;
+        }
+    }
+
+    /// CLEAR_FAULTS, faults still present are latched again by the next evaluate
+    pub fn clear_faults(&mut self) {
+        self.status_vout = 0;
+        self.status_iout = 0;
+        self.status_input = 0;
+        self.status_temperature = 0;
+        self.status_cml = 0;
+        self.status_mfr_specific = 0;
+        self.tripped = false;
+    }
+
+    /// STATUS_WORD summarising every sub-register
+    pub fn status_word(&self, vin: f32, busy: bool) -> u16 {
+        let mut word = 0u16;
+        let mut set = |bit: u16, cond: bool| {
+            if cond {
+                word |= 1 << bit;
+            }
+        };
+        set(1, self.status_cml != 0);
+        set(2, self.status_temperature != 0);
+        set(3, self.status_input & VIN_UV_FAULT != 0);
+        set(4, self.status_iout & IOUT_OC_FAULT != 0);
+        set(5, self.status_vout & VOUT_OV_FAULT != 0);
+        set(6, !self.is_on(vin));
+        set(7, busy);
+        set(11, !self.is_on(vin));
+        set(12, self.status_mfr_specific != 0);
+        set(13, self.status_input != 0);
+        set(14, self.status_iout != 0);
+        set(15, self.status_vout != 0);
+        word
+    }
+}
@@ -0,0 +1,176 @@
+use embedded_hal::i2c::{ErrorKind, I2c, NoAcknowledgeSource};
+use tps536c7_simulator::linear::{pec, vout_to};
+use tps536c7_simulator::rail::{
+    Rail, INVALID_COMMAND, INVALID_DATA, IOUT_OC_FAULT, OT_FAULT, PEC_FAILED,
+};
+use tps536c7_simulator::{Channel, Fault, Tps536c7, ADDRESS};
+
+const NACK_ADDRESS: ErrorKind = ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address);
+const NACK_DATA: ErrorKind = ErrorKind::NoAcknowledge(NoAcknowledgeSource::Data);
+
+// Command codes
+const PAGE: u8 = 0x00;
+const OPERATION: u8 = 0x01;
+const CLEAR_FAULTS: u8 = 0x03;
+const STORE_DEFAULT_ALL: u8 = 0x11;
+const VOUT_COMMAND: u8 = 0x21;
+const STATUS_BYTE: u8 = 0x78;
+const STATUS_WORD: u8 = 0x79;
+const MFR_ID: u8 = 0x99;
+
+/// data (command code first) with its PEC byte appended
+fn with_pec(data: &[u8]) -> Vec<u8> {
+    let mut msg = vec![ADDRESS << 1];
+    msg.extend_from_slice(data);
+    let mut data = data.to_vec();
+    data.push(pec(&msg));
+    data
+}
+
+/// Reads len bytes of cmd, the PEC byte follows them
+fn read(sim: &mut Tps536c7, cmd: u8, len: usize) -> Result<Vec<u8>, ErrorKind> {
+    let mut buf = vec![0; len + 1];
+    sim.write_read(ADDRESS, &[cmd], &mut buf)?;
+    Ok(buf)
+}
+
+fn status_word(sim: &mut Tps536c7) -> u16 {
+    let resp = read(sim, STATUS_WORD, 2).unwrap();
+    u16::from_le_bytes([resp[0], resp[1]])
+}
+
+#[test]
+fn pec_matches_the_crc8_smbus_check_value() {
+    assert_eq!(pec(b"123456789"), 0xF4);
+}
+
+#[test]
+fn reads_end_in_the_pec_of_the_whole_transaction() {
+    let mut sim = Tps536c7::default();
+
+    let resp = read(&mut sim, OPERATION, 1).unwrap();
+    let expected = pec(&[ADDRESS << 1, OPERATION, ADDRESS << 1 | 1, resp[0]]);
+    assert_eq!(resp[1], expected);
+
+    // Block reads cover the byte count too
+    let resp = read(&mut sim, MFR_ID, 3).unwrap();
+    assert_eq!(&resp[..3], &[2, b'T', b'I']);
+    let expected = pec(&[ADDRESS << 1, MFR_ID, ADDRESS << 1 | 1, 2, b'T', b'I']);
+    assert_eq!(resp[3], expected);
+}
+
+#[test]
+fn writes_with_a_bad_pec_are_refused() {
+    let mut sim = Tps536c7::default();
+
+    let mut msg = with_pec(&[PAGE, 0x01]);
+    msg[2] ^= 0x01;
+    assert_eq!(sim.write(ADDRESS, &msg), Err(NACK_DATA));
+    assert_eq!(sim.page(), 0x00);
+    assert_eq!(sim.rail(Channel::A).status_cml, PEC_FAILED);
+
+    sim.write(ADDRESS, &with_pec(&[PAGE, 0x01])).unwrap();
+    assert_eq!(sim.page(), 0x01);
+}
+
+#[test]
+fn writes_without_a_pec_are_refused_only_when_required() {
+    let mut sim = Tps536c7::default();
+    sim.write(ADDRESS, &[PAGE, 0x01]).unwrap();
+
+    sim.require_pec(true);
+    assert_eq!(sim.write(ADDRESS, &[PAGE, 0x00]), Err(NACK_DATA));
+    assert_eq!(sim.page(), 0x01);
+    assert_eq!(sim.rail(Channel::B).status_cml, INVALID_DATA);
+}
+
+#[test]
+fn nvm_stores_keep_the_controller_busy() {
+    let mut sim = Tps536c7::default();
+    sim.write(ADDRESS, &[STORE_DEFAULT_ALL]).unwrap();
+
+    // Anything but STATUS is NACKed until the store completes
+    assert_eq!(read(&mut sim, OPERATION, 1), Err(NACK_ADDRESS));
+    assert_eq!(sim.write(ADDRESS, &[PAGE, 0x01]), Err(NACK_ADDRESS));
+    let mut polls = 0;
+    while read(&mut sim, STATUS_BYTE, 1).unwrap()[0] & 0x80 != 0 {
+        polls += 1;
+        assert!(polls < 5, "still busy after {polls} polls");
+    }
+    assert_eq!(read(&mut sim, OPERATION, 1).unwrap()[0], 0x80);
+}
+
+#[test]
+fn injected_faults_latch_until_cleared() {
+    let mut sim = Tps536c7::default();
+    sim.inject_fault(Channel::A, Fault::OverCurrent);
+    sim.inject_fault(Channel::B, Fault::OverTemperature);
+
+    assert!(!sim.is_on(Channel::A) && !sim.is_on(Channel::B));
+    assert_eq!(read(&mut sim, 0x7B, 1).unwrap()[0], IOUT_OC_FAULT);
+    // IOUT_OC, OFF, POWER_GOOD# and IOUT/POUT
+    assert_eq!(status_word(&mut sim), 1 << 4 | 1 << 6 | 1 << 11 | 1 << 14);
+
+    // CLEAR_FAULTS only clears the paged channel
+    sim.write(ADDRESS, &[CLEAR_FAULTS]).unwrap();
+    assert!(sim.is_on(Channel::A));
+    assert_eq!(status_word(&mut sim), 0);
+    assert_eq!(sim.rail(Channel::B).status_temperature, OT_FAULT);
+    assert!(!sim.is_on(Channel::B));
+}
+
+#[test]
+fn writes_go_to_the_paged_channels_and_reads_come_from_channel_a() {
+    let mut sim = Tps536c7::default();
+    let vout_command = |sim: &Tps536c7, channel| {
+        let rail = sim.rail(channel);
+        vout_to(Rail::DEFAULT_VOUT_MODE, rail.word(VOUT_COMMAND)).unwrap()
+    };
+
+    sim.write(ADDRESS, &[PAGE, 0x01]).unwrap();
+    // 1.25 V in 2^-9 V steps
+    sim.write(ADDRESS, &[VOUT_COMMAND, 0x80, 0x02]).unwrap();
+    assert!((vout_command(&sim, Channel::A) - 0.9).abs() < 0.001);
+    assert_eq!(vout_command(&sim, Channel::B), 1.25);
+
+    sim.write(ADDRESS, &[PAGE, 0xFF]).unwrap();
+    sim.write(ADDRESS, &[VOUT_COMMAND, 0x00, 0x02]).unwrap();
+    assert_eq!(vout_command(&sim, Channel::A), 1.0);
+    assert_eq!(vout_command(&sim, Channel::B), 1.0);
+    assert_eq!(read(&mut sim, PAGE, 1).unwrap()[0], 0xFF);
+
+    // Only pages 0, 1 and 0xFF exist
+    assert_eq!(sim.write(ADDRESS, &[PAGE, 0x02]), Err(NACK_DATA));
+    assert_eq!(sim.page(), 0xFF);
+    assert_eq!(sim.rail(Channel::A).status_cml, INVALID_DATA);
+    assert_eq!(sim.rail(Channel::B).status_cml, INVALID_DATA);
+}
+
+#[test]
+fn block_process_calls_are_refused_as_unsupported() {
+    let mut sim = Tps536c7::default();
+    let mut buf = [0; 4];
+
+    assert_eq!(
+        sim.write_read(ADDRESS, &[MFR_ID, 1, 0x00], &mut buf),
+        Err(NACK_DATA)
+    );
+    assert_eq!(sim.rail(Channel::A).status_cml, INVALID_COMMAND);
+    // Unknown commands are refused the same way
+    sim.write(ADDRESS, &[CLEAR_FAULTS]).unwrap();
+    assert_eq!(read(&mut sim, 0xFE, 1), Err(NACK_DATA));
+    assert_eq!(sim.rail(Channel::A).status_cml, INVALID_COMMAND);
+}
+
+#[test]
+fn only_its_own_address_is_acknowledged() {
+    let mut sim = Tps536c7::default();
+    assert_eq!(sim.write(ADDRESS + 1, &[PAGE, 0x01]), Err(NACK_ADDRESS));
+
+    sim.inject_nack(2);
+    for _ in 0..2 {
+        assert_eq!(sim.write(ADDRESS, &[PAGE, 0x01]), Err(NACK_ADDRESS));
+    }
+    sim.write(ADDRESS, &[PAGE, 0x01]).unwrap();
+    assert_eq!(sim.page(), 0x01);
+}